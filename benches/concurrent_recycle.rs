@@ -0,0 +1,77 @@
+//! `ConcurrentRecycler` under contention, against claiming slots directly on
+//! the shared tail's cache line, on a simulated ring.
+//!
+//! Nothing consumes the ring, so returning the same buffers again only
+//! overwrites entries nobody reads, as if the kernel were always ahead. The
+//! baseline moves the shared tail with a compare-exchange per return, which is
+//! the line traffic the padded counters keep away from it. Cache line
+//! contention shows best in `perf c2c record` around either run.
+//!
+//! Needs several cores to show anything. Run with
+//! `cargo bench --features sim --bench concurrent_recycle`.
+
+use io_uring_buf_ring::{BufRing, ConcurrentRecycler};
+use std::sync::Barrier;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+const ENTRIES: u16 = 4096;
+const PER_THREAD: u32 = 200_000;
+
+/// Times `work` on `threads` threads started together, returns the mean time
+/// per return.
+fn contend(threads: u16, work: impl Fn(u16) + Sync) -> Duration {
+    let barrier = Barrier::new(threads as usize + 1);
+    let start = std::thread::scope(|s| {
+        for t in 0..threads {
+            let (barrier, work) = (&barrier, &work);
+            s.spawn(move || {
+                barrier.wait();
+                work(t);
+            });
+        }
+        barrier.wait();
+        Instant::now()
+    });
+    start.elapsed() / (threads as u32 * PER_THREAD)
+}
+
+fn recycler(recycler: &ConcurrentRecycler<'_>, threads: u16) -> Duration {
+    let share = recycler.entries() / threads;
+    contend(threads, |t| {
+        for i in 0..PER_THREAD {
+            let buf_id = t * share + (i % share as u32) as u16;
+            // SAFETY: in range, and see the module docs on returning it again
+            unsafe { recycler.recycle(buf_id) };
+        }
+    })
+}
+
+fn shared_line(ring: &BufRing<io_uring_buf_ring::buf_ring_state::Init>, threads: u16) -> Duration {
+    // SAFETY: the mapping lives as long as `ring`
+    let tail = unsafe { &*ring.tail_ptr() };
+    contend(threads, |_| {
+        for _ in 0..PER_THREAD {
+            let mut t = tail.load(Ordering::Relaxed);
+            while let Err(current) =
+                tail.compare_exchange_weak(t, t.wrapping_add(1), Ordering::Release, Ordering::Relaxed)
+            {
+                t = current;
+            }
+        }
+    })
+}
+
+fn main() {
+    let mut ring = BufRing::new(ENTRIES, 64, 0).unwrap().register_simulated();
+    // the baseline moves the tail behind the ring's back
+    let other = BufRing::new(ENTRIES, 64, 1).unwrap().register_simulated();
+    println!("{:<8} {:>14} {:>14}", "threads", "recycler", "shared line");
+    for threads in [1, 2, 4, 8] {
+        let padded = recycler(&ring.concurrent_recycler(), threads);
+        let baseline = shared_line(&other, threads);
+        println!("{threads:<8} {padded:>14.2?} {baseline:>14.2?}");
+    }
+    drop(ring.unregister_simulated());
+    drop(other.unregister_simulated());
+}
@@ -0,0 +1,56 @@
+//! A low-rate echo on a simulated ring, FIFO against LIFO provisioning.
+//!
+//! One message is in flight at a time: the simulated kernel receives it into
+//! the next provided buffer, the application reads it back out and returns the
+//! buffer. The ring's buffers add up to well past the last level cache, so in
+//! FIFO order every message lands in memory that was evicted since the last
+//! lap, while in LIFO mode the same few buffers stay hot.
+//!
+//! Run with `cargo bench --features sim,test-support --bench lifo`.
+
+use io_uring_buf_ring::{BufRing, FakeCqe, SimKernel, buf_ring_state};
+use std::hint::black_box;
+use std::time::Instant;
+
+const ENTRIES: u16 = 8192;
+const BUF_SIZE: u32 = 4096;
+const ROUNDS: u32 = 200_000;
+
+type Ring = BufRing<buf_ring_state::Init>;
+
+fn run(name: &str, lifo_depth: Option<u16>) {
+    let mut ring = BufRing::new(ENTRIES, BUF_SIZE, 0).unwrap().register_simulated();
+    if let Some(depth) = lifo_depth {
+        ring.enable_lifo(depth);
+    }
+    let mut kernel = SimKernel::attach(&ring);
+    let message = vec![0x5a; BUF_SIZE as usize];
+    let mut echo = vec![0; BUF_SIZE as usize];
+
+    // one lap first, so both start with every buffer touched once
+    for _ in 0..ENTRIES {
+        echo_one(&mut ring, &mut kernel, &message, &mut echo);
+    }
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        echo_one(&mut ring, &mut kernel, &message, &mut echo);
+        black_box(&echo);
+    }
+    let per_msg = start.elapsed().as_nanos() as f64 / ROUNDS as f64;
+    println!("{name:<16} {per_msg:>8.1}ns per message");
+    drop(ring.unregister_simulated());
+}
+
+/// Receives `message` and copies it back out, returning the buffer.
+fn echo_one(ring: &mut Ring, kernel: &mut SimKernel, message: &[u8], echo: &mut [u8]) {
+    let (flags, res) = kernel.recv(ring, message, true);
+    let cqe = FakeCqe { flags, res };
+    let id = ring.buffer_id_from_fields(&cqe).unwrap().unwrap();
+    echo.copy_from_slice(id.buffer());
+}
+
+fn main() {
+    run("fifo", None);
+    run("lifo, depth 4", Some(4));
+}
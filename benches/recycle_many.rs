@@ -0,0 +1,52 @@
+//! `recycle_many` against a loop of single recycles, on a simulated ring.
+//!
+//! Run with `cargo bench --features sim,test-support --bench recycle_many`.
+
+use io_uring_buf_ring::{BufRing, FakeCqe, OwnedBuffer, SimKernel, buf_ring_state, cqe_buffer_id};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const ENTRIES: u16 = 256;
+const BATCH: usize = 64;
+const ROUNDS: u32 = 20_000;
+
+fn detach_batch(
+    ring: &mut BufRing<buf_ring_state::Init>,
+    kernel: &mut SimKernel,
+) -> Vec<OwnedBuffer> {
+    (0..BATCH)
+        .map(|_| {
+            let (flags, res) = kernel.recv(ring, b"payload", true);
+            let cqe = FakeCqe::buffer(cqe_buffer_id(flags), res as u32, true);
+            let id = ring.buffer_id_from_fields(&cqe).unwrap().unwrap();
+            // SAFETY: every buffer is returned before the ring is released
+            unsafe { id.detach() }
+        })
+        .collect()
+}
+
+fn run(name: &str, mut recycle: impl FnMut(&mut BufRing<buf_ring_state::Init>, Vec<OwnedBuffer>)) {
+    let mut ring = BufRing::new(ENTRIES, 2048, 0).unwrap().register_simulated();
+    let mut kernel = SimKernel::attach(&ring);
+    let mut spent = Duration::ZERO;
+    for _ in 0..ROUNDS {
+        let bufs = detach_batch(&mut ring, &mut kernel);
+        let start = Instant::now();
+        recycle(&mut ring, black_box(bufs));
+        spent += start.elapsed();
+    }
+    let per_buf = spent / (ROUNDS * BATCH as u32);
+    println!("{name:<16} {per_buf:>10.2?} per buffer");
+    drop(ring.unregister_simulated());
+}
+
+fn main() {
+    run("recycle_many", |ring, bufs| {
+        ring.recycle_many(bufs).unwrap();
+    });
+    run("single recycles", |ring, bufs| {
+        for buf in bufs {
+            ring.recycle_many([buf]).unwrap();
+        }
+    });
+}
@@ -0,0 +1,27 @@
+//! Setting up a ring of `MAX_ENTRIES` entries, on a simulated ring.
+//!
+//! `init` writes every entry in one pass and publishes them with a single
+//! tail store; mapping the ring is timed on its own for comparison.
+//!
+//! Run with `cargo bench --features sim --bench startup`.
+
+use io_uring_buf_ring::{BufRing, MAX_ENTRIES};
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 200;
+
+fn main() {
+    let (mut map, mut init) = (Duration::ZERO, Duration::ZERO);
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let ring = BufRing::new(MAX_ENTRIES, 2048, 0).unwrap();
+        map += start.elapsed();
+
+        let start = Instant::now();
+        let ring = ring.register_simulated();
+        init += start.elapsed();
+        drop(ring.unregister_simulated());
+    }
+    println!("map  {:>10.2?} per ring", map / RUNS);
+    println!("init {:>10.2?} per ring", init / RUNS);
+}
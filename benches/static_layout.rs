@@ -0,0 +1,82 @@
+//! Returning buffers on a static layout, where only a slot holding another
+//! buffer id is rewritten, against rewriting every field of every entry, on a
+//! simulated ring.
+//!
+//! `KeptBuffers::reprovide_all` takes the static path, `provide_with_len` at the full buffer
+//! size always rewrites the whole entry. In FIFO order every buffer comes back
+//! to the slot it was consumed from, so the static path writes nothing but the
+//! tail. Nothing else touches the entries of a simulated ring, so the stores
+//! saved cost little here; they matter once the kernel reads the entries from
+//! another core.
+//!
+//! Run with `cargo bench --features sim,test-support --bench static_layout`.
+
+use io_uring_buf_ring::{
+    BufRing, FakeCqe, KeptBuffers, SimKernel, buf_ring_state, cqe_buffer_id,
+};
+use std::time::{Duration, Instant};
+
+const ENTRIES: u16 = 256;
+const BATCH: usize = 64;
+const ROUNDS: u32 = 20_000;
+
+type Ring = BufRing<buf_ring_state::Init>;
+
+/// Times returning each batch of buffers the kernel received into.
+fn run(
+    name: &str,
+    shuffled: bool,
+    mut give_back: impl FnMut(&mut Ring, &mut SimKernel, &[u16]) -> Duration,
+) {
+    let mut ring = BufRing::new(ENTRIES, 2048, 0).unwrap().register_simulated();
+    let mut kernel = SimKernel::attach(&ring);
+    let mut spent = Duration::ZERO;
+    let mut ids = Vec::with_capacity(BATCH);
+    for round in 0..ROUNDS {
+        ids.clear();
+        for _ in 0..BATCH {
+            let (flags, _) = kernel.recv(&ring, b"payload", true);
+            ids.push(cqe_buffer_id(flags));
+        }
+        if shuffled {
+            ids.reverse();
+            ids.rotate_left(round as usize % BATCH);
+        }
+        spent += give_back(&mut ring, &mut kernel, &ids);
+    }
+    let per_buf = spent.as_nanos() as f64 / (ROUNDS as f64 * BATCH as f64);
+    println!("{name:<28} {per_buf:>8.2}ns per buffer");
+    drop(ring.unregister_simulated());
+}
+
+fn static_layout(ring: &mut Ring, _: &mut SimKernel, ids: &[u16]) -> Duration {
+    let mut kept = KeptBuffers::new();
+    for &bid in ids {
+        let cqe = FakeCqe::buffer(bid, 7, true);
+        kept.keep(ring.buffer_id_from_fields(&cqe).unwrap().unwrap());
+    }
+    let start = Instant::now();
+    kept.reprovide_all(ring);
+    start.elapsed()
+}
+
+fn full_rewrite(ring: &mut Ring, _: &mut SimKernel, ids: &[u16]) -> Duration {
+    for &bid in ids {
+        let cqe = FakeCqe::buffer(bid, 7, true);
+        ring.resolve_raw(cqe.flags, cqe.res).unwrap();
+    }
+    let start = Instant::now();
+    ring.set_deferred_advance(true);
+    for &bid in ids {
+        ring.provide_with_len(bid, ring.buf_size()).unwrap();
+    }
+    ring.set_deferred_advance(false);
+    start.elapsed()
+}
+
+fn main() {
+    run("static layout, fifo", false, static_layout);
+    run("static layout, shuffled", true, static_layout);
+    run("full rewrite, fifo", false, full_rewrite);
+    run("full rewrite, shuffled", true, full_rewrite);
+}
@@ -0,0 +1,166 @@
+use crate::buf_ring::{BufRing, state};
+use crate::buffer_id::BufferId;
+use io_uring::cqueue::EntryMarker;
+
+use core::marker::PhantomData;
+
+/// Bounds how many completions a single pass over the completion queue
+/// resolves.
+///
+/// Recycling is deferred for the length of the pass, and everything returned
+/// during it is published together when the budget is finished or dropped.
+/// Completions left over once the budget runs out stay in the queue for the
+/// next pass.
+///
+/// Created with [`BufRing::budget`].
+pub struct ProcessBudget<'a> {
+    ring: &'a mut BufRing<state::Init>,
+    remaining: usize,
+    processed: usize,
+    was_deferred: bool,
+    /// Borrows the ring, which stays on its thread.
+    not_send: PhantomData<*const ()>,
+}
+
+impl<'a> ProcessBudget<'a> {
+    pub(crate) fn new(ring: &'a mut BufRing<state::Init>, max: usize) -> Self {
+        let was_deferred = ring.deferred_advance();
+        ring.set_deferred_advance(true);
+        Self {
+            ring,
+            remaining: max,
+            processed: 0,
+            was_deferred,
+            not_send: PhantomData,
+        }
+    }
+
+    /// Resolves `cqe`, or returns `None` without touching it once the budget
+    /// is exhausted.
+    pub fn resolve<'b, E: EntryMarker>(
+        &mut self,
+        cqe: &'b E,
+    ) -> Option<std::io::Result<Option<BufferId<'_, 'b, E>>>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        self.processed += 1;
+        Some(self.ring.buffer_id_from_cqe(cqe))
+    }
+
+    /// Hands completions from `cq` to `f` until either runs out, and returns
+    /// how many were left unprocessed in `cq`.
+    pub fn drain<E, I>(
+        &mut self,
+        cq: &mut I,
+        mut f: impl FnMut(std::io::Result<Option<BufferId<'_, '_, E>>>),
+    ) -> usize
+    where
+        E: EntryMarker,
+        I: ExactSizeIterator<Item = E>,
+    {
+        while self.remaining > 0
+            && let Some(cqe) = cq.next()
+        {
+            if let Some(res) = self.resolve(&cqe) {
+                f(res);
+            }
+        }
+        cq.len()
+    }
+
+    /// Completions that can still be resolved.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Completions resolved so far.
+    pub fn processed(&self) -> usize {
+        self.processed
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /// Publishes everything recycled during the pass and returns how many
+    /// completions were resolved.
+    pub fn finish(self) -> usize {
+        self.processed
+    }
+}
+
+impl Drop for ProcessBudget<'_> {
+    fn drop(&mut self) {
+        self.ring.flush();
+        self.ring.set_deferred_advance(self.was_deferred);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buf_ring::BufRing;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::net::UnixDatagram;
+
+    /// Two passes over six completions with a budget of four: the second
+    /// picks up exactly where the first stopped, and every buffer comes back
+    /// once.
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn consecutive_passes_lose_nothing() {
+        let mut uring = io_uring::IoUring::new(8).unwrap();
+        let ring = BufRing::new(8, 16, 11).unwrap();
+        let mut ring = ring
+            .register_and_init(&uring.submitter())
+            .map_err(|(e, _)| e)
+            .unwrap();
+        let (tx, rx) = UnixDatagram::pair().unwrap();
+        for i in 0..6u8 {
+            tx.send(&[i]).unwrap();
+            let sqe = ring.recv_sqe(rx.as_raw_fd(), 0, i as u64);
+            unsafe { uring.submission().push(&sqe).unwrap() };
+        }
+        uring.submit_and_wait(6).unwrap();
+
+        let mut received = Vec::new();
+        let mut held = Vec::new();
+        let mut pass = |ring: &mut BufRing<_>, uring: &mut io_uring::IoUring| {
+            let mut budget = ring.budget(4);
+            let left = budget.drain(&mut uring.completion(), |id| {
+                let id = id.unwrap().unwrap();
+                received.push((id.buf_id(), id.buffer()[0]));
+                // keep every third one past the pass
+                if received.len() % 3 == 0 {
+                    // SAFETY: every buffer is returned before the ring is released
+                    held.push(unsafe { id.detach() });
+                }
+            });
+            (left, budget.finish())
+        };
+
+        let tail = ring.published_tail();
+        assert_eq!(pass(&mut ring, &mut uring), (2, 4));
+        // three recycled during the pass, published at its end
+        assert_eq!(ring.published_tail(), tail.wrapping_add(3));
+        assert_eq!(pass(&mut ring, &mut uring), (0, 2));
+        assert_eq!(ring.published_tail(), tail.wrapping_add(4));
+        assert!(!ring.deferred_advance());
+
+        let payloads: Vec<_> = received.iter().map(|&(_, byte)| byte).collect();
+        assert_eq!(payloads, [0, 1, 2, 3, 4, 5]);
+        let mut bids: Vec<_> = received.iter().map(|&(bid, _)| bid).collect();
+        bids.sort_unstable();
+        bids.dedup();
+        assert_eq!(bids.len(), 6);
+        assert_eq!(held.len(), 2);
+        assert_eq!(ring.provided(), 6);
+
+        ring.recycle_many(held).unwrap();
+        assert_eq!(ring.provided(), 8);
+        assert_eq!(ring.verify(), Ok(()));
+        let ring = ring.unregister(&uring.submitter());
+        drop(ring.map_err(|(e, _)| e).unwrap());
+    }
+}
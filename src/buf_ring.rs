@@ -1,5 +1,33 @@
+//! Provided buffer rings.
+//!
+//! The entry array is shared with the kernel: userspace produces entries at the
+//! tail, the kernel consumes them from its private head. The crate keeps its own
+//! copy of the tail plus a count of entries written after it that haven't been
+//! published yet, which gives the following invariants:
+//!
+//! - A returned buffer is always written into the next free slot,
+//!   `tail + pending`, never into a slot derived from the buffer id. Buffers can
+//!   therefore be returned in any order.
+//! - Every slot in `tail..tail + pending` has been written exactly once since the
+//!   last publish, and publishing never exceeds `pending`.
+//! - Each buffer id is either owned by the kernel or by the application, so at
+//!   most `entries` slots are ever in use and writing at `tail + pending` can't
+//!   overwrite a slot the kernel hasn't consumed yet.
+//! - The tail wraps from `u16::MAX` to 0, and 65536 is a multiple of every
+//!   valid ring size (at most 32768 entries), so `slot & mask` stays
+//!   consistent across the wrap.
+
+use crate::sync::{Ordering, SharedTail, fence};
 use io_uring::types::BufRingEntry;
-use std::sync::atomic::{AtomicU16, Ordering};
+// shared with the kernel, so never loom's
+use std::sync::atomic::AtomicU16;
+
+/// Bytes each entry takes up in front of the buffers, the per buffer overhead
+/// of every ring.
+pub const ENTRY_SIZE: usize = core::mem::size_of::<BufRingEntry>();
+
+/// Most entries the kernel accepts for a single ring.
+pub const MAX_ENTRIES: u16 = 1 << 15;
 
 pub mod state {
     pub struct Uninit;
@@ -7,8 +35,21 @@ pub mod state {
     pub struct Init;
 }
 
+use crate::metadata::{Metadata, MetadataTable};
+use crate::observer::RingObserver;
+use crate::watermark::LowWatermark;
 use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::num::NonZeroU16;
+use std::os::fd::{AsRawFd, BorrowedFd};
 
+/// A provided buffer ring and the buffers it hands to the kernel.
+///
+/// The ring is `Send`, so it can be built on one thread and moved to the
+/// worker that owns it, but not `Sync`: returning buffers from several threads
+/// goes through a [`ConcurrentRecycler`]. Handles borrowing the ring, like
+/// [`BufferId`], stay on its thread, while [`OwnedBuffer`] is `Send` and
+/// `Sync` on its own.
 pub struct BufRing<State> {
     base: *mut BufRingEntry,
     entries: u32,
@@ -16,13 +57,339 @@ pub struct BufRing<State> {
     mask: u32,
     bgid: u16,
     buffer_base: *const u8,
+    shared_tail: *const AtomicU16,
+    /// Stands in for the tail in the mapping, see [`crate::sync`].
+    #[cfg(loom)]
+    loom_tail: Box<SharedTail>,
+    tail: u16,
+    pending: u16,
+    deferred: bool,
+    static_layout: bool,
+    single_producer: bool,
+    opts: MapOpts,
+    mode: RegistrationMode,
+    /// Bytes the kernel has consumed of each buffer, only used in incremental mode.
+    consumed: Box<[u32]>,
+    /// Buffers the kernel had consumed without their completions being
+    /// resolved, as of the last `reconcile`.
+    unreaped: u16,
+    /// Reduced lengths given to `provide_with_len`, 0 for a full buffer.
+    /// Empty until it is first used.
+    short_lens: Box<[u32]>,
+    /// Slots written by `provide_buffer_at` beyond the first unwritten one,
+    /// one bit per masked slot. Empty until it is first used.
+    written_ahead: Box<[u64]>,
+    /// Number of bits set in `written_ahead`.
+    ahead: u16,
+    /// The unmasked slot each buffer id was last written into.
+    slot_of: Box<[u16]>,
+    /// Where non-contiguous bundles are copied to.
+    bundle_scratch: Vec<u8>,
+    bids: Box<[BidState]>,
+    counts: [u16; BidState::COUNT],
+    parked: Vec<u16>,
+    provision_limit: Option<u16>,
+    lifo: Option<Lifo>,
+    watermark: Option<LowWatermark>,
+    metadata: Option<Box<dyn Metadata>>,
+    observer: Option<Box<dyn RingObserver>>,
+    #[cfg(feature = "stats")]
+    stats: crate::Stats,
+    #[cfg(feature = "metrics")]
+    metrics: Option<crate::ring_metrics::RingMetrics>,
+    #[cfg(feature = "watchdog")]
+    watchdog: crate::watchdog::Watchdog,
+    lifecycle: Lifecycle,
     state: PhantomData<State>,
 }
 
+// SAFETY: the mapping belongs to the process rather than a thread, and the
+// kernel accepts tail updates from any thread. Every other field is `Send`, the
+// boxed callbacks by their bounds. Using the ring from two threads at once is
+// what would be unsound, and that takes `&mut` or `Sync`.
+unsafe impl<S> Send for BufRing<S> {}
+
+/// Opaque handle to an initialized ring owned by foreign code.
+pub struct BufRingRaw {
+    ring: BufRing<state::Init>,
+}
+
+/// Buffers held back in LIFO mode, see [`BufRing::enable_lifo`].
+#[derive(Debug)]
+struct Lifo {
+    /// How many buffers are kept provided.
+    depth: u16,
+    /// Returned buffers, the most recent on top. Never longer than the ring.
+    stack: Vec<u16>,
+}
+
+/// Runtime mirror of `State`, checked when the ring is dropped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Lifecycle {
+    Uninit,
+    Registered,
+    Init,
+}
+
+/// Who currently owns a buffer id.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum BidState {
+    /// Written into the ring for the kernel to consume.
+    Provided,
+    /// Returned by a completion and not yet recycled.
+    HeldByApp,
+    /// Owned by neither.
+    Parked,
+    /// Set aside by the application with [`BufRing::reserve`].
+    Reserved,
+    /// Taken out of rotation with [`BufRing::quarantine`].
+    Quarantined,
+}
+
+impl BidState {
+    const COUNT: usize = 5;
+}
+
+/// The geometry of a ring, everything but its bgid.
+///
+/// Taken from an existing ring with [`BufRing::config`] to build identical
+/// rings with [`BufRing::from_config`], e.g. one per core.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BufRingConfig {
+    /// Already rounded up to a power of two.
+    pub entries: u16,
+    pub buf_size: u32,
+    pub opts: MapOpts,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
 pub struct MapOpts {
     pub privacy: MapPrivacy,
-    pub populate: bool,
+    pub warmup: Warmup,
+    /// Alignment of the first buffer, padding the gap after the entry array.
+    ///
+    /// 0 places the buffers right after the entries, otherwise it must be a
+    /// power of two no larger than the page size.
+    pub buffer_align: u32,
+}
+
+/// How much of the mapping is faulted in at construction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Warmup {
+    /// Pages are faulted lazily by the first recv into each buffer.
+    /// Cheapest construction, but early completions pay the page faults.
+    #[default]
+    None,
+    /// `MAP_POPULATE`: every page is faulted in by `mmap` itself, making
+    /// construction proportional to the mapping size.
+    Populate,
+    /// `MAP_POPULATE` followed by `madvise(MADV_WILLNEED)`, additionally asking
+    /// the kernel to read ahead anything that isn't resident yet. Slowest to
+    /// construct, for latency critical startups.
+    PopulateAndWillneed,
+}
+
+/// A completion resolved with [`BufRing::resolve_raw`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RawCompletion {
+    pub bid: u16,
+    /// Where the data starts in the buffer, always 0 unless the ring consumes
+    /// incrementally.
+    pub offset: u32,
+    pub len: u32,
+    /// The kernel keeps filling the buffer, so it isn't the application's to
+    /// provide yet. Only ever set in incremental mode.
+    pub more: bool,
+}
+
+/// One slot of the entry array, see [`BufRing::snapshot_entries`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct EntrySnapshot {
+    pub slot: u16,
+    /// Where `addr` points into the ring's buffers, `None` if it points
+    /// elsewhere, e.g. for a slot that was never written.
+    pub offset: Option<u64>,
+    pub len: u32,
+    pub bid: u16,
+}
+
+/// What the kernel reported when a ring was registered.
+///
+/// Registering a ring only returns success or failure, so this is mostly the
+/// crate's own record of how the registration went.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RegistrationInfo {
+    pub mode: RegistrationMode,
+    /// Offset to `mmap` the ring at for kernel allocated rings
+    /// (`IOU_PBUF_RING_MMAP`). Always `None`: this crate maps its rings itself.
+    pub mmap_offset: Option<u64>,
+}
+
+/// Why a ring rejected a request.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RingError {
+    /// A length of 0 or beyond the buffer's capacity.
+    InvalidLen { len: u32, capacity: u32 },
+    /// The buffer id is out of range or already provided to the kernel.
+    NotOwned { buf_id: u16 },
+    /// Buffers are provided to the kernel, which may write into them.
+    Provided { count: u16 },
+    /// The slot was already written since the last publication.
+    SlotWritten { slot: u16 },
+}
+
+impl core::fmt::Display for RingError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidLen { len, capacity } => {
+                write!(f, "length {len} isn't within a buffer of {capacity} bytes")
+            }
+            Self::NotOwned { buf_id } => write!(f, "buffer {buf_id} isn't owned by the application"),
+            Self::Provided { count } => write!(f, "{count} buffers are provided to the kernel"),
+            Self::SlotWritten { slot } => write!(f, "slot {slot} was already written"),
+        }
+    }
+}
+
+impl std::error::Error for RingError {}
+
+/// An entry contradicting the ring's record of it, see [`BufRing::verify`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InvariantViolation {
+    /// The slot carries a buffer id past the end of the ring.
+    BidOutOfRange { slot: u16, bid: u16 },
+    /// The slot carries another buffer than the one written into it.
+    WrongBid {
+        slot: u16,
+        expected: u16,
+        found: u16,
+    },
+    /// Two provided buffers were written into the same slot, so one of them
+    /// was overwritten before the kernel consumed it.
+    SharedSlot { slot: u16, first: u16, second: u16 },
+    /// `addr` doesn't point where the buffer's data goes.
+    BadAddr { slot: u16, bid: u16, addr: u64 },
+    /// `len` is 0 or runs past the end of the buffer.
+    BadLen {
+        slot: u16,
+        bid: u16,
+        len: u32,
+        capacity: u32,
+    },
+}
+
+impl core::fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::BidOutOfRange { slot, bid } => {
+                write!(f, "slot {slot}: buffer id {bid} out of range")
+            }
+            Self::WrongBid {
+                slot,
+                expected,
+                found,
+            } => write!(f, "slot {slot}: buffer {found} instead of {expected}"),
+            Self::SharedSlot {
+                slot,
+                first,
+                second,
+            } => write!(f, "slot {slot}: claimed by buffers {first} and {second}"),
+            Self::BadAddr { slot, bid, addr } => {
+                write!(f, "slot {slot}: addr {addr:#x} outside buffer {bid}")
+            }
+            Self::BadLen {
+                slot,
+                bid,
+                len,
+                capacity,
+            } => write!(
+                f,
+                "slot {slot}: len {len} for buffer {bid} with {capacity} bytes left"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+/// Registration on an SQPOLL ring kept failing with an error that is usually
+/// transient, see [`BufRing::register_with_params`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SqpollRegisterError {
+    /// Registrations attempted.
+    pub attempts: u32,
+    /// The errno of the last one.
+    pub errno: i32,
+}
+
+impl core::fmt::Display for SqpollRegisterError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "buf ring registration failed {} times while the SQPOLL thread was busy: {}",
+            self.attempts,
+            std::io::Error::from_raw_os_error(self.errno)
+        )
+    }
+}
+
+impl std::error::Error for SqpollRegisterError {}
+
+/// A failed [`BufRing::reconfigure`], with the ring in the state it was left in.
+#[derive(Debug)]
+pub enum ReconfigureError {
+    /// Nothing changed, the ring is still registered with its old buffers.
+    Unchanged(std::io::Error, BufRing<state::Init>),
+    /// The old ring was unregistered but the new one couldn't be registered.
+    /// It is handed back with the new buffer size and the carried-over
+    /// settings, ready to be registered again.
+    Unregistered(std::io::Error, BufRing<state::Uninit>),
+}
+
+impl ReconfigureError {
+    pub fn error(&self) -> &std::io::Error {
+        match self {
+            Self::Unchanged(e, _) | Self::Unregistered(e, _) => e,
+        }
+    }
+}
+
+impl core::fmt::Display for ReconfigureError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Unchanged(e, _) => write!(f, "reconfigure failed, ring unchanged: {e}"),
+            Self::Unregistered(e, _) => write!(f, "reconfigured ring couldn't be registered: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ReconfigureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error())
+    }
+}
+
+/// How often [`BufRing::register_with_params`] tries to register on an SQPOLL ring.
+pub const SQPOLL_REGISTER_ATTEMPTS: u32 = 5;
+
+/// Whether a registration failing with `errno` may succeed on retry while the
+/// SQPOLL thread is parked or being created.
+pub(crate) fn is_transient_sqpoll_errno(errno: i32) -> bool {
+    errno == libc::EBUSY || errno == libc::EOWNERDEAD
+}
+
+/// How a ring was registered, see [`BufRing::register_auto`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum RegistrationMode {
+    /// Every completion consumes a whole buffer.
+    #[default]
+    Plain,
+    /// `IOU_PBUF_RING_INC`: a completion only consumes the bytes it received,
+    /// and the kernel keeps filling the same buffer until it is exhausted.
+    /// Completions of a buffer that isn't exhausted yet carry
+    /// `IORING_CQE_F_BUF_MORE`, and their [`BufferId`] doesn't recycle it.
+    Incremental,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
@@ -30,67 +397,42 @@ pub enum MapPrivacy {
     #[default]
     Private,
     Shared,
+    /// `MAP_SHARED_VALIDATE`: shared, but `mmap` fails on flags the kernel
+    /// doesn't know instead of ignoring them.
+    SharedValidate,
 }
 
 impl BufRing<state::Uninit> {
     pub fn new(entries: u16, buf_size: u32, bgid: u16) -> std::io::Result<Self> {
-        Self::new_with_opts(entries, buf_size, bgid, Default::default())
+        let entries = NonZeroU16::new(entries).ok_or(std::io::ErrorKind::InvalidInput)?;
+        Self::new_nonzero(entries, buf_size, bgid)
+    }
+
+    /// Like [`new`](Self::new), for an entry count already known not to be 0.
+    pub fn new_nonzero(entries: NonZeroU16, buf_size: u32, bgid: u16) -> std::io::Result<Self> {
+        Self::map_new(entries, buf_size, bgid, Default::default())
     }
 
     pub fn new_with_opts(
-        mut entries: u16,
+        entries: u16,
         buf_size: u32,
         bgid: u16,
         opts: MapOpts,
     ) -> std::io::Result<Self> {
-        if entries == 0 || entries == u16::MAX {
-            return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput));
-        }
+        let entries = NonZeroU16::new(entries).ok_or(std::io::ErrorKind::InvalidInput)?;
+        Self::map_new(entries, buf_size, bgid, opts)
+    }
 
-        if !entries.is_power_of_two() {
-            entries = entries.next_power_of_two()
-        }
+    fn map_new(
+        entries: NonZeroU16,
+        buf_size: u32,
+        bgid: u16,
+        opts: MapOpts,
+    ) -> std::io::Result<Self> {
+        let entries = ring_entries(entries)?;
         let mask = entries - 1;
 
-        let mut map_flags = libc::MAP_ANONYMOUS;
-
-        map_flags |= match opts.privacy {
-            MapPrivacy::Private => libc::MAP_PRIVATE,
-            MapPrivacy::Shared => libc::MAP_SHARED,
-        };
-
-        if opts.populate {
-            map_flags |= libc::MAP_POPULATE;
-        }
-
-        let buf_ring_size =
-            entries as usize * (buf_size as usize + core::mem::size_of::<BufRingEntry>());
-
-        let base = unsafe {
-            match libc::mmap(
-                core::ptr::null_mut(),
-                buf_ring_size,
-                libc::PROT_READ | libc::PROT_WRITE,
-                map_flags,
-                -1,
-                0,
-            ) {
-                libc::MAP_FAILED => return Err(std::io::Error::last_os_error()),
-                addr => addr,
-            }
-        };
-
-        let buf_base: *const u8 = unsafe {
-            base.offset(entries as isize * core::mem::size_of::<BufRingEntry>() as isize)
-                as *const u8
-        };
-
-        let base = base as *mut _;
-
-        unsafe {
-            let tail = BufRingEntry::tail(base);
-            let _ = AtomicU16::from_ptr(tail as _).store(0, Ordering::Relaxed);
-        }
+        let (base, buf_base) = map_ring(entries, buf_size, opts)?;
 
         Ok(Self {
             base,
@@ -99,25 +441,246 @@ impl BufRing<state::Uninit> {
             mask: mask as u32,
             bgid,
             buffer_base: buf_base,
+            shared_tail: unsafe { tail_atomic(base) },
+            #[cfg(loom)]
+            loom_tail: Box::new(SharedTail::new(0)),
+            tail: 0,
+            pending: 0,
+            deferred: false,
+            static_layout: false,
+            single_producer: false,
+            opts,
+            mode: RegistrationMode::Plain,
+            consumed: Box::new([]),
+            unreaped: 0,
+            short_lens: Box::new([]),
+            written_ahead: Box::new([]),
+            ahead: 0,
+            slot_of: vec![0; entries as usize].into_boxed_slice(),
+            bundle_scratch: Vec::new(),
+            bids: vec![BidState::Parked; entries as usize].into_boxed_slice(),
+            counts: [0, 0, entries, 0, 0],
+            parked: Vec::new(),
+            provision_limit: None,
+            lifo: None,
+            watermark: None,
+            metadata: None,
+            observer: None,
+            #[cfg(feature = "stats")]
+            stats: Default::default(),
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            #[cfg(feature = "watchdog")]
+            watchdog: crate::watchdog::Watchdog::new(entries),
+            lifecycle: Lifecycle::Uninit,
             state: PhantomData,
         })
     }
 
+    /// Maps a new ring with the geometry of `config`.
+    pub fn from_config(config: BufRingConfig, bgid: u16) -> std::io::Result<Self> {
+        Self::new_with_opts(config.entries, config.buf_size, bgid, config.opts)
+    }
+
     pub fn set_bgid(&mut self, bgid: u16) {
         self.bgid = bgid;
     }
 
+    /// Drops the physical pages backing the ring while keeping the mapping.
+    ///
+    /// Meant for an idle ring after a traffic spike. Pages are faulted back in
+    /// lazily, and for a private mapping they read as zeroes afterwards, which
+    /// is fine since `init` rewrites every entry and the tail is already 0.
+    /// A [`MapPrivacy::Shared`] or [`MapPrivacy::SharedValidate`] mapping
+    /// keeps its contents and pages.
+    pub fn release_pages(&self) -> std::io::Result<()> {
+        #[cfg(miri)]
+        {
+            if self.opts.privacy == MapPrivacy::Private {
+                // what the kernel does for a private mapping
+                unsafe { self.base.cast::<u8>().write_bytes(0, self.mmap_size()) };
+            }
+            Ok(())
+        }
+        #[cfg(not(miri))]
+        {
+            let ret =
+                unsafe { libc::madvise(self.base.cast(), self.mmap_size(), libc::MADV_DONTNEED) };
+            if ret != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
     pub fn register(
         self,
         submitter: &io_uring::Submitter<'_>,
     ) -> Result<BufRing<state::Registered>, (std::io::Error, Self)> {
-        if let Err(e) =
-            unsafe { submitter.register_buf_ring(self.ring_addr(), self.entries(), self.bgid()) }
-        {
+        self.register_with_flags(submitter, 0)
+    }
+
+    /// Like [`register`](Self::register), also returning what is known about
+    /// the registration.
+    pub fn register_with_info(
+        self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<(BufRing<state::Registered>, RegistrationInfo), (std::io::Error, Self)> {
+        let ring = self.register(submitter)?;
+        let info = ring.registration_info();
+        Ok((ring, info))
+    }
+
+    /// Like [`register`](Self::register), retrying transient failures on an
+    /// io_uring created with `IORING_SETUP_SQPOLL`, as reported by `params`.
+    ///
+    /// While the SQ thread is parked or still being created, some kernels fail
+    /// the registration with `EBUSY` or `EOWNERDEAD`. Those are retried up to
+    /// [`SQPOLL_REGISTER_ATTEMPTS`] times, sleeping 1ms, 2ms, 4ms, ... in
+    /// between, so this may block. Once they are exhausted the error wraps a
+    /// [`SqpollRegisterError`]. Any other error, or any error without SQPOLL,
+    /// is returned right away.
+    pub fn register_with_params(
+        self,
+        submitter: &io_uring::Submitter<'_>,
+        params: &io_uring::Parameters,
+    ) -> Result<BufRing<state::Registered>, (std::io::Error, Self)> {
+        self.retry_on_sqpoll(params.is_setup_sqpoll(), |ring| ring.register(submitter))
+    }
+
+    /// The retry loop of [`register_with_params`](Self::register_with_params),
+    /// around any registration.
+    fn retry_on_sqpoll<T>(
+        self,
+        sqpoll: bool,
+        mut register: impl FnMut(Self) -> Result<T, (std::io::Error, Self)>,
+    ) -> Result<T, (std::io::Error, Self)> {
+        let mut ring = self;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let (e, this) = match register(ring) {
+                Ok(registered) => return Ok(registered),
+                Err(err) => err,
+            };
+            let errno = match e.raw_os_error() {
+                Some(errno) if sqpoll && is_transient_sqpoll_errno(errno) => errno,
+                _ => return Err((e, this)),
+            };
+            if attempts == SQPOLL_REGISTER_ATTEMPTS {
+                let err = SqpollRegisterError { attempts, errno };
+                return Err((std::io::Error::new(e.kind(), err), this));
+            }
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                bgid = this.bgid,
+                errno,
+                attempts,
+                "retrying registration on SQPOLL ring"
+            );
+            std::thread::sleep(std::time::Duration::from_millis(1 << (attempts - 1)));
+            ring = this;
+        }
+    }
+
+    /// Registers the ring with the best mode the running kernel supports.
+    ///
+    /// Incremental consumption (6.12+) is tried first, falling back to a plain
+    /// registration if the kernel rejects the flag. Completion handling through
+    /// [`BufferId`] works with either mode, but code reading entries or
+    /// offsets directly has to check which one was chosen.
+    pub fn register_auto(
+        self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<(BufRing<state::Registered>, RegistrationMode), (std::io::Error, Self)> {
+        match self.register_mode(submitter, RegistrationMode::Incremental) {
+            Ok(ring) => Ok((ring, RegistrationMode::Incremental)),
+            Err((e, ring)) if e.raw_os_error() == Some(libc::EINVAL) => ring
+                .register(submitter)
+                .map(|ring| (ring, RegistrationMode::Plain)),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn register_mode(
+        self,
+        submitter: &io_uring::Submitter<'_>,
+        mode: RegistrationMode,
+    ) -> Result<BufRing<state::Registered>, (std::io::Error, Self)> {
+        const IOU_PBUF_RING_INC: u16 = 2;
+
+        match mode {
+            RegistrationMode::Plain => self.register(submitter),
+            RegistrationMode::Incremental => self
+                .register_with_flags(submitter, IOU_PBUF_RING_INC)
+                .map(|mut ring| {
+                    ring.mode = RegistrationMode::Incremental;
+                    ring.consumed = vec![0; ring.entries as usize].into_boxed_slice();
+                    ring
+                }),
+        }
+    }
+
+    fn register_with_flags(
+        self,
+        submitter: &io_uring::Submitter<'_>,
+        flags: u16,
+    ) -> Result<BufRing<state::Registered>, (std::io::Error, Self)> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "buf_ring_register",
+            bgid = self.bgid,
+            entries = self.entries,
+            buf_size = self.buf_size
+        )
+        .entered();
+
+        // entries written before registration (or left over from a previous one)
+        // and the reset tail must be ordered before the kernel can see the ring
+        fence(Ordering::Release);
+        if let Err(e) = unsafe {
+            submitter.register_buf_ring_with_flags(
+                self.ring_addr(),
+                self.entries(),
+                self.bgid(),
+                flags,
+            )
+        } {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(error = %e, "register failed");
             return Err((e, self));
         }
+        Ok(self.into_registered())
+    }
+
+    pub(crate) fn into_registered(mut self) -> BufRing<state::Registered> {
+        self.lifecycle = Lifecycle::Registered;
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics = Some(crate::ring_metrics::RingMetrics::new(self.bgid));
+        }
         // SAFETY: same type layout
-        Ok(unsafe { core::mem::transmute(self) })
+        unsafe { core::mem::transmute(self) }
+    }
+
+    /// Moves the ring to `Registered` without a kernel, for running the
+    /// crate under Miri, which can't set up an io_uring.
+    ///
+    /// `cargo +nightly miri test --lib` runs every test that doesn't need a kernel.
+    #[cfg(miri)]
+    pub fn register_for_miri(self) -> BufRing<state::Registered> {
+        fence(Ordering::Release);
+        self.into_registered()
+    }
+
+    /// Registers the ring and provides every buffer to the kernel.
+    ///
+    /// The entries are published by the `Release` tail store in `init`.
+    pub fn register_and_init(
+        self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<BufRing<state::Init>, (std::io::Error, Self)> {
+        self.register(submitter).map(BufRing::init)
     }
 }
 
@@ -129,13 +692,80 @@ impl BufRing<state::Registered> {
         unsafe { self.unregister_(submitter) }
     }
 
+    /// Provides `ids` in the given order, one slot after the other from the
+    /// tail, and publishes them.
+    ///
+    /// [`init`](Self::init) afterwards only provides the remaining buffers.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that every id is < `self.entries()`, appears only
+    /// once and hasn't been provided yet. Debug builds check all three.
+    pub unsafe fn provide_in_order(&mut self, ids: &[u16]) {
+        for &buf_id in ids {
+            debug_assert!(buf_id < self.entries(), "buffer id out of range");
+            debug_assert_eq!(self.bid_state(buf_id), BidState::Parked, "buffer provided twice");
+            unsafe { self.push(buf_id) };
+        }
+        unsafe { self.flush_pending() }
+    }
+
+    /// Provides every buffer not provided yet and moves the ring to `Init`.
     pub fn init(mut self) -> BufRing<state::Init> {
         let entries = self.entries();
+        if self.provided() == 0 {
+            // a freshly registered ring always starts with an empty tail
+            debug_assert_eq!((self.tail, self.pending), (0, 0));
 
-        for i in 0..entries {
-            unsafe { self.add(i, i) };
+            // so slot `i` gets buffer `i`, and no tail has to be read per entry
+            let mut addr = self.buffer_base as u64;
+            for i in 0..entries {
+                let entry = unsafe { &mut *self.base.add(i as usize) };
+                entry.set_addr(addr);
+                entry.set_len(self.buf_size);
+                entry.set_bid(i);
+                addr += self.buf_size as u64;
+            }
+
+            for (slot, slot_of) in self.slot_of.iter_mut().enumerate() {
+                *slot_of = slot as u16;
+            }
+            self.bids.fill(BidState::Provided);
+            self.counts = [entries, 0, 0, 0, 0];
+            if let Some(watermark) = &mut self.watermark {
+                watermark.update(entries);
+            }
+
+            self.pending = entries;
+            unsafe { self.publish(entries) }
+        } else {
+            // the rest of the buffers after `provide_in_order`, in id order
+            for buf_id in 0..entries {
+                if self.bid_state(buf_id) == BidState::Parked {
+                    unsafe { self.push(buf_id) };
+                }
+            }
+            unsafe { self.flush_pending() }
         }
-        unsafe { self.advance_(entries) }
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &mut self.metrics {
+            metrics.set_provided(entries);
+        }
+        // the kernel rewrites `addr` and `len` of partially consumed entries
+        self.static_layout = self.mode == RegistrationMode::Plain;
+        self.lifecycle = Lifecycle::Init;
+
+        // SAFETY: same type layout
+        unsafe { core::mem::transmute(self) }
+    }
+
+    /// Moves the ring to `Init` with every buffer parked and nothing provided,
+    /// for rings the application fills before the kernel reads them.
+    pub(crate) fn init_parked(mut self) -> BufRing<state::Init> {
+        debug_assert_eq!(self.provided(), 0);
+        // popped from the back, so buffer 0 is taken first
+        self.parked = (0..self.entries()).rev().collect();
+        self.lifecycle = Lifecycle::Init;
 
         // SAFETY: same type layout
         unsafe { core::mem::transmute(self) }
@@ -150,6 +780,71 @@ impl BufRing<state::Init> {
         unsafe { self.unregister_(submitter) }
     }
 
+    /// Remaps the ring with buffers of `new_buf_size`, keeping `entries` and `bgid`.
+    ///
+    /// The ring is unregistered, remapped, registered and initialized again, so
+    /// any recv still waiting on this buffer group will see `ENOBUFS`. The
+    /// registration mode, deferred advance, provision limit, LIFO depth, low
+    /// watermark, observer and metadata table carry over, with every metadata
+    /// slot reset. Like [`init`](BufRing::init), the new ring starts with every
+    /// buffer provided; a limit only parks them as they come back.
+    pub fn reconfigure(
+        mut self,
+        new_buf_size: u32,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<BufRing<state::Init>, ReconfigureError> {
+        let invalid = || std::io::Error::from(std::io::ErrorKind::InvalidInput);
+        if new_buf_size == 0 {
+            return Err(ReconfigureError::Unchanged(invalid(), self));
+        }
+        let entries = self.entries();
+        if ring_size(entries as u32, new_buf_size, self.opts.buffer_align) > isize::MAX as usize {
+            return Err(ReconfigureError::Unchanged(invalid(), self));
+        }
+
+        let mut new = match BufRing::new_with_opts(entries, new_buf_size, self.bgid, self.opts) {
+            Ok(new) => new,
+            Err(e) => return Err(ReconfigureError::Unchanged(e, self)),
+        };
+        new.deferred = self.deferred;
+        new.provision_limit = self.provision_limit;
+        new.lifo = self.lifo.as_ref().map(|lifo| Lifo {
+            depth: lifo.depth,
+            stack: Vec::with_capacity(entries as usize),
+        });
+        new.watermark = self.watermark.take();
+        new.observer = self.observer.take();
+        new.metadata = self.metadata.take();
+        let mode = self.mode;
+
+        if let Err((e, mut this)) = self.unregister(submitter) {
+            this.watermark = new.watermark.take();
+            this.observer = new.observer.take();
+            this.metadata = new.metadata.take();
+            return Err(ReconfigureError::Unchanged(e, this));
+        }
+        if let Some(metadata) = &mut new.metadata {
+            for buf_id in 0..entries {
+                metadata.reset(buf_id);
+            }
+        }
+
+        match new.register_mode(submitter, mode) {
+            Ok(ring) => Ok(ring.init()),
+            Err((e, new)) => Err(ReconfigureError::Unregistered(e, new)),
+        }
+    }
+
+    /// Resolves the buffer `cqe` selected, recycled when the id is dropped.
+    ///
+    /// Works with both `cqueue::Entry` and the big `cqueue::Entry32` of rings
+    /// set up with `IORING_SETUP_CQE32`, whose extra 16 bytes follow the same
+    /// `user_data`, `res` and `flags` and are ignored.
+    ///
+    /// A failed completion is returned as its error, and a buffer id past
+    /// [`entries`](Self::entries) as `InvalidData`, e.g. for a completion of
+    /// another buffer group.
+    #[cfg_attr(feature = "watchdog", track_caller)]
     pub fn buffer_id_from_cqe<'a, 'b, E: io_uring::cqueue::EntryMarker>(
         &'a mut self,
         cqe: &'b E,
@@ -157,13 +852,130 @@ impl BufRing<state::Init> {
         BufferId::new(self, cqe)
     }
 
+    /// Like [`buffer_id_from_cqe`](Self::buffer_id_from_cqe), for anything
+    /// carrying the fields of a completion, e.g. a
+    /// [`FakeCqe`](crate::cqe::FakeCqe) in tests.
+    #[cfg_attr(feature = "watchdog", track_caller)]
+    pub fn buffer_id_from_fields<'a, 'b, C: CqeFields>(
+        &'a mut self,
+        cqe: &'b C,
+    ) -> std::io::Result<Option<BufferId<'a, 'b, io_uring::cqueue::Entry>>> {
+        BufferId::from_flags(self, cqe.flags(), cqe.result())
+    }
+
+    /// Resolves a completion from its `flags` and `res` without handing out a
+    /// [`BufferId`].
+    ///
+    /// Nothing is recycled automatically: in raw mode the application owns
+    /// every buffer it resolves and must provide it again itself, e.g. with
+    /// [`provide_with_len`](Self::provide_with_len) at [`buf_size`](Self::buf_size),
+    /// or the ring runs dry.
+    #[cfg_attr(feature = "watchdog", track_caller)]
+    pub fn resolve_raw(&mut self, flags: u32, res: i32) -> std::io::Result<Option<RawCompletion>> {
+        let id = BufferId::<io_uring::cqueue::Entry>::from_flags(self, flags, res)?;
+        Ok(id.map(|id| {
+            let (bid, offset, len, held) = id.into_parts();
+            RawCompletion {
+                bid,
+                offset,
+                len,
+                more: !held,
+            }
+        }))
+    }
+
+    /// Appends a copy of every buffer received by `cqes` to `out`, recycling
+    /// each one right after it is copied, and returns how many were appended.
+    ///
+    /// Completions without a buffer, including failed ones, are skipped.
+    pub fn drain_into<E, C>(&mut self, cqes: impl Iterator<Item = E>, out: &mut C) -> usize
+    where
+        E: io_uring::cqueue::EntryMarker,
+        C: Extend<Vec<u8>>,
+    {
+        let mut count = 0;
+        for cqe in cqes {
+            if let Ok(Some(id)) = BufferId::new(self, &cqe) {
+                out.extend(Some(id.buffer().to_vec()));
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Resolves the completion of a bundle recv (`IORING_RECVSEND_BUNDLE`),
+    /// which may fill several buffers.
+    ///
+    /// The bundle's data is read in place when its buffers are adjacent, and
+    /// otherwise copied into a scratch buffer kept by the ring, e.g. when the
+    /// bundle wraps around the end of the ring. See
+    /// [`set_bundle_scratch_capacity`](Self::set_bundle_scratch_capacity) to
+    /// avoid allocating on that path.
+    pub fn bundle_from_cqe<E: io_uring::cqueue::EntryMarker>(
+        &mut self,
+        cqe: &E,
+    ) -> std::io::Result<Option<Bundle<'_>>> {
+        let Some(first) = BufferId::new(self, cqe)?.map(BufferId::into_parts) else {
+            return Ok(None);
+        };
+        let (buf_id, _, len, _) = first;
+        Ok(Some(Bundle::new(self, buf_id, len)))
+    }
+
+    /// Like [`bundle_from_cqe`](Self::bundle_from_cqe), for anything carrying
+    /// the fields of a completion.
+    pub fn bundle_from_fields<C: CqeFields>(
+        &mut self,
+        cqe: &C,
+    ) -> std::io::Result<Option<Bundle<'_>>> {
+        let first = BufferId::<io_uring::cqueue::Entry>::from_flags(self, cqe.flags(), cqe.result());
+        let Some((buf_id, _, len, _)) = first?.map(BufferId::into_parts) else {
+            return Ok(None);
+        };
+        Ok(Some(Bundle::new(self, buf_id, len)))
+    }
+
+    /// Preallocates the scratch buffer non-contiguous bundles are copied into.
+    ///
+    /// A bundle view borrows the ring mutably either way, since it may read
+    /// from the scratch buffer.
+    pub fn set_bundle_scratch_capacity(&mut self, bytes: usize) {
+        self.bundle_scratch.clear();
+        self.bundle_scratch.reserve(bytes);
+    }
+
+    /// Recycles the buffer carried by `cqe` without looking at its contents.
+    ///
+    /// Returns whether the completion carried a buffer.
+    pub fn discard_cqe<E: io_uring::cqueue::EntryMarker>(&mut self, cqe: &E) -> std::io::Result<bool> {
+        // dropping the id right away recycles it
+        Ok(BufferId::new(self, cqe)?.is_some())
+    }
+
     /// # Safety
     ///
     /// The caller must ensure that `offset` is < `self.entries()`
+    #[deprecated(note = "use `entry_ref` or `entry_mut`")]
     pub unsafe fn entry(&self, offset: u16) -> *const BufRingEntry {
         unsafe { self.base.offset(offset as isize) }
     }
 
+    /// The entry at index `offset` of the entry array, `None` if it is out of range.
+    pub fn entry_ref(&self, offset: u16) -> Option<&BufRingEntry> {
+        // SAFETY: in range, and the mapping lives as long as `self`
+        (offset < self.entries()).then(|| unsafe { &*self.base.add(offset as usize) })
+    }
+
+    /// Like [`entry_ref`](Self::entry_ref), for editing the entry in place.
+    ///
+    /// Memory safe, but rewriting an entry that is published and not yet
+    /// consumed changes the buffer the kernel will fill under the ring's feet,
+    /// and the ring's own bookkeeping of which buffer sits where is not updated.
+    pub fn entry_mut(&mut self, offset: u16) -> Option<&mut BufRingEntry> {
+        // SAFETY: in range, and the mapping lives as long as `self`
+        (offset < self.entries()).then(|| unsafe { &mut *self.base.add(offset as usize) })
+    }
+
     /// # Safety
     ///
     /// The caller must ensure that `buf_id` is < `self.entries()`
@@ -174,108 +986,3348 @@ impl BufRing<state::Init> {
         }
     }
 
-    /// # Safety
-    ///
-    /// The caller must ensure that an entry has been written into the buf ring.
-    pub unsafe fn advance(&mut self, count: u16) {
-        unsafe { self.advance_(count) }
-    }
-}
-
-use crate::buffer_id::BufferId;
-
-impl<S> BufRing<S> {
-    /// # Safety
+    /// Hints the cpu to pull the first cache line of `buf_id` into cache, e.g.
+    /// for the next buffer a recv loop will read.
     ///
-    /// The caller must ensure that `buf_id` and `buf_offset` is < `self.entries()`
+    /// A no-op for ids out of range and on targets without a stable prefetch
+    /// intrinsic.
     #[inline]
-    unsafe fn add(&mut self, buf_id: u16, buf_offset: u16) {
-        let (entry, buffer_addr) = unsafe {
-            let offset = (self.tail() + buf_offset as u32) & self.mask;
-            (
-                &mut *self.base.offset(offset as isize),
-                self.get_buffer(buf_id),
-            )
+    pub fn prefetch_buffer(&self, buf_id: u16) {
+        if buf_id >= self.entries() {
+            return;
+        }
+        // SAFETY: in range
+        let _ptr = unsafe { self.get_buffer(buf_id) };
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: a prefetch never faults, and SSE is part of x86_64
+        unsafe {
+            core::arch::x86_64::_mm_prefetch::<{ core::arch::x86_64::_MM_HINT_T0 }>(_ptr.cast())
         };
-        entry.set_addr(buffer_addr as u64);
-        entry.set_len(self.buf_size);
-        entry.set_bid(buf_id);
     }
 
+    /// The `count` adjacent buffers starting at `first_id` as one slice.
+    ///
     /// # Safety
     ///
-    /// The caller must ensure `buf_id` < `self.entries()`
-    #[inline]
-    unsafe fn get_buffer(&self, buf_id: u16) -> *const u8 {
+    /// The caller must ensure that `first_id + count <= self.entries()`; the data
+    /// region doesn't wrap.
+    pub unsafe fn buffers_contiguous(&self, first_id: u16, count: u16) -> &[u8] {
+        debug_assert!(first_id as u32 + count as u32 <= self.entries);
         unsafe {
-            self.buffer_base
-                .offset((buf_id as u32 * self.buf_size) as isize)
+            let buf = self.get_buffer(first_id);
+            core::slice::from_raw_parts(buf, count as usize * self.buf_size as usize)
         }
     }
 
+    /// The buffer for `buf_id` as a write target.
+    ///
     /// # Safety
     ///
-    /// This function should not be called before the buf ring is registered
-    #[inline]
-    pub(crate) unsafe fn advance_(&mut self, count: u16) {
+    /// The caller must ensure that `buf_id` is < `self.entries()` and that the
+    /// buffer isn't currently provided to the kernel.
+    pub unsafe fn buffer_uninit(&mut self, buf_id: u16) -> &mut [MaybeUninit<u8>] {
         unsafe {
-            let tail = BufRingEntry::tail(self.base);
-            let _ = AtomicU16::from_ptr(tail as _).fetch_add(count, Ordering::Relaxed);
+            let buf = self.get_buffer(buf_id) as *mut MaybeUninit<u8>;
+            core::slice::from_raw_parts_mut(buf, self.buf_size as usize)
         }
     }
 
-    pub unsafe fn init_(&mut self) {
-        unsafe {
-            let tail = BufRingEntry::tail(self.base);
-            let _ = AtomicU16::from_ptr(tail as _).store(0, Ordering::Relaxed);
+    /// Publishes `count` entries written with [`provide_buffer_at`](Self::provide_buffer_at).
+    ///
+    /// Not needed for buffers resolved through [`BufferId`]: dropping one writes
+    /// its id into the next free slot at the tail and publishes it, whatever
+    /// order completions arrive and are dropped in. The kernel only ever
+    /// consumes slots in order, so which buffer sits in which slot doesn't
+    /// matter, and since every id is owned by either the kernel or the
+    /// application no slot can be provided twice or lost.
+    ///
+    /// The tail is a `u16` that wraps from 65535 to 0. Every ring size divides
+    /// 65536, so `tail & (entries - 1)` keeps naming the same slot across the
+    /// wrap and callers never have to special case it.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `count` entries have been written past the
+    /// tail. Debug builds check this against the slots written since the last
+    /// publication.
+    pub unsafe fn advance(&mut self, count: u16) {
+        debug_assert!(
+            count <= self.pending,
+            "advancing by {count} with only {} slots written",
+            self.pending
+        );
+        unsafe { self.advance_(count) }
+    }
+
+    /// Defers publishing recycled buffers until [`BufRing::flush`] is called.
+    ///
+    /// This delays when the kernel sees buffers again, so it trades `ENOBUFS`
+    /// headroom for fewer stores to the shared tail. If more than half the ring
+    /// is waiting to be published it is flushed automatically. Disabling it
+    /// flushes immediately.
+    pub fn set_deferred_advance(&mut self, deferred: bool) {
+        self.deferred = deferred;
+        if !deferred {
+            self.flush();
         }
     }
 
-    pub fn entries(&self) -> u16 {
-        self.entries as u16
+    pub fn deferred_advance(&self) -> bool {
+        self.deferred
     }
 
-    pub fn ring_addr(&self) -> u64 {
-        self.base as u64
+    /// Resolves at most `max` completions, publishing the buffers they return
+    /// in one batch, see [`ProcessBudget`].
+    pub fn budget(&mut self, max: usize) -> ProcessBudget<'_> {
+        ProcessBudget::new(self, max)
     }
 
-    pub fn bgid(&self) -> u16 {
-        self.bgid
+    /// Caps how many buffers are provided to the kernel at once.
+    ///
+    /// Buffers returned while the limit is reached are parked instead of being
+    /// provided, and whenever a completion takes a buffer the ring is topped
+    /// back up from the parked ones. Lowering the limit never recalls buffers
+    /// from the kernel, it only parks them as they come back.
+    pub fn set_provision_limit(&mut self, n: u16) {
+        self.provision_limit = Some(n.min(self.entries()));
+        self.provide_parked_to_limit();
     }
 
-    /// # Safety
+    /// Removes the limit, providing every parked buffer.
+    pub fn clear_provision_limit(&mut self) {
+        self.provision_limit = None;
+        self.provide_parked_to_limit();
+    }
+
+    /// Raises the limit by `n`, providing parked buffers right away.
+    pub fn raise_limit(&mut self, n: u16) {
+        let limit = self.provision_limit.unwrap_or(self.entries());
+        self.set_provision_limit(limit.saturating_add(n));
+    }
+
+    /// Lowers the limit by `n`, parking buffers as they are returned.
+    pub fn lower_limit(&mut self, n: u16) {
+        let limit = self.provision_limit.unwrap_or(self.entries());
+        self.set_provision_limit(limit.saturating_sub(n));
+    }
+
+    pub fn provision_limit(&self) -> Option<u16> {
+        self.provision_limit
+    }
+
+    /// Keeps at most `depth` buffers provided, reusing the most recently
+    /// returned ones first.
     ///
-    /// The caller must ensure that this `BufRing` is already initialized
-    pub unsafe fn tail(&self) -> u32 {
+    /// The kernel consumes provided buffers in order, so at low load a FIFO ring
+    /// cycles through every buffer and each recv lands in cold memory. In LIFO
+    /// mode buffers returned while `depth` are provided go on a stack, and
+    /// whenever a completion takes a buffer the ring is topped back up from
+    /// the top of that stack, so the kernel keeps getting the same few hot
+    /// buffers. Once the stack is empty buffers simply rotate as usual.
+    ///
+    /// The stack is separate from the buffers parked under the
+    /// [provision limit](Self::set_provision_limit), which stays in force.
+    /// Buffers already provided are not recalled.
+    pub fn enable_lifo(&mut self, depth: u16) {
+        let depth = depth.clamp(1, self.entries());
+        match &mut self.lifo {
+            Some(lifo) => lifo.depth = depth,
+            None => {
+                self.lifo = Some(Lifo {
+                    depth,
+                    stack: Vec::with_capacity(self.entries() as usize),
+                })
+            }
+        }
+        self.provide_parked_to_limit();
+    }
+
+    /// Goes back to FIFO rotation, providing every buffer on the LIFO stack
+    /// up to the provision limit.
+    pub fn disable_lifo(&mut self) {
+        let Some(lifo) = self.lifo.take() else {
+            return;
+        };
+        // SAFETY: stacked ids are owned by neither side, and an initialized
+        // ring is registered
         unsafe {
-            let ptr = BufRingEntry::tail(self.base);
-            core::ptr::addr_of!(ptr) as u32
+            for buf_id in lifo.stack {
+                self.push_or_park(buf_id, self.buf_size);
+            }
+            self.flush_pending();
+        }
+    }
+
+    pub fn lifo_depth(&self) -> Option<u16> {
+        self.lifo.as_ref().map(|lifo| lifo.depth)
+    }
+
+    /// Provides stacked, then parked buffers up to whatever limits are active.
+    pub(crate) fn provide_parked_to_limit(&mut self) {
+        unsafe { self.provide_parked(self.provision_cap()) }
+    }
+
+    /// Takes a buffer that is neither provided nor held out of the parked
+    /// ones and the LIFO stack.
+    fn unpark(&mut self, buf_id: u16) {
+        self.parked.retain(|&id| id != buf_id);
+        if let Some(lifo) = &mut self.lifo {
+            lifo.stack.retain(|&id| id != buf_id);
+        }
+    }
+
+    /// Provides every buffer owned by neither the kernel nor the application,
+    /// up to the provision limit, in a single publication, and returns how
+    /// many were provided.
+    ///
+    /// Buffers held by the application, including kept ones and those of
+    /// dropped [`OwnedBuffer`]s, are left alone. Calling it again without
+    /// anything being parked in between provides nothing.
+    pub fn refill_unprovided(&mut self) -> u16 {
+        self.parked.clear();
+        if let Some(lifo) = &mut self.lifo {
+            lifo.stack.clear();
+        }
+        let before = self.pending;
+        for buf_id in 0..self.entries() {
+            if self.bid_state(buf_id) == BidState::Parked {
+                // SAFETY: parked ids are owned by neither side
+                unsafe { self.push_or_park(buf_id, self.buf_size) };
+            }
+        }
+        let count = self.pending - before;
+        // SAFETY: an initialized ring is registered
+        unsafe { self.flush_pending() };
+        count
+    }
+
+    /// Publishes every recycled buffer that hasn't been published yet.
+    pub fn flush(&mut self) {
+        unsafe { self.flush_pending() }
+        #[cfg(feature = "metrics")]
+        {
+            let provided = self.provided();
+            if let Some(metrics) = &mut self.metrics {
+                metrics.set_provided(provided);
+            }
         }
     }
 
+    /// Whether at least `n` buffers are published to the kernel.
+    ///
+    /// Meant as a check before arming a multishot recv, which otherwise risks
+    /// terminating right away with `ENOBUFS`. Buffers the kernel has consumed
+    /// but whose completions haven't been resolved yet still count, so this
+    /// is an upper bound. Unpublished buffers don't; `flush` first to count them.
+    pub fn has_at_least(&self, n: u16) -> bool {
+        self.kernel_held_estimate() >= n
+    }
+
+    /// Buffers the kernel still holds, estimated without a syscall.
+    ///
+    /// Counts published buffers whose completions haven't been resolved yet,
+    /// so it is an upper bound: a buffer whose completion is still waiting in
+    /// the completion queue counts as held. [`reconcile`](Self::reconcile)
+    /// corrects it with the kernel's actual head.
+    pub fn kernel_held_estimate(&self) -> u16 {
+        self.provided() - self.unpublished() - self.unreaped
+    }
+
+    /// Buffers the kernel had consumed without their completions being
+    /// resolved, as of the last [`reconcile`](Self::reconcile).
+    pub fn unreaped(&self) -> u16 {
+        self.unreaped
+    }
+
+    /// Snaps [`kernel_held_estimate`](Self::kernel_held_estimate) to the
+    /// kernel's `head`, as returned by `IORING_REGISTER_PBUF_STATUS`.
+    ///
+    /// Completions for buffers consumed up to `head` must be resolved in
+    /// completion queue order afterwards, as they are with a single consumer.
+    pub fn reconcile(&mut self, head: u16) {
+        let outstanding = self.tail.wrapping_sub(head);
+        self.unreaped = (self.provided() - self.unpublished()).saturating_sub(outstanding);
+    }
+
+    /// Hands ownership of the ring to foreign code as an opaque pointer.
+    ///
+    /// The ring isn't dropped; it must be reclaimed with [`BufRing::from_raw`].
+    pub fn into_raw(self) -> *mut BufRingRaw {
+        Box::into_raw(Box::new(BufRingRaw { ring: self }))
+    }
+
+    /// Reclaims a ring previously released with [`BufRing::into_raw`].
+    ///
     /// # Safety
     ///
-    /// The caller must ensure that the buf ring is registered
-    unsafe fn unregister_(
-        self,
-        submitter: &io_uring::Submitter<'_>,
-    ) -> Result<BufRing<state::Uninit>, (std::io::Error, Self)> {
-        if let Err(e) = submitter.unregister_buf_ring(self.bgid()) {
-            return Err((e, self));
+    /// `ptr` must come from `into_raw` and must not be used again afterwards.
+    pub unsafe fn from_raw(ptr: *mut BufRingRaw) -> Self {
+        unsafe { Box::from_raw(ptr) }.ring
+    }
+
+    pub(crate) fn set_single_producer(&mut self, single_producer: bool) {
+        self.single_producer = single_producer;
+    }
+
+    /// Lets several threads return buffers to the kernel at once.
+    ///
+    /// Anything written but not yet published is published first. The ring
+    /// stays borrowed until the recycler is dropped, at which point it adopts
+    /// everything the recycler published.
+    pub fn concurrent_recycler(&mut self) -> ConcurrentRecycler<'_> {
+        unsafe { self.flush_pending() };
+        ConcurrentRecycler::new(self)
+    }
+
+    /// Provides `buf_id` advertising only its first `len` bytes, e.g. to cap how
+    /// much a single recv can consume.
+    ///
+    /// The reduction only lasts for this provision: once the buffer comes back
+    /// through a completion it is recycled at its full size again. If the
+    /// provision limit or the LIFO depth is reached the buffer is parked
+    /// instead, and provided at its full size once there is room.
+    pub fn provide_with_len(&mut self, buf_id: u16, len: u32) -> Result<(), RingError> {
+        if len == 0 || len > self.buf_size {
+            return Err(RingError::InvalidLen {
+                len,
+                capacity: self.buf_size,
+            });
+        }
+        match self.bids.get(buf_id as usize) {
+            Some(BidState::HeldByApp) => {}
+            Some(BidState::Parked) => self.unpark(buf_id),
+            _ => return Err(RingError::NotOwned { buf_id }),
         }
 
-        // SAFETY: same type layout
-        Ok(unsafe { core::mem::transmute(self) })
+        // SAFETY: the application owns `buf_id`, and an initialized ring is registered
+        if !unsafe { self.push_or_park(buf_id, len) } {
+            return Ok(());
+        }
+        if self.short_lens.is_empty() {
+            self.short_lens = vec![0; self.entries as usize].into_boxed_slice();
+        }
+        self.short_lens[buf_id as usize] = if len == self.buf_size { 0 } else { len };
+        unsafe { self.publish_unless_deferred() };
+        Ok(())
+    }
+
+    /// Returns detached buffers to the kernel with a single publication.
+    ///
+    /// Returns how many were taken back, including those parked past the
+    /// provision limit. Every buffer is checked before any entry is written:
+    /// either all buffers are returned, or none are and the error hands every
+    /// one of them back, in the order given, still held by the application.
+    pub fn recycle_many(
+        &mut self,
+        bufs: impl IntoIterator<Item = OwnedBuffer>,
+    ) -> Result<u16, (RecycleError, Vec<OwnedBuffer>)> {
+        let bufs: Vec<_> = bufs.into_iter().collect();
+        if let Err(e) = self.check_recyclable_all(&bufs) {
+            return Err((e, bufs));
+        }
+        if bufs.is_empty() {
+            return Ok(0);
+        }
+
+        #[cfg(feature = "stats")]
+        {
+            self.stats.recycled += bufs.len() as u64;
+        }
+        for buf in &bufs {
+            self.observe(|o| o.on_recycle(buf.buf_id()));
+            // SAFETY: checked above
+            unsafe { self.push_or_park(buf.buf_id(), self.buf_size) };
+        }
+        // SAFETY: an initialized ring is registered
+        unsafe { self.publish_unless_deferred() };
+        Ok(bufs.len() as u16)
+    }
+
+    /// Whether [`recycle_many`](Self::recycle_many) would take `buf` back.
+    pub fn check_recyclable(&self, buf: &OwnedBuffer) -> Result<(), RecycleError> {
+        let buf_id = buf.buf_id();
+        if buf.bgid() != self.bgid {
+            Err(RecycleError::WrongRing { bgid: buf.bgid() })
+        } else if buf_id >= self.entries() || self.bid_state(buf_id) != BidState::HeldByApp {
+            Err(RecycleError::NotHeld { buf_id })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks every buffer, including for ids appearing twice.
+    fn check_recyclable_all(&self, bufs: &[OwnedBuffer]) -> Result<(), RecycleError> {
+        for buf in bufs {
+            self.check_recyclable(buf)?;
+        }
+        let mut ids: Vec<_> = bufs.iter().map(OwnedBuffer::buf_id).collect();
+        ids.sort_unstable();
+        match ids.windows(2).find(|w| w[0] == w[1]) {
+            Some(w) => Err(RecycleError::NotHeld { buf_id: w[0] }),
+            None => Ok(()),
+        }
+    }
+
+    /// Takes a parked buffer to fill before providing it.
+    ///
+    /// Returns `None` if no buffer is parked, see
+    /// [`set_provision_limit`](Self::set_provision_limit).
+    pub fn take_free(&mut self) -> Option<FreeBuffer<'_>> {
+        let stacked = self.lifo.as_mut().and_then(|lifo| lifo.stack.pop());
+        let buf_id = stacked.or_else(|| self.parked.pop())?;
+        self.set_bid_state(buf_id, BidState::HeldByApp);
+        Some(FreeBuffer::new(self, buf_id, BidState::Parked))
+    }
+
+    /// Sets a parked buffer aside, e.g. for a send path to fill, so that
+    /// nothing provides it until [`unreserve`](Self::unreserve) parks it again.
+    pub fn reserve(&mut self, buf_id: u16) -> Result<(), RingError> {
+        if self.bids.get(buf_id as usize) != Some(&BidState::Parked) {
+            return Err(RingError::NotOwned { buf_id });
+        }
+        self.unpark(buf_id);
+        self.set_bid_state(buf_id, BidState::Reserved);
+        Ok(())
+    }
+
+    /// Parks a buffer set aside with [`reserve`](Self::reserve).
+    pub fn unreserve(&mut self, buf_id: u16) -> Result<(), RingError> {
+        if self.bids.get(buf_id as usize) != Some(&BidState::Reserved) {
+            return Err(RingError::NotOwned { buf_id });
+        }
+        self.park(buf_id);
+        Ok(())
+    }
+
+    /// Takes a buffer held by the application or parked out of rotation, e.g.
+    /// one whose contents are suspect, until
+    /// [`lift_quarantine`](Self::lift_quarantine) parks it again.
+    pub fn quarantine(&mut self, buf_id: u16) -> Result<(), RingError> {
+        match self.bids.get(buf_id as usize) {
+            Some(BidState::HeldByApp) => {}
+            Some(BidState::Parked) => self.unpark(buf_id),
+            _ => return Err(RingError::NotOwned { buf_id }),
+        }
+        self.set_bid_state(buf_id, BidState::Quarantined);
+        Ok(())
+    }
+
+    /// Parks a buffer taken out of rotation with [`quarantine`](Self::quarantine).
+    pub fn lift_quarantine(&mut self, buf_id: u16) -> Result<(), RingError> {
+        if self.bids.get(buf_id as usize) != Some(&BidState::Quarantined) {
+            return Err(RingError::NotOwned { buf_id });
+        }
+        self.park(buf_id);
+        Ok(())
+    }
+
+    /// A buffer held by the application, e.g. one kept with
+    /// [`BufferId::keep`], to fill before providing it.
+    ///
+    /// Dropping the handle without providing leaves the buffer held.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `buf_id` is < `self.entries()`, that it is held
+    /// by the application and that nothing else returns it to the kernel.
+    pub unsafe fn free_buffer(&mut self, buf_id: u16) -> FreeBuffer<'_> {
+        debug_assert_eq!(self.bid_state(buf_id), BidState::HeldByApp);
+        FreeBuffer::new(self, buf_id, BidState::HeldByApp)
+    }
+
+    /// Writes the entry for `buf_id` into `slot & mask` without advancing the tail.
+    ///
+    /// Slots may be written in any order. Only the run of written slots
+    /// starting at the tail can be published with [`advance`](Self::advance),
+    /// a slot written past a gap waits until the gap is filled. Rejects a
+    /// `buf_id` the application doesn't own and a slot that was already
+    /// written since the last publication. Being an explicit placement, it
+    /// ignores the provision limit.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `slot` is not currently published to the kernel.
+    pub unsafe fn provide_buffer_at(&mut self, slot: u16, buf_id: u16) -> Result<(), RingError> {
+        match self.bids.get(buf_id as usize) {
+            Some(BidState::HeldByApp) => {}
+            Some(BidState::Parked) => self.unpark(buf_id),
+            _ => return Err(RingError::NotOwned { buf_id }),
+        }
+        let offset = (slot.wrapping_sub(self.tail) as u32 & self.mask) as u16;
+        let masked = slot as u32 & self.mask;
+        if offset < self.pending || self.is_written_ahead(masked) {
+            return Err(RingError::SlotWritten { slot });
+        }
+
+        unsafe { self.write_entry(slot as u32, buf_id) }
+        self.set_bid_state(buf_id, BidState::Provided);
+        if offset == self.pending {
+            self.pending += 1;
+            self.absorb_written_ahead();
+        } else {
+            if self.written_ahead.is_empty() {
+                self.written_ahead = vec![0; self.entries.div_ceil(64) as usize].into_boxed_slice();
+            }
+            self.written_ahead[masked as usize / 64] |= 1 << (masked % 64);
+            self.ahead += 1;
+        }
+        Ok(())
+    }
+
+    /// Checks the entry of every provided buffer against the ring's own record
+    /// of it, e.g. after editing entries by hand.
+    ///
+    /// The slot a provided buffer was written into must still carry its id,
+    /// with `addr` inside the buffer, at its start unless the ring consumes
+    /// incrementally, and a `len` between 1 and the bytes left in the buffer,
+    /// so lengths reduced by [`provide_with_len`](Self::provide_with_len) pass.
+    /// No two provided buffers may claim the same slot.
+    pub fn verify(&self) -> Result<(), Vec<InvariantViolation>> {
+        let incremental = self.mode == RegistrationMode::Incremental;
+        let mut violations = Vec::new();
+        let mut claimed = vec![None; self.entries as usize];
+        for bid in 0..self.entries() {
+            if self.bid_state(bid) != BidState::Provided {
+                continue;
+            }
+            let slot = (self.slot_of[bid as usize] as u32 & self.mask) as u16;
+            if let Some(first) = claimed[slot as usize].replace(bid) {
+                violations.push(InvariantViolation::SharedSlot {
+                    slot,
+                    first,
+                    second: bid,
+                });
+                continue;
+            }
+
+            // SAFETY: masked into the entry array
+            let entry = unsafe { &*self.entry_ptr(slot as u32) };
+            let found = entry.bid();
+            if found >= self.entries() {
+                violations.push(InvariantViolation::BidOutOfRange { slot, bid: found });
+                continue;
+            }
+            if found != bid {
+                violations.push(InvariantViolation::WrongBid {
+                    slot,
+                    expected: bid,
+                    found,
+                });
+                continue;
+            }
+
+            // SAFETY: `bid` is in range
+            let start = unsafe { self.get_buffer(bid) } as u64;
+            let end = start + self.buf_size as u64;
+            let addr = entry.addr();
+            if !(start..end).contains(&addr) || (!incremental && addr != start) {
+                violations.push(InvariantViolation::BadAddr { slot, bid, addr });
+                continue;
+            }
+            let (len, capacity) = (entry.len(), (end - addr) as u32);
+            if len == 0 || len > capacity {
+                violations.push(InvariantViolation::BadLen {
+                    slot,
+                    bid,
+                    len,
+                    capacity,
+                });
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
     }
 }
 
-impl<S> Drop for BufRing<S> {
-    fn drop(&mut self) {
-        let buf_ring_size =
-            self.entries as usize * (self.buf_size as usize + core::mem::size_of::<BufRingEntry>());
+use crate::budget::ProcessBudget;
+use crate::buffer_id::BufferId;
+use crate::bundle::Bundle;
+use crate::concurrent::ConcurrentRecycler;
+use crate::cqe::CqeFields;
+use crate::free_buffer::FreeBuffer;
+use crate::owned_buffer::{OwnedBuffer, RecycleError};
+
+impl<S> BufRing<S> {
+    /// Writes `buf_id` into the next free slot without publishing it.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `buf_id` < `self.entries()` and that it is
+    /// not currently owned by the kernel
+    #[inline]
+    pub(crate) unsafe fn push(&mut self, buf_id: u16) {
+        debug_assert!((self.pending as u32) < self.entries, "buf ring overfilled");
         unsafe {
-            libc::munmap(self.base.cast(), buf_ring_size);
+            let slot = self.tail.wrapping_add(self.pending) as u32;
+            if self.static_layout {
+                self.write_entry_static(slot, buf_id);
+            } else {
+                self.write_entry(slot, buf_id);
+            }
         }
+        self.pending += 1;
+        self.absorb_written_ahead();
+        self.set_bid_state(buf_id, BidState::Provided);
+    }
+
+    /// Like `push`, with an entry `len` other than `buf_size`.
+    ///
+    /// # Safety
+    ///
+    /// Same as `push`, and `len` must be <= `self.buf_size()`
+    pub(crate) unsafe fn push_with_len(&mut self, buf_id: u16, len: u32) {
+        debug_assert!((self.pending as u32) < self.entries, "buf ring overfilled");
+        debug_assert!(len <= self.buf_size);
+        let slot = self.tail.wrapping_add(self.pending) as u32;
+        unsafe { self.write_entry_len(slot, buf_id, len) };
+        self.pending += 1;
+        self.absorb_written_ahead();
+        self.set_bid_state(buf_id, BidState::Provided);
+    }
+
+    fn is_written_ahead(&self, masked: u32) -> bool {
+        self.ahead > 0 && self.written_ahead[masked as usize / 64] & 1 << (masked % 64) != 0
+    }
+
+    /// Extends `pending` over slots `provide_buffer_at` wrote ahead of it,
+    /// so the next free slot is never one already written.
+    #[inline]
+    fn absorb_written_ahead(&mut self) {
+        loop {
+            let next = self.tail.wrapping_add(self.pending) as u32 & self.mask;
+            if !self.is_written_ahead(next) {
+                return;
+            }
+            self.written_ahead[next as usize / 64] &= !(1 << (next % 64));
+            self.ahead -= 1;
+            self.pending += 1;
+        }
+    }
+
+    /// Publishes everything pushed, unless deferred advance holds it back.
+    ///
+    /// # Safety
+    ///
+    /// This function should not be called before the buf ring is registered
+    #[inline]
+    pub(crate) unsafe fn publish_unless_deferred(&mut self) {
+        if !self.deferred || self.pending > self.entries() / 2 {
+            unsafe { self.publish(self.pending) }
+        }
+    }
+
+    /// Publishes `count` slots previously written with `push`.
+    ///
+    /// # Safety
+    ///
+    /// This function should not be called before the buf ring is registered
+    #[inline]
+    pub(crate) unsafe fn publish(&mut self, count: u16) {
+        debug_assert!(count <= self.pending, "publishing unwritten slots");
+        #[cfg(feature = "stats")]
+        {
+            self.stats.batches += 1;
+        }
+        self.observe(|o| o.on_provide_batch(count));
+        #[cfg(feature = "metrics")]
+        {
+            let provided = self.provided();
+            if let Some(metrics) = &mut self.metrics {
+                metrics.published(provided);
+            }
+        }
+        unsafe { self.advance_(count) }
+    }
+
+    /// Returns `buf_id` to the kernel.
+    ///
+    /// With deferred advance enabled the entry is only written, and published
+    /// later by `flush` or once more than half the ring is waiting.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `buf_id` < `self.entries()`, that it is held by
+    /// the application and that the buf ring is registered
+    #[inline]
+    pub(crate) unsafe fn recycle(&mut self, buf_id: u16) {
+        #[cfg(feature = "stats")]
+        {
+            self.stats.recycled += 1;
+        }
+        self.observe(|o| o.on_recycle(buf_id));
+        #[cfg(feature = "tracing")]
+        tracing::trace!(bgid = self.bgid, bid = buf_id, "recycle");
+        unsafe {
+            if self.push_or_park(buf_id, self.buf_size) {
+                self.publish_unless_deferred();
+            }
+        }
+    }
+
+    /// Writes `buf_id` into the next free slot with an entry `len`, unless the
+    /// provision limit is reached, in which case it is parked. Returns whether
+    /// it was written.
+    ///
+    /// Every path providing buffers after `init` goes through here.
+    ///
+    /// # Safety
+    ///
+    /// Same as `push_with_len`
+    #[inline]
+    pub(crate) unsafe fn push_or_park(&mut self, buf_id: u16, len: u32) -> bool {
+        if self.provision_limit.is_some_and(|limit| self.provided() >= limit) {
+            self.park(buf_id);
+            return false;
+        }
+        if let Some(lifo) = &mut self.lifo
+            && self.counts[BidState::Provided as usize] >= lifo.depth
+        {
+            lifo.stack.push(buf_id);
+            self.set_bid_state(buf_id, BidState::Parked);
+            return false;
+        }
+        unsafe {
+            if len == self.buf_size {
+                self.push(buf_id);
+            } else {
+                self.push_with_len(buf_id, len);
+            }
+        }
+        true
+    }
+
+    #[inline]
+    pub(crate) fn park(&mut self, buf_id: u16) {
+        self.set_bid_state(buf_id, BidState::Parked);
+        self.parked.push(buf_id);
+    }
+
+    /// Provides buffers off the LIFO stack, then parked ones, most recent
+    /// first, until `limit` buffers are provided or none are left.
+    ///
+    /// # Safety
+    ///
+    /// This function should not be called before the buf ring is registered
+    pub(crate) unsafe fn provide_parked(&mut self, limit: u16) {
+        while self.provided() < limit
+            && let Some(buf_id) = self.lifo.as_mut().and_then(|lifo| lifo.stack.pop())
+        {
+            unsafe { self.push(buf_id) };
+        }
+        while self.provided() < limit
+            && let Some(buf_id) = self.parked.pop()
+        {
+            unsafe { self.push(buf_id) };
+        }
+        unsafe { self.flush_pending() }
+    }
+
+    /// How many buffers may be provided at once, under both the provision
+    /// limit and the LIFO depth.
+    fn provision_cap(&self) -> u16 {
+        let limit = self.provision_limit.unwrap_or(self.entries());
+        self.lifo.as_ref().map_or(limit, |lifo| limit.min(lifo.depth))
+    }
+
+    /// Tops the provided buffers back up to the provision limit and LIFO
+    /// depth after one was taken.
+    ///
+    /// # Safety
+    ///
+    /// This function should not be called before the buf ring is registered
+    #[inline]
+    pub(crate) unsafe fn top_up(&mut self) {
+        let stacked = self.lifo.as_ref().is_some_and(|lifo| !lifo.stack.is_empty());
+        if (self.provision_limit.is_some() && !self.parked.is_empty()) || stacked {
+            unsafe { self.provide_parked(self.provision_cap()) }
+        }
+    }
+
+    /// Returns every id in `buf_ids` to the kernel with a single publication,
+    /// parking those past the provision limit.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that every id is < `self.entries()`, held by the
+    /// application, appears only once, and that the buf ring is registered
+    pub(crate) unsafe fn recycle_batch(&mut self, buf_ids: &[u16]) {
+        if buf_ids.is_empty() {
+            return;
+        }
+        #[cfg(feature = "stats")]
+        {
+            self.stats.recycled += buf_ids.len() as u64;
+        }
+        for &buf_id in buf_ids {
+            self.observe(|o| o.on_recycle(buf_id));
+        }
+        unsafe {
+            for &buf_id in buf_ids {
+                self.push_or_park(buf_id, self.buf_size);
+            }
+            self.flush_pending();
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The caller must ensure that `buf_id` < `self.entries()`
+    #[inline]
+    unsafe fn write_entry(&mut self, slot: u32, buf_id: u16) {
+        unsafe { self.write_entry_len(slot, buf_id, self.buf_size) }
+    }
+
+    /// # Safety
+    ///
+    /// The caller must ensure that `buf_id` < `self.entries()`
+    #[inline]
+    unsafe fn write_entry_len(&mut self, slot: u32, buf_id: u16, len: u32) {
+        let (entry, buffer_addr) = unsafe { (&mut *self.entry_ptr(slot), self.get_buffer(buf_id)) };
+        entry.set_addr(buffer_addr as u64);
+        entry.set_len(len);
+        entry.set_bid(buf_id);
+        self.slot_of[buf_id as usize] = slot as u16;
     }
-}
 
+    /// Like `write_entry`, for rings where every slot already holds a full
+    /// entry: only a slot holding a different buffer id needs its `addr` and
+    /// `bid` rewritten, and `len` only if a shorter entry was written there.
+    ///
+    /// # Safety
+    ///
+    /// Same as `write_entry`, and `self.static_layout` must hold
+    #[inline]
+    unsafe fn write_entry_static(&mut self, slot: u32, buf_id: u16) {
+        let entry = unsafe { &mut *self.entry_ptr(slot) };
+        if entry.bid() != buf_id {
+            entry.set_addr(unsafe { self.get_buffer(buf_id) } as u64);
+            entry.set_bid(buf_id);
+        }
+        if entry.len() != self.buf_size {
+            entry.set_len(self.buf_size);
+        }
+        self.slot_of[buf_id as usize] = slot as u16;
+    }
+
+    /// The unmasked slot `buf_id` was last written into.
+    #[inline]
+    pub(crate) fn slot_of(&self, buf_id: u16) -> u16 {
+        self.slot_of[buf_id as usize]
+    }
+
+    #[inline]
+    pub(crate) fn bundle_scratch(&mut self) -> &mut Vec<u8> {
+        &mut self.bundle_scratch
+    }
+
+    #[inline]
+    pub(crate) fn bundle_scratch_ref(&self) -> &[u8] {
+        &self.bundle_scratch
+    }
+
+    #[inline]
+    pub(crate) fn entry_ptr(&self, slot: u32) -> *mut BufRingEntry {
+        // SAFETY: masking keeps the offset inside the entry array
+        unsafe { self.base.offset((slot & self.mask) as isize) }
+    }
+
+    /// Publishes anything written with `push` that hasn't been published yet.
+    ///
+    /// # Safety
+    ///
+    /// This function should not be called before the buf ring is registered
+    pub(crate) unsafe fn flush_pending(&mut self) {
+        if self.pending > 0 {
+            unsafe { self.publish(self.pending) }
+        }
+    }
+
+    /// Takes over entries published by someone else up to `new_tail`,
+    /// marking the buffer ids they carry as provided.
+    pub(crate) fn adopt_published(&mut self, new_tail: u16) {
+        debug_assert_eq!(self.pending, 0);
+        let count = new_tail.wrapping_sub(self.tail);
+        for i in 0..count {
+            let slot = self.tail.wrapping_add(i);
+            // SAFETY: every slot up to `new_tail` was written before it was published
+            let buf_id = unsafe { (*self.entry_ptr(slot as u32)).bid() };
+            self.slot_of[buf_id as usize] = slot;
+            self.set_bid_state(buf_id, BidState::Provided);
+            self.observe(|o| o.on_recycle(buf_id));
+        }
+        #[cfg(feature = "stats")]
+        {
+            self.stats.recycled += count as u64;
+        }
+        self.tail = new_tail;
+    }
+
+    /// # Safety
+    ///
+    /// The caller must ensure `buf_id` < `self.entries()`
+    #[inline]
+    pub(crate) unsafe fn get_buffer(&self, buf_id: u16) -> *const u8 {
+        debug_assert!((buf_id as u32) < self.entries, "buffer id out of range");
+        // in `usize`, the product can exceed `u32::MAX` for large pools
+        unsafe { self.buffer_base.add(buf_id as usize * self.buf_size as usize) }
+    }
+
+    /// Publishes `count` written entries to the kernel.
+    ///
+    /// The ring is single producer (this process) single consumer (the kernel).
+    /// Entries are written with plain stores, so the tail must be published
+    /// with `Release` ordering: once the kernel observes the new tail, every
+    /// entry write made before it is guaranteed to be visible as well. Without
+    /// it a weakly ordered cpu may let the kernel read a stale `addr`/`len`/`bid`.
+    ///
+    /// # Safety
+    ///
+    /// This function should not be called before the buf ring is registered
+    ///
+    /// With a single producer the shared tail always equals `self.tail`, so it
+    /// is published with a plain store.
+    #[inline]
+    pub(crate) unsafe fn advance_(&mut self, count: u16) {
+        self.tail = self.tail.wrapping_add(count);
+        self.pending = self.pending.saturating_sub(count);
+        if self.single_producer {
+            self.shared_tail().store(self.tail, Ordering::Release);
+        } else {
+            let _ = self.shared_tail().fetch_add(count, Ordering::Release);
+        }
+    }
+
+    /// Resets the tail to 0.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the kernel isn't consuming the ring, i.e.
+    /// that it isn't registered
+    pub unsafe fn init_(&mut self) {
+        self.tail = 0;
+        self.pending = 0;
+        self.written_ahead.fill(0);
+        self.ahead = 0;
+        self.shared_tail().store(0, Ordering::Release);
+    }
+
+    /// The atomic the tail is published through, the same location the kernel reads.
+    ///
+    /// The tail lives at a fixed offset inside the first entry for both
+    /// userspace allocated rings and rings mapped from the kernel with
+    /// `IOU_PBUF_RING_MMAP`, so it is always derived from the ring's base address.
+    pub fn tail_ptr(&self) -> *const AtomicU16 {
+        self.shared_tail
+    }
+
+    #[inline]
+    pub(crate) fn shared_tail(&self) -> &SharedTail {
+        #[cfg(loom)]
+        return &self.loom_tail;
+        // SAFETY: `shared_tail` points into the mapping, which lives as long as `self`
+        #[cfg(not(loom))]
+        unsafe {
+            &*self.shared_tail
+        }
+    }
+
+    pub fn entries(&self) -> u16 {
+        self.entries as u16
+    }
+
+    /// Buffers held by the application for longer than `max_age`.
+    #[cfg(feature = "watchdog")]
+    pub fn check_stale(&self, max_age: std::time::Duration) -> Vec<crate::StaleReport> {
+        self.watchdog.stale(max_age)
+    }
+
+    #[cfg(feature = "watchdog")]
+    pub(crate) fn watchdog_mut(&mut self) -> &mut crate::watchdog::Watchdog {
+        &mut self.watchdog
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> crate::Stats {
+        self.stats
+    }
+
+    /// Completion sizes seen since the last [`reset_stats`](Self::reset_stats),
+    /// see [`SIZE_BUCKETS`](crate::stats::SIZE_BUCKETS) for the bucket bounds.
+    #[cfg(feature = "stats")]
+    pub fn size_histogram(&self) -> [u64; crate::stats::SIZE_BUCKETS] {
+        self.stats.size_histogram
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.stats = Default::default();
+    }
+
+    #[cfg(feature = "stats")]
+    pub(crate) fn stats_mut(&mut self) -> &mut crate::Stats {
+        &mut self.stats
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn metrics(&self) -> Option<&crate::ring_metrics::RingMetrics> {
+        self.metrics.as_ref()
+    }
+
+    /// Number of buffers written into the ring but not yet published,
+    /// including those written past a gap by `provide_buffer_at`.
+    pub fn unpublished(&self) -> u16 {
+        self.pending + self.ahead
+    }
+
+    /// Slots left before the next entry written wraps to the start of the
+    /// ring, for providing a contiguous run of buffers without splitting it.
+    ///
+    /// Counts from the first unwritten slot, after any unpublished entries.
+    pub fn slots_until_wrap(&self) -> u16 {
+        let next = self.tail.wrapping_add(self.pending) as u32;
+        (self.entries - (next & self.mask)) as u16
+    }
+
+    /// Number of buffers currently provided to the kernel, including unpublished ones.
+    pub fn provided(&self) -> u16 {
+        self.counts[BidState::Provided as usize]
+    }
+
+    /// Number of buffers returned by completions that haven't been recycled yet.
+    pub fn held_by_app(&self) -> u16 {
+        self.counts[BidState::HeldByApp as usize]
+    }
+
+    /// Number of buffers parked under the provision limit or on the LIFO
+    /// stack, waiting to be provided.
+    pub fn parked(&self) -> u16 {
+        let stacked = self.lifo.as_ref().map_or(0, |lifo| lifo.stack.len());
+        (self.parked.len() + stacked) as u16
+    }
+
+    /// Number of buffers owned by neither the kernel nor the application.
+    pub fn unprovided(&self) -> u16 {
+        self.counts[BidState::Parked as usize]
+    }
+
+    /// Number of buffers set aside with [`reserve`](BufRing::reserve).
+    pub fn reserved(&self) -> u16 {
+        self.counts[BidState::Reserved as usize]
+    }
+
+    /// Number of buffers taken out of rotation with [`quarantine`](BufRing::quarantine).
+    pub fn quarantined(&self) -> u16 {
+        self.counts[BidState::Quarantined as usize]
+    }
+
+    /// Installs hooks called as buffers are acquired, recycled and provided.
+    pub fn set_observer(&mut self, observer: Box<dyn RingObserver>) {
+        self.observer = Some(observer);
+    }
+
+    pub fn take_observer(&mut self) -> Option<Box<dyn RingObserver>> {
+        self.observer.take()
+    }
+
+    #[inline]
+    pub(crate) fn observe(&mut self, f: impl FnOnce(&mut dyn RingObserver)) {
+        if let Some(observer) = &mut self.observer {
+            f(observer.as_mut());
+        }
+    }
+
+    /// Calls `callback` with the provided count whenever it drops below `n`.
+    ///
+    /// After firing, the callback is re-armed only once the count recovers to
+    /// `n + entries / 16`. The callback only sees the count, it can't touch the ring.
+    pub fn set_low_watermark(&mut self, n: u16, callback: impl FnMut(u16) + Send + 'static) {
+        self.watermark = Some(LowWatermark::new(
+            n,
+            self.entries(),
+            self.provided(),
+            Box::new(callback),
+        ));
+    }
+
+    /// Signals `eventfd` whenever the provided count drops below `watermark`,
+    /// e.g. to wake a control plane polling it with epoll.
+    ///
+    /// Built on [`set_low_watermark`](Self::set_low_watermark), which it
+    /// replaces, with the same hysteresis. Each crossing adds 1 to the eventfd
+    /// counter; a failed write (e.g. the counter is saturated) is dropped, since
+    /// the reader is already due a wakeup. Make the eventfd `EFD_NONBLOCK` so a
+    /// saturated counter can't block the completion path.
+    ///
+    /// # Safety
+    ///
+    /// `eventfd` must stay open until the watermark is cleared or replaced, or
+    /// the ring is dropped.
+    pub unsafe fn notify_low_buffers(&mut self, eventfd: BorrowedFd<'_>, watermark: u16) {
+        let fd = eventfd.as_raw_fd();
+        self.set_low_watermark(watermark, move |_| {
+            let one = 1u64;
+            // SAFETY: writes 8 bytes from `one`, and the caller keeps `fd` open
+            let _ = unsafe { libc::write(fd, (&raw const one).cast(), 8) };
+        });
+    }
+
+    pub fn clear_low_watermark(&mut self) {
+        self.watermark = None;
+    }
+
+    /// Whether the provided count is currently below the low watermark.
+    pub fn is_below_watermark(&self) -> bool {
+        self.watermark.as_ref().is_some_and(LowWatermark::is_below)
+    }
+
+    /// Attaches a `T` to every buffer id, replacing any previous table.
+    ///
+    /// A buffer's slot is reset to `T::default()` whenever it is recycled, so
+    /// metadata never outlives the use it was written for.
+    pub fn with_metadata<T: Default + Send + 'static>(&mut self) {
+        self.metadata = Some(Box::new(MetadataTable::<T>::new(self.entries())));
+    }
+
+    /// The table attached with `with_metadata::<T>()`.
+    pub fn metadata<T: 'static>(&self) -> Option<&MetadataTable<T>> {
+        self.metadata.as_ref()?.as_any().downcast_ref()
+    }
+
+    pub fn metadata_mut<T: 'static>(&mut self) -> Option<&mut MetadataTable<T>> {
+        self.metadata.as_mut()?.as_any_mut().downcast_mut()
+    }
+
+    pub fn meta<T: 'static>(&self, buf_id: u16) -> Option<&T> {
+        self.metadata::<T>()?.get(buf_id)
+    }
+
+    pub fn meta_mut<T: 'static>(&mut self, buf_id: u16) -> Option<&mut T> {
+        self.metadata_mut::<T>()?.get_mut(buf_id)
+    }
+
+    /// Who currently owns `buf_id`, read from the ring's state table.
+    ///
+    /// Buffers written into the ring but not yet published already count as
+    /// provided.
+    ///
+    /// # Panics
+    ///
+    /// If `buf_id` >= `self.entries()`.
+    #[inline]
+    pub fn bid_state(&self, buf_id: u16) -> BidState {
+        self.bids[buf_id as usize]
+    }
+
+    /// The state of every buffer id, in id order.
+    pub fn bid_states(&self) -> impl Iterator<Item = (u16, BidState)> + '_ {
+        self.bids
+            .iter()
+            .enumerate()
+            .map(|(id, &state)| (id as u16, state))
+    }
+
+    #[inline]
+    pub(crate) fn set_bid_state(&mut self, buf_id: u16, state: BidState) {
+        let prev = core::mem::replace(&mut self.bids[buf_id as usize], state);
+        self.counts[prev as usize] -= 1;
+        self.counts[state as usize] += 1;
+
+        // collapsible only without the watchdog
+        #[allow(clippy::collapsible_if)]
+        if prev == BidState::HeldByApp && state != BidState::HeldByApp {
+            if let Some(metadata) = &mut self.metadata {
+                metadata.reset(buf_id);
+            }
+            #[cfg(feature = "watchdog")]
+            self.watchdog.release(buf_id);
+        }
+
+        if prev != state && (prev == BidState::Provided || state == BidState::Provided) {
+            let provided = self.provided();
+            if let Some(watermark) = &mut self.watermark {
+                watermark.update(provided);
+            }
+        }
+    }
+
+    fn reset_bid_states(&mut self) {
+        #[cfg(feature = "watchdog")]
+        {
+            self.watchdog = crate::watchdog::Watchdog::new(self.entries());
+        }
+        self.parked.clear();
+        if let Some(lifo) = &mut self.lifo {
+            lifo.stack.clear();
+        }
+        self.consumed.fill(0);
+        self.short_lens.fill(0);
+        self.unreaped = 0;
+        self.bids.fill(BidState::Parked);
+        self.counts = [0, 0, self.entries(), 0, 0];
+    }
+
+    /// Every buffer as one slice, without the entry array or any padding
+    /// before the buffers.
+    ///
+    /// Fails while any buffer is provided to the kernel.
+    pub fn pool(&self) -> Result<&[u8], RingError> {
+        self.check_unprovided()?;
+        // SAFETY: the kernel owns no buffer, so it doesn't write into the pool
+        Ok(unsafe { core::slice::from_raw_parts(self.buffer_base, self.pool_len()) })
+    }
+
+    /// Like [`pool`](Self::pool), for writing every buffer at once.
+    pub fn pool_mut(&mut self) -> Result<&mut [u8], RingError> {
+        self.check_unprovided()?;
+        let buffer_base = self.buffer_base as *mut u8;
+        // SAFETY: as in `pool`, and `self` is borrowed mutably
+        Ok(unsafe { core::slice::from_raw_parts_mut(buffer_base, self.pool_len()) })
+    }
+
+    /// The start and length in bytes of the region holding every buffer, e.g.
+    /// to register it elsewhere or checksum the whole pool.
+    ///
+    /// A pointer rather than a slice, since the kernel may be writing into
+    /// provided buffers at any time: a `&[u8]` over them would be undefined
+    /// behavior, while racy reads through the pointer at worst see torn data.
+    /// [`pool`](Self::pool) is the slice once nothing is provided.
+    pub fn data_region(&self) -> (*const u8, usize) {
+        (self.buffer_base, self.pool_len())
+    }
+
+    /// A copy of every slot of the entry array, as the kernel would read it.
+    ///
+    /// Only meant for debugging: the kernel may rewrite entries while they're
+    /// copied, e.g. in incremental mode.
+    pub fn snapshot_entries(&self) -> Vec<EntrySnapshot> {
+        let pool = self.buffer_base as u64..self.buffer_base as u64 + self.pool_len() as u64;
+        (0..self.entries)
+            .map(|slot| {
+                // SAFETY: every slot of the mapping is initialized, zeroed if
+                // it was never written
+                let entry = unsafe { &*self.entry_ptr(slot) };
+                EntrySnapshot {
+                    slot: slot as u16,
+                    offset: pool
+                        .contains(&entry.addr())
+                        .then(|| entry.addr() - pool.start),
+                    len: entry.len(),
+                    bid: entry.bid(),
+                }
+            })
+            .collect()
+    }
+
+    /// The tail as published to the kernel.
+    pub fn published_tail(&self) -> u16 {
+        self.shared_tail().load(Ordering::Acquire)
+    }
+
+    /// Writes [`snapshot_entries`](Self::snapshot_entries) and the published
+    /// tail as a table.
+    pub fn write_dump(&self, mut out: impl core::fmt::Write) -> core::fmt::Result {
+        let (bgid, tail) = (self.bgid, self.published_tail());
+        let slot = tail as u32 & self.mask;
+        writeln!(out, "bgid {bgid} tail {tail} (slot {slot})")?;
+        writeln!(out, " slot       offset        len   bid")?;
+        for e in self.snapshot_entries() {
+            let offset = e.offset.map_or_else(|| "-".to_owned(), |o| o.to_string());
+            writeln!(out, "{:>5} {offset:>12} {:>10} {:>5}", e.slot, e.len, e.bid)?;
+        }
+        Ok(())
+    }
+
+    /// Provides every parked buffer `len_of` gives a length for, in id order,
+    /// with a single publication, and returns how many were provided.
+    ///
+    /// Fails while any buffer is provided, leaving everything parked.
+    pub(crate) fn reprovide_parked(
+        &mut self,
+        len_of: impl Fn(u16) -> Option<u32>,
+    ) -> Result<u16, RingError> {
+        self.check_unprovided()?;
+        let mut parked = core::mem::take(&mut self.parked);
+        if let Some(lifo) = &mut self.lifo {
+            parked.append(&mut lifo.stack);
+        }
+        parked.sort_unstable();
+        let before = self.pending;
+        for buf_id in parked {
+            match len_of(buf_id) {
+                // SAFETY: parked ids are owned by neither side
+                Some(len) => unsafe {
+                    self.push_or_park(buf_id, len);
+                },
+                None => self.parked.push(buf_id),
+            }
+        }
+        let count = self.pending - before;
+        // SAFETY: an initialized ring is registered
+        unsafe { self.flush_pending() };
+        Ok(count)
+    }
+
+    fn check_unprovided(&self) -> Result<(), RingError> {
+        match self.provided() {
+            0 => Ok(()),
+            count => Err(RingError::Provided { count }),
+        }
+    }
+
+    #[inline]
+    fn pool_len(&self) -> usize {
+        self.entries as usize * self.buf_size as usize
+    }
+
+    pub fn map_opts(&self) -> MapOpts {
+        self.opts
+    }
+
+    pub fn config(&self) -> BufRingConfig {
+        BufRingConfig {
+            entries: self.entries(),
+            buf_size: self.buf_size,
+            opts: self.opts,
+        }
+    }
+
+    pub fn registration_mode(&self) -> RegistrationMode {
+        self.mode
+    }
+
+    pub fn registration_info(&self) -> RegistrationInfo {
+        RegistrationInfo {
+            mode: self.mode,
+            mmap_offset: None,
+        }
+    }
+
+    /// Records `len` bytes received into `buf_id` and returns the offset they
+    /// start at, which is always 0 unless the ring consumes incrementally.
+    ///
+    /// `more` is whether the kernel keeps the buffer, it is only ever set
+    /// in incremental mode.
+    pub(crate) fn consume(&mut self, buf_id: u16, len: u32, more: bool) -> u32 {
+        if !more {
+            self.unreaped = self.unreaped.saturating_sub(1);
+            // the next provision of `buf_id` is a full one again
+            if let Some(short) = self.short_lens.get_mut(buf_id as usize) {
+                debug_assert!(*short == 0 || len <= *short, "kernel wrote past the entry");
+                *short = 0;
+            }
+        }
+        let Some(consumed) = self.consumed.get_mut(buf_id as usize) else {
+            return 0;
+        };
+        let offset = *consumed;
+        *consumed = if more { offset + len } else { 0 };
+        offset
+    }
+
+    /// Length of the mapping backing the entries and buffers.
+    pub fn mmap_size(&self) -> usize {
+        ring_size(self.entries, self.buf_size, self.opts.buffer_align)
+    }
+
+    pub fn buf_size(&self) -> u32 {
+        self.buf_size
+    }
+
+    pub fn ring_addr(&self) -> u64 {
+        self.base as u64
+    }
+
+    pub fn bgid(&self) -> u16 {
+        self.bgid
+    }
+
+    /// Best-effort check that the ring is consistent with what the kernel
+    /// registered, e.g. after rebuilding one from its parts.
+    ///
+    /// Nothing is registered or unregistered. The ring's own geometry is
+    /// always checked. For a registered ring the kernel's head for its buffer
+    /// group is read with `IORING_REGISTER_PBUF_STATUS`, which fails unless the
+    /// group is a registered ring, and must trail the published tail by at most
+    /// `entries`. The kernel doesn't report a ring's address or size, so
+    /// another ring registered under the same bgid can still pass. Kernels
+    /// before 6.8 can't report the head, and only get the geometry check.
+    pub fn validate<Sq, Cq>(&self, uring: &io_uring::IoUring<Sq, Cq>) -> std::io::Result<()>
+    where
+        Sq: io_uring::squeue::EntryMarker,
+        Cq: io_uring::cqueue::EntryMarker,
+    {
+        use std::io::{Error, ErrorKind};
+
+        if !self.entries.is_power_of_two() || self.mask != self.entries - 1 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "entries isn't a power of two",
+            ));
+        }
+        // SAFETY: `base` points to the ring's mapping
+        if self.shared_tail != unsafe { tail_atomic(self.base) } {
+            return Err(Error::new(ErrorKind::InvalidData, "tail outside the ring"));
+        }
+        if self.lifecycle == Lifecycle::Uninit {
+            return Ok(());
+        }
+
+        let head = match pbuf_status(uring.as_raw_fd(), self.bgid) {
+            Ok(head) => head,
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    "buffer group isn't registered",
+                ));
+            }
+            // also what kernels without the opcode return
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) && !kernel_at_least(6, 8) => {
+                return Ok(());
+            }
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "buffer group isn't a buffer ring",
+                ));
+            }
+            Err(e) => return Err(e),
+        };
+        if self.published_tail().wrapping_sub(head) as u32 > self.entries {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "kernel head is more than `entries` behind the tail",
+            ));
+        }
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// The caller must ensure that this `BufRing` is already initialized
+    pub unsafe fn tail(&self) -> u32 {
+        self.shared_tail().load(Ordering::Acquire) as u32
+    }
+
+    /// # Safety
+    ///
+    /// The caller must ensure that the buf ring is registered
+    unsafe fn unregister_(
+        mut self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<BufRing<state::Uninit>, (std::io::Error, Self)> {
+        if let Err(e) = self.unregister_in_place(submitter) {
+            return Err((e, self));
+        }
+
+        // SAFETY: same type layout
+        Ok(unsafe { core::mem::transmute::<BufRing<S>, BufRing<state::Uninit>>(self) })
+    }
+
+    /// Unregisters the ring if it is registered, leaving its typestate behind.
+    ///
+    /// Afterwards the ring is `Uninit` in all but its type, so it must only be
+    /// dropped or transmuted to `BufRing<Uninit>`.
+    pub(crate) fn unregister_in_place(
+        &mut self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> std::io::Result<()> {
+        if self.lifecycle == Lifecycle::Uninit {
+            return Ok(());
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "buf_ring_unregister",
+            bgid = self.bgid,
+            entries = self.entries,
+            buf_size = self.buf_size
+        )
+        .entered();
+
+        // a `?` only without tracing
+        #[allow(clippy::question_mark)]
+        if let Err(e) = submitter.unregister_buf_ring(self.bgid()) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(error = %e, "unregister failed");
+            return Err(e);
+        }
+        self.reset_registration();
+        Ok(())
+    }
+
+    /// Moves the ring back to `Uninit` without a kernel, the counterpart of
+    /// [`register_for_miri`](BufRing::register_for_miri).
+    #[cfg(miri)]
+    pub fn unregister_for_miri(mut self) -> BufRing<state::Uninit> {
+        self.reset_registration();
+        // SAFETY: same type layout
+        unsafe { core::mem::transmute(self) }
+    }
+
+    pub(crate) fn reset_registration(&mut self) {
+        // the kernel starts its head at 0 on the next registration
+        unsafe { self.init_() };
+        self.reset_bid_states();
+        self.mode = RegistrationMode::Plain;
+        self.consumed = Box::new([]);
+        // entries may be zeroed by `release_pages` before the next `init`
+        self.static_layout = false;
+        self.lifecycle = Lifecycle::Uninit;
+    }
+
+    /// Lets `Drop` unmap a ring whose buffer group was unregistered elsewhere.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the kernel no longer uses the buffer group.
+    pub(crate) unsafe fn forget_registration(&mut self) {
+        self.lifecycle = Lifecycle::Uninit;
+    }
+}
+
+impl<S> Drop for BufRing<S> {
+    fn drop(&mut self) {
+        if self.lifecycle != Lifecycle::Uninit {
+            debug_assert!(
+                std::thread::panicking(),
+                "BufRing with bgid {} dropped while registered",
+                self.bgid
+            );
+            // the kernel may still write into the mapping, leak it instead
+            return;
+        }
+        unsafe { unmap_ring(self.base, self.mmap_size()) }
+    }
+}
+
+/// The mapping's addresses are only shown with `{:#?}`.
+impl<S> core::fmt::Debug for BufRing<S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        // the builder borrows `f` mutably
+        let alternate = f.alternate();
+        let mut s = f.debug_struct("BufRing");
+        s.field("bgid", &self.bgid)
+            .field("entries", &self.entries)
+            .field("buf_size", &self.buf_size)
+            .field("state", &self.lifecycle)
+            .field("tail", &self.published_tail())
+            .field("provided", &self.provided())
+            .field("held_by_app", &self.held_by_app())
+            .field("parked", &self.parked())
+            .field("mmap_size", &self.mmap_size());
+        if alternate {
+            s.field("base", &self.base)
+                .field("buffer_base", &self.buffer_base);
+        }
+        s.finish()
+    }
+}
+
+/// Combined mapping size of rings built from `(entries, buf_size)` pairs with
+/// the default [`MapOpts`].
+///
+/// Applies the same validation, power of two rounding and per entry overhead
+/// as [`BufRing::new`], so the result can be checked against a memory limit
+/// before anything is allocated. Fails with `InvalidInput` for any pair the
+/// constructor rejects, and if the total doesn't fit a `usize`.
+pub fn plan_memory(configs: &[(u16, u32)]) -> std::io::Result<usize> {
+    configs.iter().try_fold(0usize, |total, &(entries, buf_size)| {
+        let entries = NonZeroU16::new(entries).ok_or(std::io::ErrorKind::InvalidInput)?;
+        let size = ring_size(ring_entries(entries)? as u32, buf_size, 0);
+        total
+            .checked_add(size)
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::InvalidInput))
+    })
+}
+
+/// The entry count a ring asked for `entries` gets, rounded up to a power of two.
+fn ring_entries(entries: NonZeroU16) -> std::io::Result<u16> {
+    // the kernel caps rings at 32768 entries, anything above would also
+    // round up past `u16::MAX`
+    if entries.get() > MAX_ENTRIES {
+        return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput));
+    }
+    Ok(entries.get().next_power_of_two())
+}
+
+/// A ring size covering `expected_concurrent_recvs` each holding up to
+/// `per_conn_buffers` buffers at once.
+///
+/// Rounded up to a power of two, which leaves some headroom for buffers
+/// waiting to be recycled, and clamped to `1..=MAX_ENTRIES`.
+pub fn suggested_entries(expected_concurrent_recvs: usize, per_conn_buffers: usize) -> u16 {
+    let needed = expected_concurrent_recvs
+        .saturating_mul(per_conn_buffers)
+        .clamp(1, MAX_ENTRIES as usize);
+    needed.next_power_of_two() as u16
+}
+
+#[inline]
+pub(crate) fn ring_size(entries: u32, buf_size: u32, buffer_align: u32) -> usize {
+    buffer_offset(entries, buffer_align) + entries as usize * buf_size as usize
+}
+
+/// Offset of the first buffer from the start of the mapping.
+#[inline]
+pub(crate) fn buffer_offset(entries: u32, buffer_align: u32) -> usize {
+    let entries_size = entries as usize * ENTRY_SIZE;
+    entries_size.next_multiple_of(buffer_align.max(1) as usize)
+}
+
+/// Maps the entry array followed by the buffer region and zeroes the tail.
+///
+/// `entries` must already be a power of two.
+pub(crate) fn map_ring(
+    entries: u16,
+    buf_size: u32,
+    opts: MapOpts,
+) -> std::io::Result<(*mut BufRingEntry, *const u8)> {
+    let align = opts.buffer_align;
+    // the mapping itself is only page aligned
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u32;
+    if align != 0 && (!align.is_power_of_two() || align > page_size) {
+        return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput));
+    }
+
+    let mut map_flags = libc::MAP_ANONYMOUS;
+
+    map_flags |= match opts.privacy {
+        MapPrivacy::Private => libc::MAP_PRIVATE,
+        MapPrivacy::Shared => libc::MAP_SHARED,
+        MapPrivacy::SharedValidate => libc::MAP_SHARED_VALIDATE,
+    };
+
+    if opts.warmup != Warmup::None {
+        map_flags |= libc::MAP_POPULATE;
+    }
+
+    let buf_ring_size = ring_size(entries as u32, buf_size, align);
+
+    #[cfg(miri)]
+    let base = {
+        // Miri can't run the mmap below, zeroed heap memory stands in for it
+        let _ = map_flags;
+        let base = unsafe { std::alloc::alloc_zeroed(miri_layout(buf_ring_size)) };
+        if base.is_null() {
+            return Err(std::io::ErrorKind::OutOfMemory.into());
+        }
+        base.cast::<libc::c_void>()
+    };
+    #[cfg(not(miri))]
+    let base = unsafe {
+        match libc::mmap(
+            core::ptr::null_mut(),
+            buf_ring_size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            map_flags,
+            -1,
+            0,
+        ) {
+            libc::MAP_FAILED => return Err(std::io::Error::last_os_error()),
+            addr => addr,
+        }
+    };
+
+    #[cfg(not(miri))]
+    if opts.warmup == Warmup::PopulateAndWillneed {
+        // only a hint, the mapping is usable whether or not it's honored
+        unsafe { libc::madvise(base, buf_ring_size, libc::MADV_WILLNEED) };
+    }
+
+    let buf_base: *const u8 =
+        unsafe { base.add(buffer_offset(entries as u32, align)) as *const u8 };
+
+    let base = base as *mut BufRingEntry;
+
+    unsafe { (*tail_atomic(base)).store(0, Ordering::Release) };
+
+    Ok((base, buf_base))
+}
+
+/// Unmaps a ring mapped by [`map_ring`].
+///
+/// # Safety
+///
+/// `base` and `size` must describe a live mapping returned by `map_ring`,
+/// which is never used again.
+pub(crate) unsafe fn unmap_ring(base: *mut BufRingEntry, size: usize) {
+    #[cfg(miri)]
+    unsafe {
+        std::alloc::dealloc(base.cast(), miri_layout(size))
+    };
+    #[cfg(not(miri))]
+    unsafe {
+        libc::munmap(base.cast(), size)
+    };
+}
+
+/// Page aligned, like the mapping it replaces.
+#[cfg(miri)]
+fn miri_layout(size: usize) -> std::alloc::Layout {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    std::alloc::Layout::from_size_align(size, page_size).expect("ring too large")
+}
+
+// io_uring doesn't expose its sys bindings
+// so they've been redefined here.
+const IORING_REGISTER_PBUF_STATUS: libc::c_uint = 26;
+
+#[repr(C)]
+struct IoUringBufStatus {
+    buf_group: u32,
+    head: u32,
+    resv: [u32; 8],
+}
+
+/// The kernel's head for the buffer ring registered as `bgid`.
+fn pbuf_status(ring_fd: std::os::fd::RawFd, bgid: u16) -> std::io::Result<u16> {
+    let mut status = IoUringBufStatus {
+        buf_group: bgid as u32,
+        head: 0,
+        resv: [0; 8],
+    };
+    // SAFETY: `status` is valid for the kernel to write for the whole call
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_io_uring_register,
+            ring_fd,
+            IORING_REGISTER_PBUF_STATUS,
+            &mut status as *mut IoUringBufStatus,
+            1,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(status.head as u16)
+}
+
+/// Whether the running kernel is at least `major.minor`.
+fn kernel_at_least(major: u32, minor: u32) -> bool {
+    let mut uts = unsafe { core::mem::zeroed::<libc::utsname>() };
+    // SAFETY: `uts` is valid for writes
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return false;
+    }
+    // SAFETY: `release` is nul terminated
+    let release = unsafe { core::ffi::CStr::from_ptr(uts.release.as_ptr()) };
+    let mut parts = release
+        .to_str()
+        .unwrap_or_default()
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|n| n.parse::<u32>().unwrap_or(0));
+    let version = (parts.next().unwrap_or(0), parts.next().unwrap_or(0));
+    version >= (major, minor)
+}
+
+/// The shared tail of the ring mapped at `base`.
+///
+/// # Safety
+///
+/// `base` must point to a live ring mapping. The tail overlaps the `resv` field
+/// of the first entry, which this crate never writes through a non-atomic
+/// store, and the mapping is writable and suitably aligned for a `u16`.
+#[inline]
+pub(crate) unsafe fn tail_atomic(base: *mut BufRingEntry) -> *const AtomicU16 {
+    // `BufRingEntry::tail` goes through a `*const`, only take its offset so the
+    // pointer keeps the write provenance of `base`
+    let offset = unsafe {
+        BufRingEntry::tail(base)
+            .cast::<u8>()
+            .offset_from(base.cast::<u8>())
+    };
+    unsafe { AtomicU16::from_ptr(base.cast::<u8>().offset(offset).cast::<u16>()) }
+}
+
+/// A kernel consumer for tests that read a ring from another thread.
+#[cfg(test)]
+pub(crate) mod model {
+    use super::*;
+
+    /// What the kernel sees of a ring: its entries, buffers and tail.
+    pub(crate) struct ModelKernel {
+        entries: *const BufRingEntry,
+        tail: *const SharedTail,
+        buffer_base: *const u8,
+        buf_size: u32,
+        mask: u32,
+    }
+
+    // SAFETY: only reads memory the ring keeps alive until the consumer thread joined
+    unsafe impl Send for ModelKernel {}
+
+    impl ModelKernel {
+        pub(crate) fn attach(ring: &BufRing<state::Init>) -> Self {
+            Self {
+                entries: ring.base,
+                tail: ring.shared_tail(),
+                buffer_base: ring.buffer_base,
+                buf_size: ring.buf_size,
+                mask: ring.mask,
+            }
+        }
+
+        /// Reads the tail with `Acquire` like the kernel, then every entry
+        /// published since `head`, and returns the new head.
+        #[cfg_attr(not(loom), allow(dead_code))]
+        pub(crate) fn consume(&self, head: u16) -> u16 {
+            let tail = self.tail();
+            for i in 0..tail.wrapping_sub(head) {
+                self.check(head.wrapping_add(i));
+            }
+            tail
+        }
+
+        /// The buffer id at `head`, if it was published.
+        pub(crate) fn take(&self, head: u16) -> Option<u16> {
+            (self.tail() != head).then(|| self.check(head))
+        }
+
+        fn tail(&self) -> u16 {
+            unsafe { &*self.tail }.load(Ordering::Acquire)
+        }
+
+        /// Checks the published entry in `slot` and returns its buffer id.
+        fn check(&self, slot: u16) -> u16 {
+            let entry = unsafe { &*self.entries.add((slot as u32 & self.mask) as usize) };
+            let bid = entry.bid();
+            assert!(bid as u32 <= self.mask, "slot {slot} published before its bid");
+            let addr = self.buffer_base as u64 + bid as u64 * self.buf_size as u64;
+            assert_eq!(entry.addr(), addr, "slot {slot} published before its addr");
+            assert_eq!(entry.len(), self.buf_size, "slot {slot} published before its len");
+            bid
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cqe::{FakeCqe, cqe_buffer_id, cqe_has_buffer};
+    use crate::sim::SimKernel;
+
+    /// A ring with every buffer provided, consumed by a [`SimKernel`].
+    fn sim_ring(entries: u16, buf_size: u32) -> BufRing<state::Init> {
+        BufRing::new(entries, buf_size, 0).unwrap().register_simulated()
+    }
+
+    /// A ring with every buffer parked.
+    fn parked_ring(entries: u16, buf_size: u32) -> BufRing<state::Init> {
+        BufRing::new(entries, buf_size, 0)
+            .unwrap()
+            .into_registered()
+            .init_parked()
+    }
+
+    fn release(ring: BufRing<state::Init>) {
+        drop(ring.unregister_simulated());
+    }
+
+    #[test]
+    fn dump_shows_written_and_untouched_slots() {
+        let mut ring = parked_ring(4, 16);
+        ring.provide_with_len(2, 8).unwrap();
+        ring.provide_with_len(0, 16).unwrap();
+
+        let entry = |slot, offset, len, bid| EntrySnapshot {
+            slot,
+            offset,
+            len,
+            bid,
+        };
+        assert_eq!(
+            ring.snapshot_entries(),
+            [
+                entry(0, Some(32), 8, 2),
+                entry(1, Some(0), 16, 0),
+                entry(2, None, 0, 0),
+                entry(3, None, 0, 0),
+            ]
+        );
+
+        let mut dump = String::new();
+        ring.write_dump(&mut dump).unwrap();
+        let expected = "\
+bgid 0 tail 2 (slot 2)
+ slot       offset        len   bid
+    0           32          8     2
+    1            0         16     0
+    2            -          0     0
+    3            -          0     0
+";
+        assert_eq!(dump, expected);
+        release(ring);
+    }
+
+    /// The tail cached at mapping time is the one io_uring's own accessor
+    /// finds, the `resv` field of the first entry, and sees every publication.
+    #[test]
+    fn cached_tail_is_the_entry_tail() {
+        let mut ring = sim_ring(8, 64);
+        // SAFETY: `base` points at the mapped entries
+        let tail = unsafe { BufRingEntry::tail(ring.base) };
+        assert_eq!(ring.tail_ptr().cast::<u16>(), tail);
+        assert_eq!(tail as usize - ring.ring_addr() as usize, 14);
+
+        let mut kernel = SimKernel::attach(&ring);
+        for round in 1..=20u16 {
+            let (flags, res) = kernel.recv(&ring, b"x", true);
+            drop(ring.buffer_id_from_fields(&FakeCqe { flags, res }).unwrap());
+            // SAFETY: as above, and only this thread writes the tail
+            assert_eq!(unsafe { *tail }, 8 + round);
+        }
+        release(ring);
+    }
+
+    /// The checked accessors stop at `entries()`, and see what the ring and
+    /// each other wrote.
+    #[test]
+    fn entry_accessors_are_bounds_checked() {
+        let mut ring = parked_ring(8, 64);
+        for past in [8, 9, u16::MAX] {
+            assert!(ring.entry_ref(past).is_none());
+            assert!(ring.entry_mut(past).is_none());
+        }
+        assert!(ring.entry_ref(7).is_some());
+
+        // SAFETY: nothing is published
+        unsafe { ring.provide_buffer_at(5, 2).unwrap() };
+        let entry = ring.entry_ref(5).unwrap();
+        assert_eq!(entry.bid(), 2);
+        assert_eq!(entry.len(), 64);
+        assert_eq!(entry.addr(), ring.buffer_base as u64 + 2 * 64);
+        #[allow(deprecated)]
+        let raw = unsafe { ring.entry(5) };
+        assert!(core::ptr::eq(entry, raw));
+
+        ring.entry_mut(5).unwrap().set_len(16);
+        assert_eq!(ring.entry_ref(5).unwrap().len(), 16);
+        assert_eq!(ring.snapshot_entries()[5].len, 16);
+        ring.entry_mut(5).unwrap().set_len(64);
+        release(ring);
+    }
+
+    /// The pool covers every buffer and nothing else, and what is written
+    /// through it is what each buffer holds.
+    #[test]
+    fn pool_spans_every_buffer() {
+        let mut ring = parked_ring(4, 16);
+        let pool = ring.pool().unwrap();
+        assert_eq!(pool.as_ptr(), ring.buffer_base);
+        assert_eq!(pool.len(), 4 * 16);
+        let end = ring.ring_addr() as usize + ring.mmap_size();
+        assert_eq!(pool.as_ptr_range().end as usize, end);
+
+        for (i, byte) in ring.pool_mut().unwrap().iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        for buf_id in 0..4u16 {
+            // SAFETY: in range
+            let buf = unsafe { ring.buffer(buf_id) };
+            let start = buf_id as u8 * 16;
+            assert!(buf.iter().copied().eq(start..start + 16));
+        }
+
+        ring.provide_with_len(3, 16).unwrap();
+        assert_eq!(ring.pool().err(), Some(RingError::Provided { count: 1 }));
+        assert_eq!(ring.pool_mut().err(), Some(RingError::Provided { count: 1 }));
+        release(ring);
+    }
+
+    #[test]
+    fn provide_buffer_at_out_of_order() {
+        let mut ring = parked_ring(8, 64);
+        unsafe {
+            ring.provide_buffer_at(2, 5).unwrap();
+            ring.provide_buffer_at(1, 6).unwrap();
+        }
+        // slot 0 is a gap, nothing can be published yet
+        assert_eq!(ring.pending, 0);
+        assert_eq!(ring.unpublished(), 2);
+        assert_eq!(ring.kernel_held_estimate(), 0);
+
+        unsafe { ring.provide_buffer_at(0, 7).unwrap() };
+        assert_eq!((ring.pending, ring.ahead), (3, 0));
+        unsafe { ring.advance(3) };
+        assert_eq!(ring.kernel_held_estimate(), 3);
+        assert_eq!(ring.verify(), Ok(()));
+
+        let mut kernel = SimKernel::attach(&ring);
+        let bids: Vec<_> = (0..3)
+            .map(|_| cqe_buffer_id(kernel.recv(&ring, b"x", true).0))
+            .collect();
+        assert_eq!(bids, [7, 6, 5]);
+        release(ring);
+    }
+
+    #[test]
+    fn provide_buffer_at_rejects_written_slots() {
+        let mut ring = parked_ring(8, 64);
+        unsafe {
+            ring.provide_buffer_at(0, 0).unwrap();
+            ring.provide_buffer_at(3, 1).unwrap();
+            assert_eq!(
+                ring.provide_buffer_at(0, 2),
+                Err(RingError::SlotWritten { slot: 0 })
+            );
+            assert_eq!(
+                ring.provide_buffer_at(3, 2),
+                Err(RingError::SlotWritten { slot: 3 })
+            );
+            assert_eq!(
+                ring.provide_buffer_at(4, 1),
+                Err(RingError::NotOwned { buf_id: 1 })
+            );
+        }
+        assert_eq!(ring.bid_state(2), BidState::Parked);
+        assert_eq!(ring.unpublished(), 2);
+        release(ring);
+    }
+
+    #[test]
+    fn push_skips_slots_written_ahead() {
+        let mut ring = parked_ring(8, 64);
+        unsafe {
+            ring.provide_buffer_at(1, 4).unwrap();
+            // lands in slot 0 and absorbs slot 1
+            let first = ring.parked.pop().unwrap();
+            ring.push(first);
+        }
+        assert_eq!((ring.pending, ring.ahead), (2, 0));
+        let next = ring.parked.pop().unwrap();
+        unsafe { ring.push(next) };
+        assert_eq!(ring.slot_of(next), 2);
+        unsafe { ring.advance(3) };
+        assert_eq!(ring.verify(), Ok(()));
+        release(ring);
+    }
+
+    /// Receives `payload` into the next buffer and detaches it.
+    fn detach_next(
+        ring: &mut BufRing<state::Init>,
+        kernel: &mut SimKernel,
+        payload: &[u8],
+    ) -> OwnedBuffer {
+        let (flags, res) = kernel.recv(ring, payload, true);
+        let cqe = FakeCqe::buffer(cqe_buffer_id(flags), res as u32, true);
+        let id = ring.buffer_id_from_fields(&cqe);
+        // SAFETY: every buffer is returned before the ring is released
+        unsafe { id.unwrap().unwrap().detach() }
+    }
+
+    #[test]
+    fn out_of_range_buffer_id_is_invalid_data() {
+        let mut ring = sim_ring(4, 16);
+        let cqe = FakeCqe::buffer(9, 4, true);
+        let err = ring.buffer_id_from_fields(&cqe).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        // nothing was consumed
+        assert_eq!((ring.provided(), ring.held_by_app()), (4, 0));
+        release(ring);
+    }
+
+    /// Reads the eventfd counter, or `None` if nothing was signalled.
+    fn read_eventfd(fd: BorrowedFd<'_>) -> Option<u64> {
+        let mut count = 0u64;
+        // SAFETY: reads 8 bytes into `count`
+        let n = unsafe { libc::read(fd.as_raw_fd(), (&raw mut count).cast(), 8) };
+        if n < 0 {
+            let e = std::io::Error::last_os_error();
+            assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock);
+            return None;
+        }
+        Some(count)
+    }
+
+    #[test]
+    fn notify_low_buffers_signals_the_eventfd() {
+        use std::os::fd::{AsFd, FromRawFd, OwnedFd};
+
+        // SAFETY: no pointers involved
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        assert!(fd >= 0, "{}", std::io::Error::last_os_error());
+        // SAFETY: `eventfd` returned a fresh descriptor
+        let eventfd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut ring = sim_ring(8, 16);
+        let mut kernel = SimKernel::attach(&ring);
+        // SAFETY: `eventfd` outlives the ring
+        unsafe { ring.notify_low_buffers(eventfd.as_fd(), 3) };
+
+        let mut held: Vec<_> = (0..5)
+            .map(|_| detach_next(&mut ring, &mut kernel, b"data"))
+            .collect();
+        assert_eq!(ring.provided(), 3);
+        assert_eq!(read_eventfd(eventfd.as_fd()), None);
+        held.push(detach_next(&mut ring, &mut kernel, b"data"));
+        assert!(ring.is_below_watermark());
+        assert_eq!(read_eventfd(eventfd.as_fd()), Some(1));
+
+        // starving the ring completely is the same crossing
+        held.extend((0..2).map(|_| detach_next(&mut ring, &mut kernel, b"data")));
+        assert_eq!(ring.provided(), 0);
+        assert_eq!(read_eventfd(eventfd.as_fd()), None);
+
+        // recovering re-arms it, the next crossing signals again
+        assert!(matches!(ring.recycle_many(held), Ok(8)));
+        let held: Vec<_> = (0..6)
+            .map(|_| detach_next(&mut ring, &mut kernel, b"data"))
+            .collect();
+        assert_eq!(read_eventfd(eventfd.as_fd()), Some(1));
+
+        ring.recycle_many(held).unwrap();
+        release(ring);
+    }
+
+    #[test]
+    fn recycle_many_is_all_or_nothing() {
+        let mut ring = sim_ring(8, 64);
+        let mut kernel = SimKernel::attach(&ring);
+        let bufs: Vec<_> = (0..3)
+            .map(|_| detach_next(&mut ring, &mut kernel, b"data"))
+            .collect();
+        let tail = ring.published_tail();
+        let ids: Vec<_> = bufs.iter().map(OwnedBuffer::buf_id).collect();
+
+        let mut batch = bufs;
+        batch.insert(2, OwnedBuffer::new(core::ptr::null(), 0, 7, 9));
+        let (err, returned) = ring.recycle_many(batch).unwrap_err();
+        assert_eq!(err, RecycleError::WrongRing { bgid: 9 });
+        assert_eq!(returned.len(), 4);
+        assert_eq!(returned[2].bgid(), 9);
+        // nothing written, nothing published
+        assert_eq!((ring.unpublished(), ring.published_tail()), (0, tail));
+        for &id in &ids {
+            assert_eq!(ring.bid_state(id), BidState::HeldByApp);
+        }
+
+        // a failed completion hands out nothing to recycle
+        let cqe = FakeCqe::error(libc::ENOBUFS);
+        assert!(ring.buffer_id_from_fields(&cqe).is_err());
+
+        let returned: Vec<_> = returned.into_iter().filter(|b| b.bgid() == 0).collect();
+        assert!(matches!(ring.recycle_many(returned), Ok(3)));
+        assert_eq!(ring.provided(), 8);
+        assert_eq!(ring.verify(), Ok(()));
+        release(ring);
+    }
+
+    #[test]
+    fn recycle_many_rejects_duplicates() {
+        let mut ring = sim_ring(4, 64);
+        let mut kernel = SimKernel::attach(&ring);
+        let buf = detach_next(&mut ring, &mut kernel, b"data");
+        let id = buf.buf_id();
+        let twin = OwnedBuffer::new(buf.as_ptr(), buf.len() as u32, id, 0);
+
+        let (err, returned) = ring.recycle_many([buf, twin]).unwrap_err();
+        assert_eq!(err, RecycleError::NotHeld { buf_id: id });
+        assert_eq!(ring.unpublished(), 0);
+        assert_eq!(ring.bid_state(id), BidState::HeldByApp);
+
+        let mut returned = returned;
+        returned.truncate(1);
+        assert!(ring.recycle_many(returned).is_ok());
+        assert_eq!(ring.bid_state(id), BidState::Provided);
+        release(ring);
+    }
+
+    #[test]
+    fn provision_limit_caps_every_path() {
+        let mut ring = sim_ring(8, 64);
+        let mut kernel = SimKernel::attach(&ring);
+        for (flags, res) in kernel.exhaust(&ring, b"data") {
+            if let Ok(id) = ring.buffer_id_from_fields(&FakeCqe { flags, res }) {
+                id.unwrap().keep();
+            }
+        }
+        assert_eq!(ring.held_by_app(), 8);
+        ring.set_provision_limit(2);
+
+        ring.provide_with_len(0, 16).unwrap();
+        ring.provide_with_len(1, 16).unwrap();
+        ring.provide_with_len(2, 16).unwrap();
+        assert_eq!(ring.bid_state(2), BidState::Parked);
+
+        let mut free = unsafe { ring.free_buffer(3) };
+        free.write_vectored_all(&[std::io::IoSlice::new(b"ping")]).unwrap();
+        free.provide();
+        assert_eq!(ring.bid_state(3), BidState::Parked);
+
+        unsafe { ring.recycle_batch(&[4, 5]) };
+        assert_eq!(ring.refill_unprovided(), 0);
+        assert_eq!((ring.provided(), ring.parked(), ring.held_by_app()), (2, 4, 2));
+        assert!(ring.kernel_held_estimate() <= 2);
+
+        // raising the limit provides parked buffers right away
+        ring.raise_limit(3);
+        assert_eq!((ring.provided(), ring.parked()), (5, 1));
+        assert_eq!(ring.kernel_held_estimate(), 5);
+        ring.clear_provision_limit();
+        assert_eq!(ring.provided(), 6);
+        assert_eq!(ring.verify(), Ok(()));
+        release(ring);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn validate_reads_the_kernel_head() {
+        let uring = io_uring::IoUring::new(8).unwrap();
+        let submitter = uring.submitter();
+        let ring = BufRing::new(8, 64, 3)
+            .unwrap()
+            .register(&submitter)
+            .map_err(|(e, _)| e)
+            .unwrap()
+            .init();
+        ring.validate(&uring).unwrap();
+
+        // a ring claiming a group the kernel doesn't know about
+        let stray = sim_ring(8, 64);
+        if kernel_at_least(6, 8) {
+            let e = stray.validate(&uring).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+        }
+        release(stray);
+        // and validating it didn't register group 0 behind its back
+        let probe = BufRing::new(8, 64, 0).unwrap().register(&submitter);
+        let probe = probe.map_err(|(e, _)| e).unwrap();
+        drop(probe.unregister(&submitter).map_err(|(e, _)| e).unwrap());
+
+        drop(ring.unregister(&submitter).map_err(|(e, _)| e).unwrap());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn reconfigure_rejects_a_zero_buffer_size() {
+        let uring = io_uring::IoUring::new(8).unwrap();
+        let submitter = uring.submitter();
+        let ring = BufRing::new(4, 64, 6).unwrap();
+        let ring = ring
+            .register_and_init(&submitter)
+            .map_err(|(e, _)| e)
+            .unwrap();
+
+        let Err(ReconfigureError::Unchanged(e, ring)) = ring.reconfigure(0, &submitter) else {
+            panic!("a zero buffer size was accepted");
+        };
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!((ring.buf_size(), ring.provided()), (64, 4));
+        // still registered
+        drop(ring.unregister(&submitter).map_err(|(e, _)| e).unwrap());
+    }
+
+    /// The remapped ring receives into the bigger buffers and keeps the
+    /// settings of the old one.
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn reconfigure_carries_the_settings_over() {
+        use std::sync::Arc;
+        use std::sync::atomic::AtomicU16;
+
+        let mut uring = io_uring::IoUring::new(8).unwrap();
+        let ring = BufRing::new(4, 16, 7).unwrap();
+        let mut ring = ring
+            .register_and_init(&uring.submitter())
+            .map_err(|(e, _)| e)
+            .unwrap();
+        ring.set_deferred_advance(true);
+        ring.set_provision_limit(3);
+        ring.with_metadata::<u32>();
+        *ring.meta_mut::<u32>(0).unwrap() = 7;
+        let fired = Arc::new(AtomicU16::new(u16::MAX));
+        let seen = fired.clone();
+        ring.set_low_watermark(4, move |provided| seen.store(provided, Ordering::Relaxed));
+
+        let mut ring = ring.reconfigure(64, &uring.submitter()).unwrap();
+        assert_eq!((ring.buf_size(), ring.provided()), (64, 4));
+        assert!(ring.deferred_advance());
+        assert_eq!(ring.provision_limit(), Some(3));
+        assert_eq!(ring.meta::<u32>(0), Some(&0));
+
+        let (tx, rx) = std::os::unix::net::UnixStream::pair().unwrap();
+        std::io::Write::write_all(&mut &tx, &[1; 40]).unwrap();
+        let sqe = ring.recv_sqe(rx.as_raw_fd(), 0, 64);
+        unsafe { uring.submission().push(&sqe).unwrap() };
+        uring.submit_and_wait(1).unwrap();
+        let cqe = uring.completion().next().unwrap();
+        let id = ring.buffer_id_from_cqe(&cqe).unwrap().unwrap();
+        assert_eq!(id.buffer(), [1; 40]);
+        drop(id);
+        assert_eq!(fired.load(Ordering::Relaxed), 3);
+        // over the limit, so parked instead of provided
+        assert_eq!((ring.provided(), ring.parked()), (3, 1));
+
+        drop(ring.unregister(&uring.submitter()).map_err(|(e, _)| e).unwrap());
+    }
+
+    /// Buffers of a pool larger than `u32::MAX` bytes follow each other at
+    /// `buf_size` strides and end within the mapping. The pages are never
+    /// touched, so the mapping costs address space only.
+    #[test]
+    #[cfg_attr(miri, ignore = "maps more than 4 GiB")]
+    fn buffers_past_4gib_stay_in_bounds() {
+        let buf_size = 600 << 20;
+        let ring = BufRing::new(8, buf_size, 0).unwrap();
+        assert!(ring.entries() as usize * buf_size as usize > u32::MAX as usize);
+
+        let (start, len) = ring.data_region();
+        let end = ring.ring_addr() as usize + ring.mmap_size();
+        assert_eq!(start as usize + len, end);
+        let mut prev = None;
+        for buf_id in 0..ring.entries() {
+            // SAFETY: in range
+            let addr = unsafe { ring.get_buffer(buf_id) } as usize;
+            assert_eq!(addr, start as usize + buf_id as usize * buf_size as usize);
+            if let Some(prev) = prev {
+                assert_eq!(addr - prev, buf_size as usize);
+            }
+            assert!(addr + buf_size as usize <= end);
+            prev = Some(addr);
+        }
+    }
+
+    /// Registering and unregistering record spans with the ring's geometry,
+    /// and a failed registration logs its error inside its span.
+    #[test]
+    #[cfg(feature = "tracing")]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn registration_span_carries_the_geometry() {
+        use std::sync::{Arc, Mutex};
+        use tracing_subscriber::fmt::format::FmtSpan;
+
+        struct Capture(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let writer = out.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || Capture(writer.clone()))
+            .with_max_level(tracing::Level::DEBUG)
+            .with_span_events(FmtSpan::NEW)
+            .with_ansi(false)
+            .with_target(false)
+            .without_time()
+            .finish();
+
+        let uring = io_uring::IoUring::new(8).unwrap();
+        let submitter = uring.submitter();
+        tracing::subscriber::with_default(subscriber, || {
+            let ring = BufRing::new(8, 64, 9).unwrap().register(&submitter);
+            let ring = ring.map_err(|(e, _)| e).unwrap();
+            // the group is taken
+            let twin = BufRing::new(4, 32, 9).unwrap();
+            let (e, twin) = twin.register(&submitter).unwrap_err();
+            assert_eq!(e.raw_os_error(), Some(libc::EEXIST));
+            drop(twin);
+            drop(ring.unregister(&submitter).map_err(|(e, _)| e).unwrap());
+        });
+
+        let out = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+        let expected = "\
+DEBUG buf_ring_register{bgid=9 entries=8 buf_size=64}: new
+DEBUG buf_ring_register{bgid=9 entries=4 buf_size=32}: new
+DEBUG buf_ring_register{bgid=9 entries=4 buf_size=32}: register failed error=File exists (os error 17)
+DEBUG buf_ring_unregister{bgid=9 entries=8 buf_size=64}: new
+";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn only_busy_and_owner_dead_are_transient() {
+        for errno in [libc::EBUSY, libc::EOWNERDEAD] {
+            assert!(is_transient_sqpoll_errno(errno));
+        }
+        for errno in [libc::EEXIST, libc::EINVAL, libc::ENOMEM, libc::EFAULT, 0] {
+            assert!(!is_transient_sqpoll_errno(errno));
+        }
+    }
+
+    /// Registers through the retry loop with each attempt failing with the
+    /// next errno of `script`, and returns the outcome and the attempts made.
+    fn scripted_retry(sqpoll: bool, script: &[i32]) -> (Result<(), std::io::Error>, usize) {
+        let mut attempts = 0;
+        let res = BufRing::new(1, 16, 0)
+            .unwrap()
+            .retry_on_sqpoll(sqpoll, |ring| {
+                attempts += 1;
+                match script.get(attempts - 1) {
+                    Some(&errno) => Err((std::io::Error::from_raw_os_error(errno), ring)),
+                    None => Ok(()),
+                }
+            })
+            .map_err(|(e, _)| e);
+        (res, attempts)
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "sleeps between attempts")]
+    fn sqpoll_retries_only_transient_errors() {
+        let busy = libc::EBUSY;
+        let (res, attempts) = scripted_retry(true, &[busy, libc::EOWNERDEAD]);
+        assert!(res.is_ok());
+        assert_eq!(attempts, 3);
+
+        // without SQPOLL, or for any other error, the first failure is final
+        let (res, attempts) = scripted_retry(false, &[busy]);
+        assert_eq!((res.unwrap_err().raw_os_error(), attempts), (Some(busy), 1));
+        let exists = libc::EEXIST;
+        let (res, attempts) = scripted_retry(true, &[exists, busy]);
+        assert_eq!((res.unwrap_err().raw_os_error(), attempts), (Some(exists), 1));
+
+        let (res, attempts) = scripted_retry(true, &[busy; 10]);
+        assert_eq!(attempts, SQPOLL_REGISTER_ATTEMPTS as usize);
+        let e = res.unwrap_err();
+        assert_eq!(e.kind(), std::io::Error::from_raw_os_error(busy).kind());
+        let inner = e.get_ref().unwrap().downcast_ref::<SqpollRegisterError>();
+        let expected = SqpollRegisterError {
+            attempts: SQPOLL_REGISTER_ATTEMPTS,
+            errno: busy,
+        };
+        assert_eq!(inner, Some(&expected));
+    }
+
+    /// Registers on a real SQPOLL ring and receives through it, where the
+    /// sandbox allows creating one.
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn registers_on_an_sqpoll_ring() {
+        let uring = io_uring::IoUring::<io_uring::squeue::Entry>::builder()
+            .setup_sqpoll(100)
+            .build(8);
+        let Ok(mut uring) = uring else {
+            eprintln!("SQPOLL isn't available, skipping");
+            return;
+        };
+        assert!(uring.params().is_setup_sqpoll());
+        let ring = BufRing::new(4, 64, 14).unwrap();
+        let ring = ring.register_with_params(&uring.submitter(), uring.params());
+        let mut ring = ring.map_err(|(e, _)| e).unwrap().init();
+
+        let (tx, rx) = std::os::unix::net::UnixStream::pair().unwrap();
+        std::io::Write::write_all(&mut &tx, b"polled").unwrap();
+        let sqe = ring.recv_sqe(rx.as_raw_fd(), 0, 1);
+        unsafe { uring.submission().push(&sqe).unwrap() };
+        uring.submit_and_wait(1).unwrap();
+        let cqe = uring.completion().next().unwrap();
+        let id = ring.buffer_id_from_cqe(&cqe).unwrap().unwrap();
+        assert_eq!(id.buffer(), b"polled");
+        drop(id);
+
+        let ring = ring.unregister(&uring.submitter());
+        drop(ring.map_err(|(e, _)| e).unwrap());
+    }
+
+    #[test]
+    fn plan_memory_validates_like_the_constructor() {
+        let one = BufRing::new(100, 4096, 0).unwrap();
+        let two = BufRing::new(MAX_ENTRIES, 16, 1).unwrap();
+        let planned = plan_memory(&[(100, 4096), (MAX_ENTRIES, 16)]).unwrap();
+        assert_eq!(planned, one.mmap_size() + two.mmap_size());
+
+        for bad in [(0, 4096), (MAX_ENTRIES + 1, 4096), (u16::MAX, 1)] {
+            assert!(BufRing::new(bad.0, bad.1, 0).is_err());
+            let e = plan_memory(&[(1, 1), bad]).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(plan_memory(&[]).unwrap(), 0);
+    }
+
+    #[test]
+    fn bid_state_lifecycle() {
+        let mut ring = sim_ring(4, 64);
+        let mut kernel = SimKernel::attach(&ring);
+        assert!(ring.bid_states().all(|(_, state)| state == BidState::Provided));
+
+        let (flags, res) = kernel.recv(&ring, b"data", true);
+        let bid = cqe_buffer_id(flags);
+        let cqe = FakeCqe::buffer(bid, res as u32, true);
+        let id = ring.buffer_id_from_fields(&cqe).unwrap().unwrap();
+        assert_eq!(id.buffer(), b"data");
+        drop(id);
+        assert_eq!(ring.bid_state(bid), BidState::Provided);
+
+        let (flags, res) = kernel.recv(&ring, b"data", true);
+        let bid = ring.resolve_raw(flags, res).unwrap().unwrap().bid;
+        assert_eq!(ring.bid_state(bid), BidState::HeldByApp);
+
+        ring.quarantine(bid).unwrap();
+        assert_eq!(ring.bid_state(bid), BidState::Quarantined);
+        assert_eq!(ring.refill_unprovided(), 0);
+        assert_eq!(ring.reserve(bid), Err(RingError::NotOwned { buf_id: bid }));
+
+        ring.lift_quarantine(bid).unwrap();
+        assert_eq!(ring.bid_state(bid), BidState::Parked);
+        ring.reserve(bid).unwrap();
+        assert_eq!(ring.bid_state(bid), BidState::Reserved);
+        assert_eq!((ring.reserved(), ring.parked()), (1, 0));
+        assert_eq!(ring.refill_unprovided(), 0);
+        assert_eq!(ring.provide_with_len(bid, 8), Err(RingError::NotOwned { buf_id: bid }));
+
+        ring.unreserve(bid).unwrap();
+        assert_eq!(ring.refill_unprovided(), 1);
+        assert_eq!(ring.bid_state(bid), BidState::Provided);
+        let states: Vec<_> = ring.bid_states().map(|(_, state)| state).collect();
+        assert_eq!(states, [BidState::Provided; 4]);
+        release(ring);
+    }
+
+    /// The highest buffer id of a full size ring survives the shift out of
+    /// the completion flags, from a fake completion and from the kernel.
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn highest_buffer_id_round_trips() {
+        let last = MAX_ENTRIES - 1;
+        let mut ring = sim_ring(MAX_ENTRIES, 1);
+        let cqe = FakeCqe::buffer(last, 1, false);
+        assert_eq!(cqe_buffer_id(cqe.flags), last);
+        let id = ring.buffer_id_from_fields(&cqe).unwrap().unwrap();
+        assert_eq!(id.buf_id(), last);
+        id.keep();
+        release(ring);
+
+        let mut uring = io_uring::IoUring::new(8).unwrap();
+        let (tx, rx) = std::os::unix::net::UnixStream::pair().unwrap();
+        let ring = BufRing::new(MAX_ENTRIES, 64, 4).unwrap();
+        let mut ring = ring.register(&uring.submitter()).map_err(|(e, _)| e).unwrap();
+        // SAFETY: in range and provided once
+        unsafe { ring.provide_in_order(&[last]) };
+        let mut ring = ring.init();
+
+        std::io::Write::write_all(&mut &tx, b"highest").unwrap();
+        let sqe = ring.recv_sqe(rx.as_raw_fd(), 0, 7);
+        unsafe { uring.submission().push(&sqe).unwrap() };
+        uring.submit_and_wait(1).unwrap();
+        let cqe = uring.completion().next().unwrap();
+        let id = ring.buffer_id_from_cqe(&cqe).unwrap().unwrap();
+        assert_eq!((id.buf_id(), id.buffer()), (last, &b"highest"[..]));
+        drop(id);
+
+        let ring = ring.unregister(&uring.submitter()).map_err(|(e, _)| e).unwrap();
+        drop(ring);
+    }
+
+    /// A big completion from a `CQE32` ring names its buffer like a normal one.
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn entry32_resolves_its_buffer() {
+        use io_uring::{cqueue, squeue};
+
+        let mut uring = io_uring::IoUring::<squeue::Entry, cqueue::Entry32>::builder()
+            .build(8)
+            .unwrap();
+        let (tx, rx) = std::os::unix::net::UnixStream::pair().unwrap();
+        let ring = BufRing::new(4, 64, 8).unwrap();
+        let mut ring = ring.register(&uring.submitter()).map_err(|(e, _)| e).unwrap();
+        // SAFETY: in range and provided once
+        unsafe { ring.provide_in_order(&[2]) };
+        let mut ring = ring.init();
+
+        std::io::Write::write_all(&mut &tx, b"big cqe").unwrap();
+        let sqe = ring.recv_sqe(rx.as_raw_fd(), 0, 9);
+        unsafe { uring.submission().push(&sqe).unwrap() };
+        uring.submit_and_wait(1).unwrap();
+        let cqe: cqueue::Entry32 = uring.completion().next().unwrap();
+        assert!(cqe_has_buffer(cqe.flags()));
+        let id = ring.buffer_id_from_cqe(&cqe).unwrap().unwrap();
+        assert_eq!((id.buf_id(), id.buffer()), (2, &b"big cqe"[..]));
+        drop(id);
+
+        let ring = ring.unregister(&uring.submitter()).map_err(|(e, _)| e).unwrap();
+        drop(ring);
+    }
+
+    /// Echoes messages through a two buffer ring resolved only with
+    /// `resolve_raw`, sending straight from the selected buffer and providing
+    /// it again by hand. Forgetting a buffer would run the ring dry within
+    /// three messages.
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn raw_mode_echo_never_stalls() {
+        use io_uring::{opcode, types};
+        use std::io::{Read, Write};
+
+        let mut uring = io_uring::IoUring::new(8).unwrap();
+        let (mut client, server) = std::os::unix::net::UnixStream::pair().unwrap();
+        let ring = BufRing::new(2, 64, 12).unwrap();
+        let mut ring = ring
+            .register_and_init(&uring.submitter())
+            .map_err(|(e, _)| e)
+            .unwrap();
+        let complete = |uring: &mut io_uring::IoUring, sqe: &io_uring::squeue::Entry| {
+            unsafe { uring.submission().push(sqe).unwrap() };
+            uring.submit_and_wait(1).unwrap();
+            let cqe = uring.completion().next().unwrap();
+            (cqe.flags(), cqe.result())
+        };
+
+        for i in 0..200u32 {
+            let msg = format!("message {i}");
+            client.write_all(msg.as_bytes()).unwrap();
+
+            let (flags, res) = complete(&mut uring, &ring.recv_sqe(server.as_raw_fd(), 0, 1));
+            let raw = ring.resolve_raw(flags, res).unwrap().unwrap();
+            assert_eq!((raw.offset, raw.len, raw.more), (0, msg.len() as u32, false));
+            assert_eq!(ring.bid_state(raw.bid), BidState::HeldByApp);
+
+            // SAFETY: in range, and held by the application
+            let buf = unsafe { &ring.buffer(raw.bid)[..raw.len as usize] };
+            let send = opcode::Send::new(types::Fd(server.as_raw_fd()), buf.as_ptr(), raw.len);
+            let (_, sent) = complete(&mut uring, &send.build().user_data(2));
+            assert_eq!(sent, raw.len as i32);
+            ring.provide_with_len(raw.bid, ring.buf_size()).unwrap();
+
+            let mut echo = vec![0; msg.len()];
+            client.read_exact(&mut echo).unwrap();
+            assert_eq!(echo, msg.as_bytes());
+        }
+        assert_eq!(ring.provided(), 2);
+        drop(ring.unregister(&uring.submitter()).map_err(|(e, _)| e).unwrap());
+    }
+
+    /// Takes the tail of a full size ring past 65535 twice, publishing in
+    /// batches that straddle the wrap, and checks every slot still names the
+    /// buffer written into it.
+    #[test]
+    #[cfg_attr(miri, ignore = "too slow under Miri")]
+    fn tail_wraps_on_a_full_size_ring() {
+        let mut ring = sim_ring(MAX_ENTRIES, 1);
+        let mut kernel = SimKernel::attach(&ring);
+        ring.set_deferred_advance(true);
+
+        let received = 2 * 65536 + 123;
+        for k in 0..received {
+            if kernel.available(&ring) == 0 {
+                ring.flush();
+            }
+            let (flags, res) = kernel.recv(&ring, b"x", true);
+            // returned in the order received, so each slot keeps its buffer
+            let bid = cqe_buffer_id(flags);
+            assert_eq!(bid, k as u16 & (MAX_ENTRIES - 1), "receive {k}");
+            drop(ring.buffer_id_from_fields(&FakeCqe { flags, res }).unwrap());
+        }
+        ring.flush();
+
+        let tail = (received + MAX_ENTRIES as u32) as u16;
+        assert_eq!(ring.published_tail(), tail);
+        assert_eq!(ring.shared_tail().load(Ordering::Acquire), tail);
+        assert_eq!(ring.provided(), MAX_ENTRIES);
+        assert_eq!(ring.verify(), Ok(()));
+        release(ring);
+    }
+
+    /// Completions the kernel produced but the ring hasn't seen make the
+    /// estimate drift above what the kernel holds, until `reconcile` reads
+    /// the kernel's head. Resolving them afterwards keeps it exact.
+    #[test]
+    fn reconcile_corrects_the_held_estimate() {
+        let mut ring = sim_ring(8, 32);
+        let mut kernel = SimKernel::attach(&ring);
+        let mut rng = Rng(0xbead);
+        let mut queued = std::collections::VecDeque::new();
+
+        for _ in 0..2_000 {
+            match rng.below(3) {
+                0 => {
+                    let cqe = kernel.recv(&ring, b"x", true);
+                    if cqe.1 > 0 {
+                        queued.push_back(cqe);
+                    }
+                }
+                1 => {
+                    if let Some((flags, res)) = queued.pop_front() {
+                        drop(ring.buffer_id_from_fields(&FakeCqe { flags, res }).unwrap());
+                    }
+                }
+                _ => {
+                    ring.reconcile(kernel.head());
+                    assert_eq!(ring.unreaped(), queued.len() as u16);
+                    assert_eq!(ring.kernel_held_estimate(), kernel.available(&ring));
+                }
+            }
+            // only ever above, by at most the completions not resolved
+            let estimate = ring.kernel_held_estimate();
+            assert!(estimate >= kernel.available(&ring));
+            assert!(estimate - kernel.available(&ring) <= queued.len() as u16);
+        }
+
+        ring.reconcile(kernel.head());
+        assert_eq!(ring.kernel_held_estimate(), kernel.available(&ring));
+        for (flags, res) in queued.drain(..) {
+            drop(ring.buffer_id_from_fields(&FakeCqe { flags, res }).unwrap());
+            assert_eq!(ring.kernel_held_estimate(), kernel.available(&ring));
+        }
+        assert_eq!(ring.kernel_held_estimate(), 8);
+        release(ring);
+    }
+
+    /// A shortened provision caps what the next recv writes and what its
+    /// `BufferId` exposes, and the buffer is back at full size the cycle after.
+    #[test]
+    fn short_provision_lasts_one_cycle() {
+        let mut ring = sim_ring(1, 32);
+        let mut kernel = SimKernel::attach(&ring);
+        let payload = [7; 32];
+
+        let (flags, res) = kernel.recv(&ring, &payload, true);
+        let bid = ring.resolve_raw(flags, res).unwrap().unwrap().bid;
+        let invalid = |len| Err(RingError::InvalidLen { len, capacity: 32 });
+        assert_eq!(ring.provide_with_len(bid, 0), invalid(0));
+        assert_eq!(ring.provide_with_len(bid, 33), invalid(33));
+        ring.provide_with_len(bid, 8).unwrap();
+        assert_eq!(ring.snapshot_entries()[0].len, 8);
+
+        let (flags, res) = kernel.recv(&ring, &payload, true);
+        assert_eq!(res, 8);
+        let cqe = FakeCqe { flags, res };
+        let id = ring.buffer_id_from_fields(&cqe).unwrap().unwrap();
+        assert_eq!(id.buffer(), [7; 8]);
+        drop(id);
+        assert_eq!(ring.snapshot_entries()[0].len, 32);
+
+        let (flags, res) = kernel.recv(&ring, &payload, true);
+        assert_eq!(res, 32);
+        let cqe = FakeCqe { flags, res };
+        let id = ring.buffer_id_from_fields(&cqe).unwrap().unwrap();
+        assert_eq!(id.buffer(), payload);
+        drop(id);
+        release(ring);
+    }
+
+    /// Deferred buffers reach the kernel on `flush`, once more than half the
+    /// ring is waiting, or when deferring is turned off.
+    #[test]
+    fn deferred_advance_publishes_on_flush_or_past_half() {
+        let mut ring = sim_ring(8, 32);
+        let mut kernel = SimKernel::attach(&ring);
+        ring.set_deferred_advance(true);
+
+        let mut held: Vec<_> = (0..8)
+            .map(|_| detach_next(&mut ring, &mut kernel, b"x"))
+            .collect();
+        for waiting in 1..=4 {
+            ring.recycle_many([held.pop().unwrap()]).unwrap();
+            assert_eq!(kernel.available(&ring), 0, "{waiting} waiting");
+        }
+        // the fifth is more than half the ring
+        ring.recycle_many([held.pop().unwrap()]).unwrap();
+        assert_eq!(kernel.available(&ring), 5);
+
+        ring.recycle_many(held).unwrap();
+        assert_eq!(kernel.available(&ring), 5);
+        ring.flush();
+        assert_eq!(kernel.available(&ring), 8);
+
+        let buf = detach_next(&mut ring, &mut kernel, b"x");
+        ring.recycle_many([buf]).unwrap();
+        assert_eq!(kernel.available(&ring), 7);
+        ring.set_deferred_advance(false);
+        assert_eq!(kernel.available(&ring), 8);
+        release(ring);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "advancing by 2 with only 1 slots written")]
+    fn advance_past_written_slots() {
+        let mut ring = sim_ring(4, 32);
+        let mut kernel = SimKernel::attach(&ring);
+        ring.set_deferred_advance(true);
+        let buf = detach_next(&mut ring, &mut kernel, b"x");
+        ring.recycle_many([buf]).unwrap();
+        unsafe { ring.advance(2) };
+    }
+
+    /// The entry array with each `addr` relative to the ring's buffers.
+    fn entry_image<S>(ring: &BufRing<S>) -> Vec<u8> {
+        let len = ring.entries as usize * ENTRY_SIZE;
+        // SAFETY: the entry array lives as long as `ring`
+        let mut image = unsafe { core::slice::from_raw_parts(ring.base.cast::<u8>(), len) }.to_vec();
+        for entry in image.chunks_exact_mut(ENTRY_SIZE) {
+            let addr = u64::from_ne_bytes(entry[..8].try_into().unwrap());
+            let offset = addr - ring.buffer_base as u64;
+            entry[..8].copy_from_slice(&offset.to_ne_bytes());
+        }
+        image
+    }
+
+    /// The bulk write in `init` leaves the entry array exactly as providing
+    /// each buffer on its own at the tail did.
+    #[test]
+    #[cfg_attr(miri, ignore = "too slow under Miri")]
+    fn init_matches_per_entry_provision() {
+        let bulk = BufRing::new(MAX_ENTRIES, 1, 0).unwrap().register_simulated();
+
+        let mut per_entry = BufRing::new(MAX_ENTRIES, 1, 0).unwrap().into_registered();
+        for buf_id in 0..per_entry.entries() {
+            // SAFETY: in range, and nothing reads the ring
+            unsafe {
+                let tail = per_entry.shared_tail().load(Ordering::Acquire);
+                let entry = &mut *per_entry.entry_ptr(tail.wrapping_add(buf_id) as u32);
+                entry.set_addr(per_entry.get_buffer(buf_id) as u64);
+                entry.set_len(per_entry.buf_size());
+                entry.set_bid(buf_id);
+            }
+        }
+        per_entry.shared_tail().store(MAX_ENTRIES, Ordering::Release);
+
+        assert!(entry_image(&bulk) == entry_image(&per_entry));
+        release(bulk);
+        per_entry.reset_registration();
+    }
+
+    /// Returns taking the static layout path still leave every published slot
+    /// valid when buffers come back to slots other than their own.
+    #[test]
+    fn static_layout_out_of_order() {
+        let mut ring = sim_ring(8, 32);
+        let mut kernel = SimKernel::attach(&ring);
+        assert!(ring.static_layout);
+
+        // buffers provided with a short entry, which the next full one written
+        // into their slot has to undo
+        let mut shorts = Vec::new();
+        for round in 0..4u8 {
+            let mut bufs: Vec<_> = (0..6)
+                .map(|_| detach_next(&mut ring, &mut kernel, &[round; 32]))
+                .collect();
+            shorts.retain(|&bid| bufs.iter().all(|buf| buf.buf_id() != bid));
+            bufs.reverse();
+            bufs.rotate_left(round as usize);
+            let short = bufs.pop().unwrap().buf_id();
+            ring.recycle_many(bufs).unwrap();
+            ring.provide_with_len(short, 8).unwrap();
+            shorts.push(short);
+            assert_eq!(ring.verify(), Ok(()));
+
+            for slot in ring.published_tail().wrapping_sub(8)..ring.published_tail() {
+                let entry = ring.entry_ref(slot & 7).unwrap();
+                let start = unsafe { ring.get_buffer(entry.bid()) } as u64;
+                assert_eq!(entry.addr(), start, "round {round}, slot {slot}");
+                let len = if shorts.contains(&entry.bid()) { 8 } else { 32 };
+                assert_eq!(entry.len(), len, "round {round}, slot {slot}");
+            }
+        }
+
+        // the kernel receives into the buffer each slot names
+        let cqes = kernel.exhaust(&ring, b"last");
+        for &(flags, res) in &cqes[..cqes.len() - 1] {
+            let cqe = FakeCqe { flags, res };
+            let id = ring.buffer_id_from_fields(&cqe).unwrap().unwrap();
+            assert_eq!(id.buffer(), b"last");
+            id.keep();
+        }
+        release(ring);
+    }
+
+    /// Entry writes, buffer slicing, the tail and bundles copied across the
+    /// wrap, on the heap backed ring Miri runs instead of the mapping.
+    #[test]
+    #[cfg(miri)]
+    fn miri_round_trip() {
+        let ring = BufRing::new(4, 16, 0).unwrap().register_for_miri();
+        let mut ring = ring.init();
+        let mut kernel = SimKernel::attach(&ring);
+
+        for round in 0..6u8 {
+            let (flags, res) = kernel.recv(&ring, &[round; 16], true);
+            let cqe = FakeCqe { flags, res };
+            let id = ring.buffer_id_from_fields(&cqe).unwrap().unwrap();
+            assert_eq!(id.buffer(), [round; 16]);
+        }
+        let first = detach_next(&mut ring, &mut kernel, b"first");
+        let second = detach_next(&mut ring, &mut kernel, b"second");
+        assert_eq!(&*second, b"second");
+        ring.recycle_many([second, first]).unwrap();
+
+        // crosses the two swapped buffers, so it's copied out
+        let (flags, res) = kernel.recv_bundle(&ring, &[9; 40], true);
+        let bundle = ring.bundle_from_fields(&FakeCqe { flags, res }).unwrap().unwrap();
+        assert!(!bundle.is_contiguous());
+        assert_eq!(bundle.data(), [9; 40]);
+        drop(bundle);
+
+        assert_eq!(ring.verify(), Ok(()));
+        assert_eq!(unsafe { &*ring.tail_ptr() }.load(Ordering::Acquire), ring.published_tail());
+        drop(ring.unregister_for_miri());
+    }
+
+    #[test]
+    fn verify_reports_corrupted_entries() {
+        let mut ring = sim_ring(8, 32);
+        assert_eq!(ring.verify(), Ok(()));
+        let start = |ring: &BufRing<_>, bid| unsafe { ring.get_buffer(bid) } as u64;
+
+        ring.entry_mut(2).unwrap().set_bid(100);
+        assert_eq!(
+            ring.verify(),
+            Err(vec![InvariantViolation::BidOutOfRange { slot: 2, bid: 100 }])
+        );
+        ring.entry_mut(2).unwrap().set_bid(5);
+        let violations = ring.verify().unwrap_err();
+        assert_eq!(
+            violations,
+            [InvariantViolation::WrongBid {
+                slot: 2,
+                expected: 2,
+                found: 5
+            }]
+        );
+        assert_eq!(violations[0].to_string(), "slot 2: buffer 5 instead of 2");
+        ring.entry_mut(2).unwrap().set_bid(2);
+
+        let addr = start(&ring, 3) + 1;
+        ring.entry_mut(3).unwrap().set_addr(addr);
+        ring.entry_mut(4).unwrap().set_len(33);
+        ring.entry_mut(6).unwrap().set_len(0);
+        assert_eq!(
+            ring.verify(),
+            Err(vec![
+                InvariantViolation::BadAddr { slot: 3, bid: 3, addr },
+                InvariantViolation::BadLen {
+                    slot: 4,
+                    bid: 4,
+                    len: 33,
+                    capacity: 32
+                },
+                InvariantViolation::BadLen {
+                    slot: 6,
+                    bid: 6,
+                    len: 0,
+                    capacity: 32
+                },
+            ])
+        );
+
+        let addr = start(&ring, 3);
+        ring.entry_mut(3).unwrap().set_addr(addr);
+        ring.entry_mut(4).unwrap().set_len(32);
+        // shorter than the buffer is fine
+        ring.entry_mut(6).unwrap().set_len(8);
+        assert_eq!(ring.verify(), Ok(()));
+        release(ring);
+    }
+
+    #[test]
+    fn debug_snapshot() {
+        let ring = BufRing::new(4, 32, 7).unwrap();
+        assert_eq!(
+            format!("{ring:?}"),
+            "BufRing { bgid: 7, entries: 4, buf_size: 32, state: Uninit, tail: 0, \
+             provided: 0, held_by_app: 0, parked: 0, mmap_size: 192 }"
+        );
+
+        let mut ring = ring.register_simulated();
+        let mut kernel = SimKernel::attach(&ring);
+        let buf = detach_next(&mut ring, &mut kernel, b"x");
+        assert_eq!(
+            format!("{ring:?}"),
+            "BufRing { bgid: 7, entries: 4, buf_size: 32, state: Init, tail: 4, \
+             provided: 3, held_by_app: 1, parked: 0, mmap_size: 192 }"
+        );
+
+        // addresses only in the alternate form
+        let fields = "    bgid: 7,\n    entries: 4,\n    buf_size: 32,\n    state: Init,\n    \
+                      tail: 4,\n    provided: 3,\n    held_by_app: 1,\n    parked: 0,\n    \
+                      mmap_size: 192,\n";
+        let (base, buffer_base) = (ring.base, ring.buffer_base);
+        assert_eq!(
+            format!("{ring:#?}"),
+            format!("BufRing {{\n{fields}    base: {base:#?},\n    buffer_base: {buffer_base:#?},\n}}")
+        );
+        assert!(!format!("{ring:?}").contains("0x"));
+
+        ring.recycle_many([buf]).unwrap();
+        release(ring);
+    }
+
+    /// Every buffer is in exactly one state, whichever path moved it there.
+    #[track_caller]
+    fn assert_counts(ring: &BufRing<state::Init>) {
+        let counts = [
+            ring.provided(),
+            ring.held_by_app(),
+            ring.unprovided(),
+            ring.reserved(),
+            ring.quarantined(),
+        ];
+        assert_eq!(counts.iter().sum::<u16>(), ring.entries(), "{counts:?}");
+        assert_eq!(ring.unprovided(), ring.parked());
+    }
+
+    #[test]
+    fn counts_sum_to_entries() {
+        let mut ring = sim_ring(8, 32);
+        let mut kernel = SimKernel::attach(&ring);
+        assert_counts(&ring);
+
+        let detached = [
+            detach_next(&mut ring, &mut kernel, b"a"),
+            detach_next(&mut ring, &mut kernel, b"b"),
+        ];
+        assert_eq!(ring.held_by_app(), 2);
+        assert_counts(&ring);
+
+        let (flags, res) = kernel.recv(&ring, b"kept", true);
+        let cqe = FakeCqe { flags, res };
+        let kept = ring.buffer_id_from_fields(&cqe).unwrap().unwrap().keep();
+        assert_counts(&ring);
+
+        let (flags, res) = kernel.recv_bundle(&ring, &[7; 80], true);
+        let bundle = ring.bundle_from_fields(&FakeCqe { flags, res }).unwrap().unwrap();
+        assert_eq!(bundle.buffers(), 3);
+        drop(bundle);
+        assert_eq!(ring.held_by_app(), 3);
+        assert_counts(&ring);
+
+        // parks what comes back from here on
+        ring.set_provision_limit(2);
+        let [a, b] = detached;
+        ring.recycle_many([a, b]).unwrap();
+        assert_eq!(ring.parked(), 2);
+        assert_counts(&ring);
+
+        let bid = ring.take_free().unwrap().buf_id();
+        assert_eq!(ring.parked(), 2);
+        ring.reserve(bid).unwrap();
+        assert_counts(&ring);
+        ring.quarantine(kept).unwrap();
+        assert_counts(&ring);
+
+        ring.clear_provision_limit();
+        ring.lift_quarantine(kept).unwrap();
+        ring.unreserve(bid).unwrap();
+        assert_eq!(ring.refill_unprovided(), 2);
+        assert_eq!(ring.provided(), ring.entries());
+        assert_counts(&ring);
+        release(ring);
+    }
+
+    struct Rng(u32);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 as usize % n
+        }
+    }
+
+    /// Returns buffers through every path in random order, with deferred
+    /// advance toggled at random, while a consumer thread reads each entry
+    /// the way the kernel does. Consuming a buffer that wasn't returned, or an
+    /// entry that wasn't fully written, fails the test.
+    #[test]
+    #[cfg_attr(miri, ignore = "too slow under Miri")]
+    fn stress_adversarial_return_orders() {
+        use super::model::ModelKernel;
+        use crate::kept::KeptBuffers;
+        use std::sync::atomic::AtomicBool;
+        use std::sync::mpsc::{TryRecvError, channel};
+
+        const ENTRIES: u16 = 64;
+        const ROUNDS: usize = 100_000;
+
+        let mut ring = sim_ring(ENTRIES, 32);
+        // whether each buffer was returned and not consumed since
+        let returned: Vec<AtomicBool> = (0..ENTRIES).map(|_| AtomicBool::new(true)).collect();
+        let kernel = ModelKernel::attach(&ring);
+        let (tx, rx) = channel();
+
+        std::thread::scope(|s| {
+            let returned = &returned;
+            s.spawn(move || {
+                let mut head = 0u16;
+                for _ in 0..ROUNDS {
+                    let bid = loop {
+                        match kernel.take(head) {
+                            Some(bid) => break bid,
+                            None => std::thread::yield_now(),
+                        }
+                    };
+                    assert!(
+                        returned[bid as usize].swap(false, Ordering::Acquire),
+                        "buffer {bid} consumed from slot {head} without being returned"
+                    );
+                    head = head.wrapping_add(1);
+                    tx.send(bid).unwrap();
+                }
+            });
+
+            let mut rng = Rng(0x2545_f491);
+            let mut detached = Vec::new();
+            let mut kept = KeptBuffers::new();
+            let return_all = |ring: &mut BufRing<state::Init>,
+                              detached: &mut Vec<OwnedBuffer>,
+                              rng: &mut Rng| {
+                for i in (1..detached.len()).rev() {
+                    detached.swap(i, rng.below(i + 1));
+                }
+                for buf in detached.iter() {
+                    returned[buf.buf_id() as usize].store(true, Ordering::Release);
+                }
+                ring.recycle_many(detached.drain(..)).unwrap();
+            };
+            loop {
+                let bid = match rx.try_recv() {
+                    Ok(bid) => bid,
+                    Err(TryRecvError::Empty) => {
+                        // the consumer may be waiting on anything held back
+                        return_all(&mut ring, &mut detached, &mut rng);
+                        for &bid in kept.ids() {
+                            returned[bid as usize].store(true, Ordering::Release);
+                        }
+                        kept.reprovide_all(&mut ring);
+                        ring.flush();
+                        std::thread::yield_now();
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => break,
+                };
+                if rng.below(64) == 0 {
+                    ring.set_deferred_advance(!ring.deferred_advance());
+                }
+
+                let cqe = FakeCqe::buffer(bid, 32, true);
+                let id = ring.buffer_id_from_fields(&cqe).unwrap().unwrap();
+                match rng.below(5) {
+                    0 => kept.keep(id),
+                    // SAFETY: every buffer is returned before the ring is released
+                    1 => detached.push(unsafe { id.detach() }),
+                    choice => {
+                        returned[bid as usize].store(true, Ordering::Release);
+                        if choice == 2 {
+                            id.recycle_now();
+                        } else {
+                            drop(id);
+                        }
+                    }
+                }
+                if detached.len() > 8 {
+                    return_all(&mut ring, &mut detached, &mut rng);
+                }
+            }
+            return_all(&mut ring, &mut detached, &mut rng);
+            kept.reprovide_all(&mut ring);
+        });
+
+        ring.flush();
+        assert_eq!(ring.provided(), ENTRIES);
+        assert_eq!(ring.verify(), Ok(()));
+        release(ring);
+    }
+
+    /// Registers the same ring over and over, providing a random prefix of a
+    /// shuffled order before `init` provides the rest, while a consumer
+    /// thread reads every registration from head 0 the way the kernel does.
+    /// An entry left over from an earlier registration, or one read before
+    /// it was written, names the wrong buffer and fails the test.
+    #[test]
+    #[cfg_attr(miri, ignore = "too slow under Miri")]
+    fn stress_reregistration_never_shows_stale_entries() {
+        use super::model::ModelKernel;
+        use std::sync::mpsc::channel;
+
+        const ENTRIES: u16 = 16;
+        const ROUNDS: usize = 20_000;
+
+        let ring = sim_ring(ENTRIES, 32);
+        let kernel = ModelKernel::attach(&ring);
+        let mut ring = ring.unregister_simulated();
+        let (order_tx, order_rx) = channel::<Vec<u16>>();
+        let (done_tx, done_rx) = channel();
+
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                for expected in order_rx {
+                    // every registration starts the head at 0
+                    for (head, &want) in expected.iter().enumerate() {
+                        let bid = loop {
+                            match kernel.take(head as u16) {
+                                Some(bid) => break bid,
+                                None => std::thread::yield_now(),
+                            }
+                        };
+                        assert_eq!(bid, want, "slot {head} held a stale entry");
+                    }
+                    done_tx.send(()).unwrap();
+                }
+            });
+
+            let mut rng = Rng(0x7a11);
+            for _ in 0..ROUNDS {
+                let mut order: Vec<u16> = (0..ENTRIES).collect();
+                for i in (1..order.len()).rev() {
+                    order.swap(i, rng.below(i + 1));
+                }
+                let (first, rest) = order.split_at(rng.below(ENTRIES as usize + 1));
+                // `init` provides whatever is left in id order
+                let mut expected = first.to_vec();
+                let mut rest = rest.to_vec();
+                rest.sort_unstable();
+                expected.append(&mut rest);
+                order_tx.send(expected).unwrap();
+
+                let mut registered = ring.into_registered();
+                // SAFETY: a permutation of the ids, none provided yet
+                unsafe { registered.provide_in_order(first) };
+                let registered = registered.init();
+                assert_eq!(registered.provided(), ENTRIES);
+                done_rx.recv().unwrap();
+                ring = registered.unregister_simulated();
+            }
+            drop(order_tx);
+        });
+    }
+
+    /// At low load a LIFO ring keeps handing the kernel the same few buffers,
+    /// and turning it on and off leaves the provision limit alone.
+    #[test]
+    fn lifo_reuses_hot_buffers() {
+        let mut ring = sim_ring(16, 32);
+        let mut kernel = SimKernel::attach(&ring);
+        ring.set_provision_limit(12);
+        ring.enable_lifo(2);
+        assert_eq!((ring.lifo_depth(), ring.provision_limit()), (Some(2), Some(12)));
+
+        // nothing provided is recalled, the stack fills as buffers come back
+        let mut held = Vec::new();
+        while kernel.available(&ring) > 0 {
+            held.push(detach_next(&mut ring, &mut kernel, b"x"));
+        }
+        ring.recycle_many(held).unwrap();
+        assert_eq!((ring.provided(), ring.parked()), (2, 14));
+        assert_counts(&ring);
+
+        let mut seen = std::collections::BTreeSet::new();
+        for _ in 0..100 {
+            let buf = detach_next(&mut ring, &mut kernel, b"ping");
+            seen.insert(buf.buf_id());
+            ring.recycle_many([buf]).unwrap();
+            assert_eq!(ring.provided(), 2);
+        }
+        // the two provided and the one just returned
+        assert_eq!(seen.len(), 3);
+
+        ring.disable_lifo();
+        assert_eq!((ring.lifo_depth(), ring.provision_limit()), (None, Some(12)));
+        assert_eq!((ring.provided(), ring.parked()), (12, 4));
+        ring.clear_provision_limit();
+        assert_eq!(ring.provided(), 16);
+        assert_counts(&ring);
+        release(ring);
+    }
+
+    /// Returns and takes buffers at random in LIFO mode, with the depth, the
+    /// mode and the provision limit changing along the way, and checks after
+    /// every step that no buffer is published twice between the kernel's
+    /// head and the tail.
+    #[test]
+    #[cfg_attr(miri, ignore = "too slow under Miri")]
+    fn lifo_never_provides_a_buffer_twice() {
+        use super::model::ModelKernel;
+
+        const ENTRIES: u16 = 32;
+
+        let mut ring = sim_ring(ENTRIES, 32);
+        let kernel = ModelKernel::attach(&ring);
+        let mut rng = Rng(0x1f0);
+        let mut head = 0u16;
+        let mut held = Vec::new();
+        ring.enable_lifo(4);
+
+        for _ in 0..20_000 {
+            match rng.below(16) {
+                0 => ring.enable_lifo(1 + rng.below(8) as u16),
+                1 => ring.disable_lifo(),
+                2 => ring.set_provision_limit(rng.below(ENTRIES as usize) as u16),
+                3 => ring.clear_provision_limit(),
+                4..=9 => {
+                    if let Some(bid) = kernel.take(head) {
+                        head = head.wrapping_add(1);
+                        let cqe = FakeCqe::buffer(bid, 32, true);
+                        let id = ring.buffer_id_from_fields(&cqe).unwrap().unwrap();
+                        // SAFETY: every buffer is returned before the ring is released
+                        held.push(unsafe { id.detach() });
+                    }
+                }
+                _ => {
+                    if !held.is_empty() {
+                        let buf = held.swap_remove(rng.below(held.len()));
+                        ring.recycle_many([buf]).unwrap();
+                    }
+                }
+            }
+
+            let published = ring.published_tail().wrapping_sub(head);
+            let mut bids: Vec<_> = (0..published)
+                .map(|i| unsafe { &*ring.entry_ptr(head.wrapping_add(i) as u32) }.bid())
+                .collect();
+            assert!(bids.iter().all(|&bid| ring.bid_state(bid) == BidState::Provided));
+            bids.sort_unstable();
+            bids.dedup();
+            assert_eq!(bids.len(), published as usize, "a buffer was provided twice");
+            assert_eq!(published, ring.provided());
+            assert_counts(&ring);
+        }
+
+        ring.recycle_many(held).unwrap();
+        ring.disable_lifo();
+        ring.clear_provision_limit();
+        assert_eq!(ring.provided(), ENTRIES);
+        release(ring);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::model::ModelKernel;
+    use super::*;
+
+    fn kernel_never_sees_unwritten_entries(single_producer: bool) {
+        loom::model(move || {
+            let ring = BufRing::new(4, 64, 0).unwrap().into_registered();
+            let mut ring = ring.init_parked();
+            if single_producer {
+                ring.set_single_producer(true);
+            }
+            let kernel = ModelKernel::attach(&ring);
+            let consumer = loom::thread::spawn(move || {
+                let head = kernel.consume(0);
+                kernel.consume(head)
+            });
+
+            ring.provide_with_len(0, 64).unwrap();
+            ring.provide_with_len(1, 64).unwrap();
+
+            let head = consumer.join().unwrap();
+            assert!(head <= 2);
+            drop(ring.unregister_simulated());
+        });
+    }
+
+    #[test]
+    fn loom_publication_fetch_add() {
+        kernel_never_sees_unwritten_entries(false);
+    }
+
+    #[test]
+    fn loom_publication_single_producer_store() {
+        kernel_never_sees_unwritten_entries(true);
+    }
+}
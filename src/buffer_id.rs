@@ -1,4 +1,7 @@
-use crate::buf_ring::{BufRing, state};
+use crate::buf_ring::{BidState, BufRing, state};
+use crate::cqe::{cqe_buffer_id, cqe_has_buffer};
+use crate::kept::KeptBuffer;
+use crate::owned_buffer::OwnedBuffer;
 use io_uring::cqueue::{Entry, EntryMarker};
 
 use core::marker::PhantomData;
@@ -7,50 +10,268 @@ pub struct BufferId<'a, 'b, E: EntryMarker> {
     buf: &'a mut BufRing<state::Init>,
     buf_id: u16,
     cqe_res: i32,
-    marker: PhantomData<&'b E>,
+    offset: u32,
+    /// Whether the application owns the buffer, false while the kernel keeps
+    /// filling it in incremental mode.
+    held: bool,
+    /// Ties the id to its completion, and keeps it on the ring's thread.
+    marker: PhantomData<(&'b E, *const ())>,
 }
 
 impl<'a, 'b, E: EntryMarker> BufferId<'a, 'b, E> {
+    #[cfg_attr(feature = "watchdog", track_caller)]
     pub(crate) fn new(buf: &'a mut BufRing<state::Init>, cqe: &'b E) -> std::io::Result<Option<Self>> {
+        // an `Entry32` converts to its leading `Entry`, which holds `res` and
+        // `flags` at the same offsets, so big CQEs resolve the same way
+        let e: Entry = cqe.clone().into();
+        Self::from_flags(buf, e.flags(), e.result())
+    }
+
+    /// Like `new`, from the `flags` and `res` of a completion.
+    #[cfg_attr(feature = "watchdog", track_caller)]
+    pub(crate) fn from_flags(
+        buf: &'a mut BufRing<state::Init>,
+        flags: u32,
+        cqe_res: i32,
+    ) -> std::io::Result<Option<Self>> {
         // io_uring doesn't expose its sys bindings
         // so they've been redefined here.
-        const IORING_CQE_F_BUFFER: libc::c_uint = 1;
-        const IORING_CQE_BUFFER_SHIFT: libc::c_uint = 16;
-
-        let e: Entry = cqe.clone().into();
-        let flags = e.flags();
-        let cqe_res = e.result();
+        const IORING_CQE_F_BUF_MORE: libc::c_uint = 1 << 4;
+        #[cfg(feature = "tracing")]
+        const IORING_CQE_F_MORE: libc::c_uint = 1 << 1;
 
         if cqe_res < 0 {
+            if -cqe_res == libc::ENOBUFS {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(bgid = buf.bgid(), "recv failed with ENOBUFS");
+                #[cfg(feature = "stats")]
+                {
+                    buf.stats_mut().enobufs += 1;
+                }
+                buf.observe(|o| o.on_enobufs());
+                #[cfg(feature = "metrics")]
+                if let Some(metrics) = buf.metrics() {
+                    metrics.enobufs();
+                }
+            }
             return Err(std::io::Error::from_raw_os_error(-cqe_res));
         }
 
-        let buf_id = if flags & IORING_CQE_F_BUFFER == 0 {
+        #[cfg(feature = "tracing")]
+        if flags & IORING_CQE_F_MORE == 0 {
+            tracing::debug!(
+                bgid = buf.bgid(),
+                res = cqe_res,
+                "completion without IORING_CQE_F_MORE, multishot terminated"
+            );
+        }
+
+        if !cqe_has_buffer(flags) {
             return Ok(None);
-        } else {
-            (flags >> IORING_CQE_BUFFER_SHIFT) as u16
-        };
+        }
+        let buf_id = cqe_buffer_id(flags);
+        if buf_id >= buf.entries() {
+            // the completion wasn't for this ring, nothing is consumed
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("buffer id {buf_id} out of range"),
+            ));
+        }
+        let held = flags & IORING_CQE_F_BUF_MORE == 0;
+        let offset = buf.consume(buf_id, cqe_res as u32, !held);
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = buf.metrics() {
+            metrics.completion(cqe_res as u32);
+        }
+        if held {
+            buf.set_bid_state(buf_id, BidState::HeldByApp);
+            buf.observe(|o| o.on_acquire(buf_id, cqe_res as u32));
+            #[cfg(feature = "tracing")]
+            tracing::trace!(bgid = buf.bgid(), bid = buf_id, len = cqe_res, "acquire");
+            #[cfg(feature = "watchdog")]
+            buf.watchdog_mut()
+                .acquire(buf_id, core::panic::Location::caller());
+            // SAFETY: an initialized ring is registered
+            unsafe { buf.top_up() };
+        }
+
+        #[cfg(feature = "stats")]
+        {
+            let held_by_app = buf.held_by_app();
+            let buf_size = buf.buf_size();
+            let stats = buf.stats_mut();
+            stats.record_completion(cqe_res as u32, buf_size);
+            stats.max_held = stats.max_held.max(held_by_app);
+        }
 
         Ok(Some(Self {
             buf,
             buf_id,
             cqe_res,
+            offset,
+            held,
             marker: PhantomData,
         }))
     }
 
+    /// the id of the buffer within its ring
+    pub fn buf_id(&self) -> u16 {
+        self.buf_id
+    }
+
+    /// the buffer group of the ring the buffer belongs to
+    pub fn bgid(&self) -> u16 {
+        self.buf.bgid()
+    }
+
+    /// the metadata attached to this buffer with [`BufRing::with_metadata`]
+    pub fn metadata<T: 'static>(&self) -> Option<&T> {
+        self.buf.meta(self.buf_id)
+    }
+
+    pub fn metadata_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.buf.meta_mut(self.buf_id)
+    }
+
     /// gives the associated buf ring buffer associated with the CQE entry
     pub fn buffer(&self) -> &[u8] {
         // SAFETY
         // `buf_id` is guaranteed to be a valid index into the buf ring
-        // and 0 < `cqe_res` < buf.len()
-        unsafe { &self.buf.buffer(self.buf_id)[..(self.cqe_res as _)] }
+        // and `offset + cqe_res` <= buf.len()
+        let start = self.offset as usize;
+        unsafe { &self.buf.buffer(self.buf_id)[start..start + self.cqe_res as usize] }
+    }
+
+    /// the received data as UTF-8 text
+    ///
+    /// The `&str` borrows the buffer, so it can't outlive this `BufferId`.
+    pub fn as_str(&self) -> Result<&str, core::str::Utf8Error> {
+        core::str::from_utf8(self.buffer())
+    }
+
+    /// appends the received data to `dst`
+    #[cfg(feature = "bytes")]
+    pub fn copy_to_bytes_mut(&self, dst: &mut bytes::BytesMut) {
+        dst.extend_from_slice(self.buffer());
+    }
+
+    /// interprets the start of the received data as a `T`
+    ///
+    /// Returns `None` if fewer than `size_of::<T>()` bytes were received or the
+    /// buffer isn't suitably aligned for `T`.
+    #[cfg(feature = "zerocopy")]
+    pub fn buffer_as<T>(&self) -> Option<&T>
+    where
+        T: zerocopy::FromBytes + zerocopy::KnownLayout + zerocopy::Immutable,
+    {
+        T::ref_from_prefix(self.buffer()).ok().map(|(t, _)| t)
+    }
+
+    /// splits off the first length-prefixed frame of the buffer
+    ///
+    /// `len_field` is given the first `header_len` bytes and returns the length of
+    /// the frame body that follows them. Returns the body and everything after it,
+    /// or `None` if the buffer doesn't hold a complete frame.
+    pub fn split_first_frame(
+        &self,
+        header_len: usize,
+        len_field: impl Fn(&[u8]) -> usize,
+    ) -> Option<(&[u8], &[u8])> {
+        let buf = self.buffer();
+        let header = buf.get(..header_len)?;
+        let end = header_len.checked_add(len_field(header))?;
+        if end > buf.len() {
+            return None;
+        }
+        Some((&buf[header_len..end], &buf[end..]))
+    }
+}
+
+impl<'a, 'b, E: EntryMarker> BufferId<'a, 'b, E> {
+    /// keeps the buffer out of rotation instead of recycling it on drop
+    ///
+    /// The returned id stays held by the application until it is re-provided,
+    /// e.g. through [`KeptBuffers`](crate::KeptBuffers).
+    ///
+    /// # Panics
+    ///
+    /// If the kernel is still filling the buffer, see
+    /// [`RegistrationMode::Incremental`](crate::buf_ring::RegistrationMode::Incremental).
+    pub fn keep(self) -> u16 {
+        assert!(self.held, "buffer is still owned by the kernel");
+        let buf_id = self.buf_id;
+        core::mem::forget(self);
+        buf_id
+    }
+
+    /// returns the buffer to the kernel right away
+    ///
+    /// Unlike dropping the id this publishes it even with deferred advance
+    /// enabled, for when the data has been copied out and keeping the pool full
+    /// matters more than batching.
+    pub fn recycle_now(mut self) {
+        if self.held {
+            // SAFETY: `buf_id` came from the kernel, so the application holds it
+            unsafe { self.buf.recycle(self.buf_id) }
+            // already recycled, so dropping `self` doesn't do it again
+            self.held = false;
+        }
+        self.buf.flush();
+    }
+
+    /// keeps the buffer as a seekable reader over the received data
+    ///
+    /// The cursor no longer borrows the completion, and the buffer is recycled
+    /// when it is dropped.
+    ///
+    /// # Panics
+    ///
+    /// If the kernel is still filling the buffer.
+    pub fn into_cursor(self) -> std::io::Cursor<KeptBuffer<'a>> {
+        assert!(self.held, "buffer is still owned by the kernel");
+        let this = core::mem::ManuallyDrop::new(self);
+        // SAFETY: `this` is never used or dropped again
+        let ring = unsafe { core::ptr::read(&this.buf) };
+        let kept = KeptBuffer::new(ring, this.buf_id, this.offset, this.cqe_res as u32);
+        std::io::Cursor::new(kept)
+    }
+
+    /// the buffer id, offset and length of the received data, and whether the
+    /// application holds the buffer, without recycling it
+    pub(crate) fn into_parts(self) -> (u16, u32, u32, bool) {
+        let parts = (self.buf_id, self.offset, self.cqe_res as u32, self.held);
+        core::mem::forget(self);
+        parts
+    }
+
+    /// detaches the received data from the ring's borrow, e.g. to hand it to
+    /// another thread
+    ///
+    /// The buffer stays held by the application until the [`OwnedBuffer`] is
+    /// returned with [`BufRing::recycle_many`].
+    ///
+    /// # Safety
+    ///
+    /// The ring must outlive the returned buffer, and must not be unregistered
+    /// before it is returned.
+    ///
+    /// # Panics
+    ///
+    /// If the kernel is still filling the buffer.
+    pub unsafe fn detach(self) -> OwnedBuffer {
+        let data = self.buffer();
+        let (ptr, len) = (data.as_ptr(), data.len() as u32);
+        let bgid = self.bgid();
+        OwnedBuffer::new(ptr, len, self.keep(), bgid)
     }
 }
 
 impl<'a, 'b, E: EntryMarker> Drop for BufferId<'a, 'b, E> {
     fn drop(&mut self) {
-        unsafe { self.buf.advance(1) }
+        if self.held {
+            // SAFETY: `buf_id` came from the kernel, so the application holds it
+            unsafe { self.buf.recycle(self.buf_id) }
+        }
     }
 }
 
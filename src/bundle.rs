@@ -0,0 +1,120 @@
+use crate::buf_ring::{BidState, BufRing, state};
+
+use core::marker::PhantomData;
+
+/// The buffers filled by a single bundle recv, recycled on drop.
+///
+/// The kernel fills a bundle from consecutive ring slots, starting at the slot
+/// holding the completion's buffer id, so the ids that follow are read back
+/// from the entries.
+pub struct Bundle<'a> {
+    ring: &'a mut BufRing<state::Init>,
+    first_slot: u16,
+    count: u16,
+    len: u32,
+    contiguous: bool,
+    /// Borrows the ring, which stays on its thread.
+    not_send: PhantomData<*const ()>,
+}
+
+impl<'a> Bundle<'a> {
+    /// `buf_id` must have just been handed to the application by a completion
+    /// of `len` bytes.
+    pub(crate) fn new(ring: &'a mut BufRing<state::Init>, buf_id: u16, len: u32) -> Self {
+        let first_slot = ring.slot_of(buf_id);
+        let mut count = 0u16;
+        let mut covered = 0u32;
+        let mut contiguous = true;
+        let mut prev = buf_id;
+        loop {
+            // SAFETY: the slots up to the one filling `len` were consumed by
+            // the kernel, and nothing is written into the ring while it is
+            // borrowed here
+            let entry = unsafe { &*ring.entry_ptr(first_slot.wrapping_add(count) as u32) };
+            let bid = entry.bid();
+            if count > 0 {
+                contiguous &= bid == prev.wrapping_add(1);
+                ring.set_bid_state(bid, BidState::HeldByApp);
+            }
+            prev = bid;
+            covered += entry.len();
+            count += 1;
+            if covered >= len || count == ring.entries() {
+                break;
+            }
+        }
+
+        let mut bundle = Self {
+            ring,
+            first_slot,
+            count,
+            len,
+            contiguous,
+            not_send: PhantomData,
+        };
+        if !contiguous {
+            bundle.copy_to_scratch();
+        }
+        bundle
+    }
+
+    fn copy_to_scratch(&mut self) {
+        let mut scratch = core::mem::take(self.ring.bundle_scratch());
+        scratch.clear();
+        let mut left = self.len as usize;
+        for i in 0..self.count {
+            let bid = self.buf_id(i);
+            // SAFETY: the bid came from an entry the kernel consumed
+            let buf = unsafe { self.ring.buffer(bid) };
+            let n = left.min(buf.len());
+            scratch.extend_from_slice(&buf[..n]);
+            left -= n;
+        }
+        *self.ring.bundle_scratch() = scratch;
+    }
+
+    /// The id of the `i`th buffer of the bundle.
+    fn buf_id(&self, i: u16) -> u16 {
+        let slot = self.first_slot.wrapping_add(i);
+        // SAFETY: the slot belongs to the bundle
+        unsafe { (*self.ring.entry_ptr(slot as u32)).bid() }
+    }
+
+    /// The received data, read in place if the buffers are adjacent.
+    pub fn data(&self) -> &[u8] {
+        if self.contiguous {
+            let first = self.buf_id(0);
+            // SAFETY: the ids are consecutive, so they don't run past the pool
+            unsafe { &self.ring.buffers_contiguous(first, self.count)[..self.len as usize] }
+        } else {
+            self.ring.bundle_scratch_ref()
+        }
+    }
+
+    /// Whether `data` is read in place rather than from the scratch copy.
+    pub fn is_contiguous(&self) -> bool {
+        self.contiguous
+    }
+
+    /// Number of buffers the bundle spans.
+    pub fn buffers(&self) -> u16 {
+        self.count
+    }
+
+    /// The ids of the buffers the bundle spans, in order.
+    pub fn buf_ids(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..self.count).map(|i| self.buf_id(i))
+    }
+}
+
+impl Drop for Bundle<'_> {
+    fn drop(&mut self) {
+        // each id is read before it is pushed, and a push only ever writes the
+        // next free slot, which is at most the slot just read
+        for i in 0..self.count {
+            let bid = self.buf_id(i);
+            // SAFETY: every id of the bundle is held by the application
+            unsafe { self.ring.recycle(bid) };
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Forwarding received buffers to another task through a channel.
+//!
+//! One side submits recvs and resolves completions, the other processes the
+//! data. Each buffer travels as a [`ReturningBuffer`], which puts itself on a
+//! return queue shared with the [`Forwarder`] when dropped; the ring owner
+//! hands everything queued back to the kernel with [`Forwarder::reclaim`].
+
+use crate::buf_ring::{BufRing, state};
+use crate::owned_buffer::{OwnedBuffer, RecycleError};
+use io_uring::cqueue::EntryMarker;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::sync::{Arc, Mutex};
+
+type ReturnQueue = Arc<Mutex<Vec<OwnedBuffer>>>;
+
+/// A received buffer that returns itself to its ring's [`Forwarder`] on drop.
+pub struct ReturningBuffer {
+    buf: ManuallyDrop<OwnedBuffer>,
+    returns: ReturnQueue,
+}
+
+impl ReturningBuffer {
+    pub fn buf_id(&self) -> u16 {
+        self.buf.buf_id()
+    }
+
+    pub fn bgid(&self) -> u16 {
+        self.buf.bgid()
+    }
+}
+
+impl core::ops::Deref for ReturningBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Drop for ReturningBuffer {
+    fn drop(&mut self) {
+        // SAFETY: never used again
+        let buf = unsafe { ManuallyDrop::take(&mut self.buf) };
+        self.returns
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(buf);
+    }
+}
+
+/// Sends the buffers of resolved completions through a bounded channel.
+pub struct Forwarder {
+    tx: async_channel::Sender<ReturningBuffer>,
+    returns: ReturnQueue,
+}
+
+/// Creates a forwarder and the receiving end of its channel.
+pub fn forwarder(capacity: usize) -> (Forwarder, async_channel::Receiver<ReturningBuffer>) {
+    let (tx, rx) = async_channel::bounded(capacity);
+    let forwarder = Forwarder {
+        tx,
+        returns: Default::default(),
+    };
+    (forwarder, rx)
+}
+
+impl Forwarder {
+    /// Resolves `cqe` against `ring` and sends its buffer, returns whether the
+    /// completion carried one.
+    ///
+    /// Never blocks: if the channel is full (`WouldBlock`) or closed
+    /// (`BrokenPipe`) the buffer goes straight onto the return queue.
+    ///
+    /// # Safety
+    ///
+    /// `ring` must outlive every buffer sent, and must not be unregistered
+    /// while any of them hasn't been reclaimed.
+    ///
+    /// # Panics
+    ///
+    /// If the ring consumes incrementally and the kernel is still filling the
+    /// buffer.
+    pub unsafe fn forward<E: EntryMarker>(
+        &self,
+        ring: &mut BufRing<state::Init>,
+        cqe: &E,
+    ) -> io::Result<bool> {
+        let Some(id) = ring.buffer_id_from_cqe(cqe)? else {
+            return Ok(false);
+        };
+        let buf = ReturningBuffer {
+            // SAFETY: upheld by the caller
+            buf: ManuallyDrop::new(unsafe { id.detach() }),
+            returns: self.returns.clone(),
+        };
+        match self.tx.try_send(buf) {
+            Ok(()) => Ok(true),
+            Err(async_channel::TrySendError::Full(_)) => Err(io::ErrorKind::WouldBlock.into()),
+            Err(async_channel::TrySendError::Closed(_)) => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    /// Returns every buffer dropped since the last call to `ring` in a single
+    /// publication, see [`BufRing::recycle_many`].
+    ///
+    /// If one is rejected none are returned, and they all go back onto the
+    /// return queue.
+    pub fn reclaim(&self, ring: &mut BufRing<state::Init>) -> Result<u16, RecycleError> {
+        let returned = core::mem::take(&mut *self.returns.lock().unwrap_or_else(|e| e.into_inner()));
+        ring.recycle_many(returned).map_err(|(e, returned)| {
+            self.returns
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .extend(returned);
+            e
+        })
+    }
+
+    /// Buffers waiting on the return queue.
+    pub fn pending_returns(&self) -> usize {
+        self.returns.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
@@ -0,0 +1,292 @@
+use crate::buf_ring::{BufRing, state};
+use crate::sync::{AtomicU16, AtomicU32, Ordering, fence};
+
+/// Set in a slot's marker once the entry for that lap has been written.
+const WRITTEN: u32 = 1 << 16;
+
+/// Keeps a counter on a cache line of its own.
+#[repr(align(64))]
+struct CachePadded<T>(T);
+
+/// Returns buffers to a ring from several threads without a lock.
+///
+/// Each return claims a slot from a reservation counter private to the
+/// recycler, writes the entry, then marks the slot with the unmasked tail value
+/// it was claimed for. A second private counter, the published tail, only ever
+/// moves over a run of marked slots, one compare-exchange per slot, and each
+/// successful exchange is mirrored into the shared tail with a single
+/// `fetch_add`. The kernel can therefore never observe a slot whose entry is
+/// still being written. Whichever thread marks the oldest unpublished slot
+/// publishes everything written after it, so nothing is left unpublished once
+/// every `recycle` call has returned.
+///
+/// The shared tail sits inside the first entry, on the same cache line as the
+/// entries of the first few slots, which other threads write while recycling.
+/// Contending on it directly made every failed compare-exchange bounce that
+/// line between cores. Both counters live on padded lines of their own instead,
+/// so the shared line only sees the final, uncontended `fetch_add`.
+///
+/// Marking a slot and then checking the published tail, against moving the
+/// published tail and then checking the next marker, is a store buffering
+/// pattern: a `SeqCst` fence sits between the two steps on both sides, so a
+/// thread that finds the slot before its own unmarked is guaranteed that the
+/// thread marking that slot will see its marker in turn.
+///
+/// Created with [`BufRing::concurrent_recycler`].
+pub struct ConcurrentRecycler<'a> {
+    ring: &'a mut BufRing<state::Init>,
+    queue: PublishQueue,
+}
+
+// SAFETY: the ring is only read through `&self`, and `PublishQueue` only
+// writes to slots it claimed exclusively and moves the tail atomically
+unsafe impl Sync for ConcurrentRecycler<'_> {}
+
+impl<'a> ConcurrentRecycler<'a> {
+    /// The ring must not have any written but unpublished entries.
+    pub(crate) fn new(ring: &'a mut BufRing<state::Init>) -> Self {
+        let queue = PublishQueue::new(ring);
+        Self { ring, queue }
+    }
+
+    /// Returns `buf_id` to the kernel.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `buf_id` < `entries()`, that it is held by the
+    /// application and that it is returned only once.
+    pub unsafe fn recycle(&self, buf_id: u16) {
+        unsafe { self.queue.recycle(self.ring, buf_id) }
+    }
+
+    pub fn entries(&self) -> u16 {
+        self.ring.entries()
+    }
+}
+
+impl Drop for ConcurrentRecycler<'_> {
+    fn drop(&mut self) {
+        self.queue.finish(self.ring);
+    }
+}
+
+/// The reservation and publication protocol of [`ConcurrentRecycler`],
+/// shared with [`SharedBufRing`](crate::SharedBufRing).
+pub(crate) struct PublishQueue {
+    reserve: CachePadded<AtomicU16>,
+    published: CachePadded<AtomicU16>,
+    written: Box<[AtomicU32]>,
+}
+
+impl PublishQueue {
+    /// The ring must not have any written but unpublished entries.
+    pub(crate) fn new(ring: &BufRing<state::Init>) -> Self {
+        let tail = ring.shared_tail().load(Ordering::Acquire);
+        let written = (0..ring.entries()).map(|_| AtomicU32::new(0)).collect();
+        Self {
+            reserve: CachePadded(AtomicU16::new(tail)),
+            published: CachePadded(AtomicU16::new(tail)),
+            written,
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Same as [`ConcurrentRecycler::recycle`], and nothing but this queue may
+    /// write entries into `ring` while it is in use.
+    pub(crate) unsafe fn recycle(&self, ring: &BufRing<state::Init>, buf_id: u16) {
+        let slot = self.reserve.0.fetch_add(1, Ordering::Relaxed);
+
+        // SAFETY: the slot was claimed exclusively above, and the kernel has
+        // consumed its previous entry since `buf_id` was handed to the application
+        unsafe {
+            let entry = &mut *ring.entry_ptr(slot as u32);
+            entry.set_addr(ring.get_buffer(buf_id) as u64);
+            entry.set_len(ring.buf_size());
+            entry.set_bid(buf_id);
+        }
+
+        self.marker(slot).store(WRITTEN | slot as u32, Ordering::Release);
+        fence(Ordering::SeqCst);
+        self.publish(ring);
+    }
+
+    fn publish(&self, ring: &BufRing<state::Init>) {
+        let published = &self.published.0;
+        let mut t = published.load(Ordering::Acquire);
+        while self.marker(t).load(Ordering::Acquire) == WRITTEN | t as u32 {
+            match published.compare_exchange(t, t.wrapping_add(1), Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {
+                    // the entry write happens before the exchange, so this
+                    // publishes it even if a later slot's increment lands first
+                    let _ = ring.shared_tail().fetch_add(1, Ordering::Release);
+                    t = t.wrapping_add(1);
+                    fence(Ordering::SeqCst);
+                }
+                Err(current) => t = current,
+            }
+        }
+    }
+
+    #[inline]
+    fn marker(&self, slot: u16) -> &AtomicU32 {
+        &self.written[slot as usize & (self.written.len() - 1)]
+    }
+
+    /// Hands everything published back to the ring's own bookkeeping.
+    pub(crate) fn finish(&mut self, ring: &mut BufRing<state::Init>) {
+        let tail = ring.shared_tail().load(Ordering::Acquire);
+        debug_assert_eq!(
+            tail,
+            self.reserve.0.load(Ordering::Relaxed),
+            "unpublished reservations"
+        );
+        ring.adopt_published(tail);
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use crate::cqe::cqe_buffer_id;
+    use crate::sim::SimKernel;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::mpsc;
+
+    const ENTRIES: u16 = 64;
+    const BUF_SIZE: u32 = 32;
+    const PRODUCERS: usize = 4;
+    /// Enough to wrap the tail and every slot's marker a few times.
+    const ROUNDS: usize = 200_000;
+
+    /// Recycles from several threads while a simulated kernel consumes and
+    /// validates every published entry, then hands the buffer to a random
+    /// thread, which may sit on a few before returning them in reverse.
+    #[test]
+    #[cfg_attr(miri, ignore = "too slow under Miri")]
+    fn stress_recycle_against_simulated_kernel() {
+        let mut ring = BufRing::new(ENTRIES, BUF_SIZE, 0)
+            .unwrap()
+            .into_registered()
+            .init_parked();
+        // whether each buffer was returned and not consumed since
+        let returned: Vec<AtomicBool> = (0..ENTRIES).map(|_| AtomicBool::new(false)).collect();
+
+        let recycler = ring.concurrent_recycler();
+        std::thread::scope(|s| {
+            let (senders, receivers): (Vec<_>, Vec<_>) =
+                (0..PRODUCERS).map(|_| mpsc::channel::<u16>()).unzip();
+            for rx in receivers {
+                let (recycler, returned) = (&recycler, &returned);
+                s.spawn(move || {
+                    let mut held = Vec::new();
+                    while let Ok(bid) = rx.recv() {
+                        held.push(bid);
+                        held.extend(rx.try_iter().take(3));
+                        for bid in held.drain(..).rev() {
+                            assert!(!returned[bid as usize].swap(true, Ordering::Release));
+                            unsafe { recycler.recycle(bid) };
+                        }
+                    }
+                });
+            }
+
+            let mut rng = 0x2545_f491_u32;
+            let mut next = move || {
+                rng ^= rng << 13;
+                rng ^= rng >> 17;
+                rng ^= rng << 5;
+                rng as usize % PRODUCERS
+            };
+            for bid in 0..ENTRIES {
+                senders[next()].send(bid).unwrap();
+            }
+
+            let ring = &*recycler.ring;
+            let mut kernel = SimKernel::attach(ring);
+            let payload = [0xa5; BUF_SIZE as usize];
+            let mut received = 0;
+            while received < ROUNDS {
+                let (flags, res) = kernel.recv(ring, &payload, true);
+                if res == -libc::ENOBUFS {
+                    std::thread::yield_now();
+                    continue;
+                }
+                assert_eq!(res, BUF_SIZE as i32, "entry published with a stale len");
+                let bid = cqe_buffer_id(flags);
+                assert!(
+                    returned[bid as usize].swap(false, Ordering::Acquire),
+                    "buffer {bid} consumed without being returned"
+                );
+                senders[next()].send(bid).unwrap();
+                received += 1;
+            }
+            drop(senders);
+        });
+
+        let tail = recycler.ring.shared_tail().load(Ordering::Acquire);
+        assert_eq!(tail, (ENTRIES as usize + ROUNDS) as u16);
+        drop(recycler);
+        drop(ring.unregister_simulated());
+    }
+
+    /// Neither counter shares a cache line with the other or with the tail.
+    #[test]
+    fn counters_sit_on_lines_of_their_own() {
+        let mut ring = BufRing::new(8, 64, 0).unwrap().register_simulated();
+        let mut queue = PublishQueue::new(&ring);
+        let line = |p: *const AtomicU16| p as usize / 64;
+        let (reserve, published) = (line(&queue.reserve.0), line(&queue.published.0));
+        assert_ne!(reserve, published);
+        for counter in [reserve, published] {
+            assert_ne!(counter, line(ring.tail_ptr()));
+        }
+        queue.finish(&mut ring);
+        drop(ring.unregister_simulated());
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use crate::buf_ring::model::ModelKernel;
+    use loom::sync::Arc;
+
+    struct Model {
+        ring: BufRing<state::Init>,
+        queue: PublishQueue,
+    }
+
+    // SAFETY: as for `ConcurrentRecycler`
+    unsafe impl Sync for Model {}
+    unsafe impl Send for Model {}
+
+    /// Two threads race to publish while the kernel reads the tail.
+    #[test]
+    fn loom_racing_recycles_publish_written_entries() {
+        loom::model(|| {
+            let ring = BufRing::new(4, 64, 0).unwrap().into_registered();
+            let ring = ring.init_parked();
+            let kernel = ModelKernel::attach(&ring);
+            let queue = PublishQueue::new(&ring);
+            let model = Arc::new(Model { ring, queue });
+
+            let recyclers: Vec<_> = (0..2)
+                .map(|bid| {
+                    let model = model.clone();
+                    loom::thread::spawn(move || unsafe { model.queue.recycle(&model.ring, bid) })
+                })
+                .collect();
+            let head = kernel.consume(0);
+            for recycler in recyclers {
+                recycler.join().unwrap();
+            }
+            // both returns are published once they have returned
+            assert_eq!(kernel.consume(head), 2);
+
+            let Model { mut ring, mut queue } = Arc::try_unwrap(model).ok().unwrap();
+            queue.finish(&mut ring);
+            drop(ring.unregister_simulated());
+        });
+    }
+}
@@ -0,0 +1,123 @@
+/// Whether a completion result means the ring had no buffer left.
+///
+/// A multishot recv terminates on `ENOBUFS`. To recover, return whatever
+/// buffers are done with and resubmit, which
+/// [`BufRing::replenish_and_resubmit`](crate::BufRing::replenish_and_resubmit) packages up.
+pub fn is_enobufs(cqe_res: i32) -> bool {
+    cqe_res == -libc::ENOBUFS
+}
+
+// io_uring doesn't expose its sys bindings
+// so they've been redefined here.
+const IORING_CQE_F_BUFFER: u32 = 1;
+const IORING_CQE_BUFFER_SHIFT: u32 = 16;
+
+/// Whether a completion with these `flags` carries a selected buffer.
+///
+/// Cheap enough to filter completions before
+/// [`BufRing::buffer_id_from_cqe`](crate::BufRing::buffer_id_from_cqe).
+pub fn cqe_has_buffer(flags: u32) -> bool {
+    flags & IORING_CQE_F_BUFFER != 0
+}
+
+/// The buffer id in a completion's `flags`.
+///
+/// Only meaningful if [`cqe_has_buffer`] is true. The id is the upper 16 bits
+/// of the flags, so the cast can't truncate.
+pub fn cqe_buffer_id(flags: u32) -> u16 {
+    (flags >> IORING_CQE_BUFFER_SHIFT) as u16
+}
+
+/// The fields of a completion a buffer is resolved from, see
+/// [`BufRing::buffer_id_from_fields`](crate::BufRing::buffer_id_from_fields).
+pub trait CqeFields {
+    fn flags(&self) -> u32;
+    fn result(&self) -> i32;
+}
+
+impl CqeFields for io_uring::cqueue::Entry {
+    fn flags(&self) -> u32 {
+        io_uring::cqueue::Entry::flags(self)
+    }
+
+    fn result(&self) -> i32 {
+        io_uring::cqueue::Entry::result(self)
+    }
+}
+
+impl CqeFields for io_uring::cqueue::Entry32 {
+    fn flags(&self) -> u32 {
+        io_uring::cqueue::Entry32::flags(self)
+    }
+
+    fn result(&self) -> i32 {
+        io_uring::cqueue::Entry32::result(self)
+    }
+}
+
+/// A completion built by hand, for driving completion handling without a
+/// kernel.
+#[cfg(any(test, feature = "test-support"))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FakeCqe {
+    pub flags: u32,
+    pub res: i32,
+}
+
+#[cfg(any(test, feature = "test-support"))]
+impl FakeCqe {
+    /// `len` bytes received into `buf_id`, by a multishot recv that goes on if
+    /// `more` is set.
+    pub fn buffer(buf_id: u16, len: u32, more: bool) -> Self {
+        const IORING_CQE_F_MORE: u32 = 1 << 1;
+
+        let more = if more { IORING_CQE_F_MORE } else { 0 };
+        Self {
+            flags: IORING_CQE_F_BUFFER | (buf_id as u32) << IORING_CQE_BUFFER_SHIFT | more,
+            res: len as i32,
+        }
+    }
+
+    /// A failed completion, e.g. `FakeCqe::error(libc::ENOBUFS)`.
+    pub fn error(errno: i32) -> Self {
+        Self {
+            flags: 0,
+            res: -errno,
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-support"))]
+impl CqeFields for FakeCqe {
+    fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    fn result(&self) -> i32 {
+        self.res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use io_uring::cqueue;
+
+    /// Fake completions decode the way the `io_uring` crate decodes the
+    /// kernel's.
+    #[test]
+    fn fake_cqe_encodes_like_the_kernel() {
+        for (bid, more) in [(0, false), (1, true), (0x7fff, true), (u16::MAX, false)] {
+            let cqe = FakeCqe::buffer(bid, 42, more);
+            assert_eq!(cqueue::buffer_select(cqe.flags()), Some(bid));
+            assert_eq!(cqe_buffer_id(cqe.flags()), bid);
+            assert_eq!(cqueue::more(cqe.flags()), more);
+            assert_eq!(cqe.result(), 42);
+        }
+
+        let cqe = FakeCqe::error(libc::ENOBUFS);
+        assert_eq!(cqueue::buffer_select(cqe.flags()), None);
+        assert!(!cqueue::more(cqe.flags()));
+        assert_eq!(cqe.result(), -libc::ENOBUFS);
+    }
+}
@@ -0,0 +1,441 @@
+//! Driving a ring from a tokio task.
+//!
+//! The [`AsyncRingDriver`] owns the [`IoUring`] and its buffer ring, and waits
+//! on an eventfd registered with the ring through [`AsyncFd`]. Each buffer it
+//! hands out is a [`DriverBuffer`], which can be moved to another task and
+//! puts itself on the driver's return channel when dropped. Returned buffers
+//! go back to the kernel in a single publication on the driver's next wakeup.
+//!
+//! The driver assumes every completion on the ring comes from a recv selecting
+//! buffers from its buffer group.
+
+use crate::buf_ring::{BufRing, RegistrationMode, state};
+use crate::owned_buffer::OwnedBuffer;
+use io_uring::{IoUring, cqueue, squeue};
+use tokio::io::unix::AsyncFd;
+use tokio::sync::mpsc;
+
+use std::collections::VecDeque;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+/// Called with the ring and the `user_data` of a completion that ended a
+/// multishot recv, returns the recv to resubmit, if any.
+///
+/// [`BufRing::replenish_and_resubmit`] builds one.
+pub type ResubmitHook =
+    Box<dyn FnMut(&mut BufRing<state::Init>, u64) -> Option<squeue::Entry> + Send>;
+
+/// Received data handed out by an [`AsyncRingDriver`], returned to it on drop.
+pub struct DriverBuffer {
+    buf: ManuallyDrop<OwnedBuffer>,
+    returns: mpsc::UnboundedSender<OwnedBuffer>,
+}
+
+impl DriverBuffer {
+    pub fn buf_id(&self) -> u16 {
+        self.buf.buf_id()
+    }
+
+    pub fn bgid(&self) -> u16 {
+        self.buf.bgid()
+    }
+}
+
+impl core::ops::Deref for DriverBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Drop for DriverBuffer {
+    fn drop(&mut self) {
+        // SAFETY: never used again
+        let buf = unsafe { ManuallyDrop::take(&mut self.buf) };
+        // once the driver is gone its ring is leaked, see its `Drop`
+        let _ = self.returns.send(buf);
+    }
+}
+
+/// Owns an [`IoUring`] and its buffer ring, and turns completions into
+/// [`DriverBuffer`]s.
+///
+/// Once [`max_outstanding`](Self::set_max_outstanding) buffers are out,
+/// [`next_buffer`](Self::next_buffer) waits for one to come back before
+/// resolving more completions, which stay in the completion queue meanwhile.
+///
+/// Dropping the driver while buffers are still out leaks its ring, which
+/// they point into, so they stay readable. The ring is only unregistered and
+/// unmapped once every buffer came back.
+pub struct AsyncRingDriver {
+    uring: IoUring,
+    ring: ManuallyDrop<BufRing<state::Init>>,
+    eventfd: AsyncFd<OwnedFd>,
+    ready: VecDeque<DriverBuffer>,
+    returns_tx: mpsc::UnboundedSender<OwnedBuffer>,
+    returns_rx: mpsc::UnboundedReceiver<OwnedBuffer>,
+    outstanding: usize,
+    max_outstanding: usize,
+    /// `user_data` of recvs ended by `ENOBUFS` whose hook had nothing to
+    /// resubmit, retried once buffers come back.
+    stalled: Vec<u64>,
+    on_enobufs: Option<ResubmitHook>,
+    on_terminated: Option<ResubmitHook>,
+}
+
+impl AsyncRingDriver {
+    /// Registers an eventfd with `uring` and takes over both rings.
+    ///
+    /// Must be called from within a tokio runtime.
+    ///
+    /// # Panics
+    ///
+    /// If `ring` is registered in [`RegistrationMode::Incremental`], whose
+    /// buffers can't be handed out while the kernel still fills them.
+    pub fn new(uring: IoUring, ring: BufRing<state::Init>) -> io::Result<Self> {
+        assert_eq!(
+            ring.registration_mode(),
+            RegistrationMode::Plain,
+            "the driver doesn't support incremental consumption"
+        );
+        // SAFETY: no pointers are passed, and the fd is owned right away
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` was just created and isn't owned by anything else
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        uring.submitter().register_eventfd(fd.as_raw_fd())?;
+
+        let (returns_tx, returns_rx) = mpsc::unbounded_channel();
+        Ok(Self {
+            max_outstanding: ring.entries() as usize,
+            uring,
+            ring: ManuallyDrop::new(ring),
+            eventfd: AsyncFd::new(fd)?,
+            ready: VecDeque::new(),
+            returns_tx,
+            returns_rx,
+            outstanding: 0,
+            stalled: Vec::new(),
+            on_enobufs: None,
+            on_terminated: None,
+        })
+    }
+
+    /// Caps how many buffers are out at once, the ring size by default.
+    pub fn set_max_outstanding(&mut self, max: usize) {
+        self.max_outstanding = max.max(1);
+    }
+
+    /// Called when a recv fails with `ENOBUFS`.
+    ///
+    /// If it returns `None` it is called again, with the same `user_data`,
+    /// after the next buffers are returned.
+    pub fn on_enobufs(&mut self, hook: ResubmitHook) {
+        self.on_enobufs = Some(hook);
+    }
+
+    /// Called when a multishot recv ends for any other reason, including a
+    /// plain recv completing.
+    pub fn on_terminated(&mut self, hook: ResubmitHook) {
+        self.on_terminated = Some(hook);
+    }
+
+    /// The io_uring, e.g. to submit the first recvs.
+    pub fn uring(&mut self) -> &mut IoUring {
+        &mut self.uring
+    }
+
+    pub fn ring(&self) -> &BufRing<state::Init> {
+        &self.ring
+    }
+
+    /// Buffers resolved and not yet returned, including ones not handed out yet.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding
+    }
+
+    /// Waits for the next received buffer.
+    ///
+    /// Every wakeup first returns the buffers dropped since the last one, then
+    /// resolves the completions that arrived. An error is the first completion
+    /// error other than `ENOBUFS`, or a failure to submit resubmitted recvs.
+    pub async fn next_buffer(&mut self) -> io::Result<DriverBuffer> {
+        loop {
+            self.reclaim()?;
+
+            if let Some(buf) = self.ready.pop_front() {
+                return Ok(buf);
+            }
+            if self.outstanding >= self.max_outstanding {
+                // the driver holds a sender, so the channel never closes
+                let buf = self.returns_rx.recv().await;
+                self.return_buffers(buf)?;
+                continue;
+            }
+            if self.process_completions()? {
+                continue;
+            }
+
+            let returned = tokio::select! {
+                guard = self.eventfd.readable() => {
+                    let mut guard = guard?;
+                    let mut count = [0u8; 8];
+                    // SAFETY: reads at most 8 bytes into `count`
+                    let n = unsafe {
+                        libc::read(guard.get_inner().as_raw_fd(), count.as_mut_ptr().cast(), 8)
+                    };
+                    if n < 0 {
+                        let e = io::Error::last_os_error();
+                        if e.kind() != io::ErrorKind::WouldBlock {
+                            return Err(e);
+                        }
+                    }
+                    guard.clear_ready();
+                    None
+                }
+                buf = self.returns_rx.recv() => buf,
+            };
+            self.return_buffers(returned)?;
+        }
+    }
+
+    /// Returns everything on the return channel to the kernel in one
+    /// publication.
+    fn reclaim(&mut self) -> io::Result<()> {
+        self.return_buffers(None)
+    }
+
+    fn return_buffers(&mut self, first: Option<OwnedBuffer>) -> io::Result<()> {
+        let mut returned: Vec<_> = first.into_iter().collect();
+        while let Ok(buf) = self.returns_rx.try_recv() {
+            returned.push(buf);
+        }
+        if returned.is_empty() {
+            return Ok(());
+        }
+        let result = match self.ring.recycle_many(returned) {
+            Ok(count) => {
+                self.outstanding -= count as usize;
+                Ok(())
+            }
+            Err((e, mut returned)) => {
+                // still return every buffer this ring handed out, only the
+                // rejected ones are dropped
+                returned.retain(|buf| self.ring.check_recyclable(buf).is_ok());
+                returned.sort_unstable_by_key(OwnedBuffer::buf_id);
+                returned.dedup_by_key(|buf| buf.buf_id());
+                if let Ok(count) = self.ring.recycle_many(returned) {
+                    self.outstanding -= count as usize;
+                }
+                Err(io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+        };
+        self.ring.flush();
+
+        if !self.stalled.is_empty()
+            && let Some(hook) = &mut self.on_enobufs
+        {
+            let mut resubmit = Vec::new();
+            for user_data in core::mem::take(&mut self.stalled) {
+                match hook(&mut self.ring, user_data) {
+                    Some(sqe) => resubmit.push(sqe),
+                    None => self.stalled.push(user_data),
+                }
+            }
+            self.submit(resubmit)?;
+        }
+        result
+    }
+
+    /// Resolves the completion queue until `max_outstanding` buffers are
+    /// out, returns whether anything was there.
+    fn process_completions(&mut self) -> io::Result<bool> {
+        let mut resubmit = Vec::new();
+        let mut error = None;
+        let mut any = false;
+        let mut completions = self.uring.completion();
+        while self.outstanding < self.max_outstanding {
+            // the rest stays queued until buffers come back
+            let Some(cqe) = completions.next() else {
+                break;
+            };
+            any = true;
+            let more = cqueue::more(cqe.flags());
+            // SAFETY: the ring is only unmapped once no buffer is out, see `Drop`
+            let resolved = match self.ring.buffer_id_from_cqe(&cqe) {
+                Ok(id) => Ok(id.map(|id| unsafe { id.detach() })),
+                Err(e) => Err(e),
+            };
+            match resolved {
+                Ok(Some(buf)) => {
+                    self.outstanding += 1;
+                    self.ready.push_back(DriverBuffer {
+                        buf: ManuallyDrop::new(buf),
+                        returns: self.returns_tx.clone(),
+                    });
+                }
+                Ok(None) => {}
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    if let Some(hook) = &mut self.on_enobufs {
+                        match hook(&mut self.ring, cqe.user_data()) {
+                            Some(sqe) => resubmit.push(sqe),
+                            None => self.stalled.push(cqe.user_data()),
+                        }
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+            if !more && let Some(hook) = &mut self.on_terminated {
+                resubmit.extend(hook(&mut self.ring, cqe.user_data()));
+            }
+        }
+        drop(completions);
+        self.submit(resubmit)?;
+        error.map_or(Ok(any), Err)
+    }
+
+    fn submit(&mut self, sqes: Vec<squeue::Entry>) -> io::Result<()> {
+        if sqes.is_empty() {
+            return Ok(());
+        }
+        for sqe in &sqes {
+            // SAFETY: recvs built against the ring's own buffer group
+            while unsafe { self.uring.submission().push(sqe) }.is_err() {
+                self.uring.submit()?;
+            }
+        }
+        self.uring.submit()?;
+        Ok(())
+    }
+}
+
+impl Drop for AsyncRingDriver {
+    fn drop(&mut self) {
+        // buffers not handed out yet come straight back
+        self.ready.clear();
+        let _ = self.reclaim();
+        if self.outstanding > 0 {
+            // the buffers still out point into the mapping, so leak it
+            return;
+        }
+        // SAFETY: never used again
+        let ring = unsafe { ManuallyDrop::take(&mut self.ring) };
+        if let Err((_, ring)) = ring.unregister(&self.uring.submitter()) {
+            core::mem::forget(ring);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    const ENTRIES: u16 = 8;
+    const BUF_SIZE: u32 = 64;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    /// A driver with a multishot recv armed on the server side of a localhost
+    /// connection, rearmed whenever it ends.
+    fn connected() -> (AsyncRingDriver, TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let uring = IoUring::new(32).unwrap();
+        let ring = BufRing::new(ENTRIES, BUF_SIZE, 5).unwrap();
+        let ring = ring.register_and_init(&uring.submitter()).map_err(|(e, _)| e).unwrap();
+        let mut driver = AsyncRingDriver::new(uring, ring).unwrap();
+
+        let fd = server.as_raw_fd();
+        driver.on_enobufs(Box::new(move |ring, user_data| {
+            ring.replenish_and_resubmit(fd, user_data)
+        }));
+        driver.on_terminated(Box::new(move |ring, user_data| {
+            ring.replenish_and_resubmit(fd, user_data)
+        }));
+        let sqe = driver.ring().recv_multi_sqe(fd, 1);
+        unsafe { driver.uring().submission().push(&sqe).unwrap() };
+        driver.uring().submit().unwrap();
+        (driver, client, server)
+    }
+
+    /// A slow consumer task holds every buffer for a while, so the driver
+    /// keeps hitting its cap of outstanding buffers and the kernel keeps
+    /// running out of provided ones, yet every byte arrives in order.
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn backpressure_over_localhost() {
+        let payload: Vec<u8> = (0..64 * BUF_SIZE).map(|i| (i % 251) as u8).collect();
+
+        runtime().block_on(async {
+            let (mut driver, mut client, _server) = connected();
+            driver.set_max_outstanding(2);
+
+            let (tx, mut rx) = tokio::sync::mpsc::channel::<DriverBuffer>(1);
+            let consumer = tokio::spawn(async move {
+                let mut received = Vec::new();
+                while let Some(buf) = rx.recv().await {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                    received.extend_from_slice(&buf);
+                }
+                received
+            });
+
+            let sent = payload.clone();
+            // hand the client back, at EOF the terminated hook would rearm
+            // forever
+            let writer = std::thread::spawn(move || {
+                client.write_all(&sent).unwrap();
+                client
+            });
+
+            let mut total = 0;
+            while total < payload.len() {
+                let buf = driver.next_buffer().await.unwrap();
+                assert!(driver.outstanding() <= 2, "{} out", driver.outstanding());
+                assert!(buf.len() <= BUF_SIZE as usize);
+                total += buf.len();
+                tx.send(buf).await.unwrap();
+            }
+            drop(tx);
+            let _client = writer.join().unwrap();
+            assert_eq!(consumer.await.unwrap(), payload);
+
+            // everything dropped comes back on the next wakeup
+            let _ = tokio::time::timeout(Duration::from_millis(10), driver.next_buffer()).await;
+            assert_eq!(driver.outstanding(), 0);
+            assert_eq!(driver.ring().provided(), ENTRIES);
+        });
+    }
+
+    /// The mapping outlives a driver dropped with buffers still out.
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn drop_leaks_the_ring_while_buffers_are_out() {
+        runtime().block_on(async {
+            let (mut driver, mut client, _server) = connected();
+            client.write_all(b"still here").unwrap();
+            let buf = driver.next_buffer().await.unwrap();
+            assert_eq!(driver.outstanding(), 1);
+            drop(driver);
+            assert_eq!(&*buf, b"still here");
+        });
+    }
+}
@@ -0,0 +1,397 @@
+use crate::buf_ring::{BufRing, state};
+use crate::buffer_id::BufferId;
+use crate::cqe::CqeFields;
+use io_uring::cqueue::EntryMarker;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// When and how an [`ElasticPool`] grows and shrinks.
+#[derive(Clone, Debug)]
+pub struct GrowthPolicy {
+    /// `ENOBUFS` completions within `window` that trigger growth.
+    pub enobufs_threshold: u32,
+    pub window: Duration,
+    /// Entries of each overflow ring, buffers are sized like the primary's.
+    pub growth_entries: u16,
+    /// Upper bound on the combined mapping size of every ring in the pool.
+    pub max_memory: usize,
+    /// Overflow rings without completions for this long are drained, and
+    /// released once empty.
+    pub idle_shrink_after: Duration,
+    /// Bgids handed to overflow rings.
+    pub overflow_bgids: Range<u16>,
+}
+
+struct Overflow {
+    ring: BufRing<state::Init>,
+    last_used: Instant,
+    /// Idle, so buffers returned to it are parked rather than provided.
+    draining: bool,
+}
+
+/// A primary ring that grows extra rings under `ENOBUFS` pressure.
+///
+/// A completion doesn't say which buffer group it came from, so the caller
+/// passes the bgid it submitted with (as returned by `bgid_for_submission`,
+/// typically carried in the user data) back to `buffer_id_from_cqe`.
+///
+/// Every ring stays registered until [`unregister_all`](Self::unregister_all);
+/// like a single ring, a pool dropped while registered leaks its rings, and
+/// debug builds panic.
+pub struct ElasticPool {
+    primary: BufRing<state::Init>,
+    overflow: Vec<Overflow>,
+    policy: GrowthPolicy,
+    enobufs: u32,
+    window_start: Instant,
+}
+
+impl ElasticPool {
+    pub fn new(primary: BufRing<state::Init>, policy: GrowthPolicy) -> Self {
+        Self {
+            primary,
+            overflow: Vec::new(),
+            policy,
+            enobufs: 0,
+            window_start: Instant::now(),
+        }
+    }
+
+    /// The bgid new recvs should select buffers from, the newest ring that
+    /// isn't draining.
+    pub fn bgid_for_submission(&self) -> u16 {
+        self.overflow
+            .iter()
+            .rfind(|o| !o.draining)
+            .map_or(self.primary.bgid(), |o| o.ring.bgid())
+    }
+
+    /// Number of overflow rings currently allocated.
+    pub fn overflow_rings(&self) -> usize {
+        self.overflow.len()
+    }
+
+    /// Combined mapping size of every ring in the pool.
+    pub fn memory(&self) -> usize {
+        self.primary.mmap_size()
+            + self
+                .overflow
+                .iter()
+                .map(|o| o.ring.mmap_size())
+                .sum::<usize>()
+    }
+
+    pub fn primary(&self) -> &BufRing<state::Init> {
+        &self.primary
+    }
+
+    /// Resolves a completion for a recv submitted against `bgid`.
+    ///
+    /// `ENOBUFS` completions are counted towards the growth threshold, unless
+    /// the ring is draining and ran dry on purpose.
+    pub fn buffer_id_from_cqe<'a, 'b, E: EntryMarker>(
+        &'a mut self,
+        bgid: u16,
+        cqe: &'b E,
+    ) -> std::io::Result<Option<BufferId<'a, 'b, E>>> {
+        self.resolve(bgid, |ring| ring.buffer_id_from_cqe(cqe))
+    }
+
+    /// Like [`buffer_id_from_cqe`](Self::buffer_id_from_cqe), for anything
+    /// carrying the fields of a completion.
+    pub fn buffer_id_from_fields<'a, 'b, C: CqeFields>(
+        &'a mut self,
+        bgid: u16,
+        cqe: &'b C,
+    ) -> std::io::Result<Option<BufferId<'a, 'b, io_uring::cqueue::Entry>>> {
+        self.resolve(bgid, |ring| ring.buffer_id_from_fields(cqe))
+    }
+
+    fn resolve<'a, T>(
+        &'a mut self,
+        bgid: u16,
+        resolve: impl FnOnce(&'a mut BufRing<state::Init>) -> std::io::Result<T>,
+    ) -> std::io::Result<T> {
+        let now = Instant::now();
+        let (ring, draining) = match self.overflow.iter_mut().find(|o| o.ring.bgid() == bgid) {
+            Some(o) => {
+                o.last_used = now;
+                (&mut o.ring, o.draining)
+            }
+            None if bgid == self.primary.bgid() => (&mut self.primary, false),
+            None => return Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        };
+
+        let res = resolve(ring);
+        if let Err(e) = &res
+            && e.raw_os_error() == Some(libc::ENOBUFS)
+            && !draining
+        {
+            if now.duration_since(self.window_start) > self.policy.window {
+                self.window_start = now;
+                self.enobufs = 0;
+            }
+            self.enobufs += 1;
+        }
+        res
+    }
+
+    /// Grows the pool if starvation crossed the threshold, then releases idle
+    /// overflow rings. Returns whether a ring was added.
+    ///
+    /// A ring without completions routed to it for `idle_shrink_after` starts
+    /// draining: it is no longer handed out for submission, and buffers
+    /// returned to it are parked. It is only released once fully quiesced,
+    /// i.e. the application holds none of its buffers and the kernel neither
+    /// holds one nor has completions left to be resolved. Buffers provided to
+    /// a draining ring only come back through recvs still armed against it,
+    /// so a ring whose recvs were all resubmitted elsewhere keeps its memory
+    /// until [`unregister_all`](Self::unregister_all).
+    pub fn maintain(&mut self, submitter: &io_uring::Submitter<'_>) -> std::io::Result<bool> {
+        let grown = self.enobufs >= self.policy.enobufs_threshold && self.grow(submitter)?;
+        if grown {
+            self.enobufs = 0;
+            self.window_start = Instant::now();
+        }
+        self.shrink(submitter)?;
+        Ok(grown)
+    }
+
+    fn grow(&mut self, submitter: &io_uring::Submitter<'_>) -> std::io::Result<bool> {
+        let buf_size = self.primary.buf_size();
+        let size = crate::buf_ring::ring_size(
+            self.policy.growth_entries.next_power_of_two() as u32,
+            buf_size,
+            self.primary.map_opts().buffer_align,
+        );
+        if self.memory() + size > self.policy.max_memory {
+            return Ok(false);
+        }
+        let Some(bgid) = self
+            .policy
+            .overflow_bgids
+            .clone()
+            .find(|id| self.overflow.iter().all(|o| o.ring.bgid() != *id))
+        else {
+            return Ok(false);
+        };
+
+        let ring = BufRing::new_with_opts(
+            self.policy.growth_entries,
+            buf_size,
+            bgid,
+            self.primary.map_opts(),
+        )?;
+        let ring = ring.register_and_init(submitter).map_err(|(e, _)| e)?;
+        self.overflow.push(Overflow {
+            ring,
+            last_used: Instant::now(),
+            draining: false,
+        });
+        Ok(true)
+    }
+
+    fn shrink(&mut self, submitter: &io_uring::Submitter<'_>) -> std::io::Result<()> {
+        let now = Instant::now();
+        let idle = self.policy.idle_shrink_after;
+
+        let mut i = 0;
+        while i < self.overflow.len() {
+            let o = &mut self.overflow[i];
+            if !o.draining && now.duration_since(o.last_used) >= idle {
+                o.draining = true;
+                o.ring.set_provision_limit(0);
+            }
+            let ring = &o.ring;
+            let quiesced = ring.held_by_app() == 0
+                && ring.kernel_held_estimate() == 0
+                && ring.unreaped() == 0;
+            if !o.draining || !quiesced {
+                i += 1;
+                continue;
+            }
+
+            let o = self.overflow.remove(i);
+            if let Err((e, ring)) = o.ring.unregister(submitter) {
+                self.overflow.insert(i, Overflow { ring, ..o });
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Unregisters every overflow ring, then the primary, and returns the
+    /// primary.
+    ///
+    /// Overflow rings are unmapped once unregistered. On error the pool is
+    /// handed back with the rings that are still registered.
+    pub fn unregister_all(
+        mut self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<BufRing<state::Uninit>, (std::io::Error, Self)> {
+        while let Some(o) = self.overflow.pop() {
+            if let Err((e, ring)) = o.ring.unregister(submitter) {
+                self.overflow.push(Overflow { ring, ..o });
+                return Err((e, self));
+            }
+        }
+        let Self {
+            primary,
+            overflow,
+            policy,
+            enobufs,
+            window_start,
+        } = self;
+        primary.unregister(submitter).map_err(|(e, primary)| {
+            let pool = Self {
+                primary,
+                overflow,
+                policy,
+                enobufs,
+                window_start,
+            };
+            (e, pool)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cqe::FakeCqe;
+    use crate::sim::SimKernel;
+    use io_uring::IoUring;
+    use std::io::Write;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    const BUF_SIZE: u32 = 64;
+
+    /// Sends more than `bufs` buffers' worth of data, arms a multishot recv
+    /// against `bgid` and resolves every completion until it runs out of
+    /// buffers.
+    fn starve(uring: &mut IoUring, pool: &mut ElasticPool, bgid: u16, bufs: usize) {
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+        tx.write_all(&vec![7; (bufs + 1) * BUF_SIZE as usize]).unwrap();
+        let sqe = io_uring::opcode::RecvMulti::new(io_uring::types::Fd(rx.as_raw_fd()), bgid)
+            .build()
+            .user_data(bgid as u64);
+        unsafe { uring.submission().push(&sqe).unwrap() };
+
+        loop {
+            uring.submit_and_wait(1).unwrap();
+            let cqes: Vec<_> = uring.completion().collect();
+            let mut done = false;
+            for cqe in &cqes {
+                done |= !io_uring::cqueue::more(cqe.flags());
+                let _ = pool.buffer_id_from_cqe(cqe.user_data() as u16, cqe);
+            }
+            if done {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn grows_under_starvation_and_shrinks_back() {
+        let mut uring = IoUring::new(32).unwrap();
+        let primary = BufRing::new(2, BUF_SIZE, 1).unwrap();
+        let primary = primary.register_and_init(&uring.submitter()).map_err(|(e, _)| e);
+        let policy = GrowthPolicy {
+            enobufs_threshold: 1,
+            window: Duration::from_secs(60),
+            growth_entries: 4,
+            max_memory: usize::MAX,
+            idle_shrink_after: Duration::from_millis(50),
+            overflow_bgids: 10..12,
+        };
+        let mut pool = ElasticPool::new(primary.unwrap(), policy);
+        let baseline = pool.memory();
+
+        starve(&mut uring, &mut pool, 1, 2);
+        assert!(pool.maintain(&uring.submitter()).unwrap());
+        assert_eq!(pool.overflow_rings(), 1);
+        assert_eq!(pool.bgid_for_submission(), 10);
+        assert!(pool.memory() > baseline);
+
+        // idle, but the kernel still holds every buffer
+        std::thread::sleep(Duration::from_millis(60));
+        pool.maintain(&uring.submitter()).unwrap();
+        assert_eq!(pool.overflow_rings(), 1);
+        assert_eq!(pool.bgid_for_submission(), 1);
+
+        // a recv still armed against it drains it
+        starve(&mut uring, &mut pool, 10, 4);
+        pool.maintain(&uring.submitter()).unwrap();
+        assert_eq!(pool.overflow_rings(), 0);
+        assert_eq!(pool.memory(), baseline);
+
+        let primary = pool.unregister_all(&uring.submitter()).map_err(|(e, _)| e);
+        drop(primary.unwrap());
+    }
+
+    /// The simulated counterpart of the starvation above: only exhaustion
+    /// of a ring that isn't draining counts towards growth.
+    #[test]
+    fn counts_starvation_against_a_simulated_kernel() {
+        let primary = BufRing::new(2, BUF_SIZE, 1).unwrap().register_simulated();
+        let mut kernel = SimKernel::attach(&primary);
+        let policy = GrowthPolicy {
+            enobufs_threshold: 1,
+            window: Duration::from_secs(60),
+            growth_entries: 4,
+            max_memory: usize::MAX,
+            idle_shrink_after: Duration::from_secs(60),
+            overflow_bgids: 10..12,
+        };
+        let mut pool = ElasticPool::new(primary, policy);
+
+        for (flags, res) in kernel.exhaust(pool.primary(), &[7; BUF_SIZE as usize]) {
+            let cqe = FakeCqe { flags, res };
+            match pool.buffer_id_from_fields(1, &cqe) {
+                Ok(id) => assert_eq!(id.unwrap().buffer(), [7; BUF_SIZE as usize]),
+                Err(e) => assert_eq!(e.raw_os_error(), Some(libc::ENOBUFS)),
+            }
+        }
+        assert_eq!(pool.enobufs, 1);
+        let cqe = FakeCqe::error(libc::ENOBUFS);
+        let unknown = pool.buffer_id_from_fields(10, &cqe);
+        assert!(unknown.is_err_and(|e| e.kind() == std::io::ErrorKind::NotFound));
+        assert_eq!(pool.enobufs, 1);
+
+        // every buffer was recycled when its id dropped
+        assert_eq!(kernel.available(pool.primary()), 2);
+        drop(pool.primary.unregister_simulated());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn unregister_all_releases_overflow_rings() {
+        let uring = IoUring::new(8).unwrap();
+        let submitter = uring.submitter();
+        let primary = BufRing::new(2, BUF_SIZE, 1).unwrap();
+        let primary = primary.register_and_init(&submitter).map_err(|(e, _)| e);
+        let policy = GrowthPolicy {
+            enobufs_threshold: 0,
+            window: Duration::from_secs(60),
+            growth_entries: 2,
+            max_memory: usize::MAX,
+            idle_shrink_after: Duration::from_secs(60),
+            overflow_bgids: 10..12,
+        };
+        let mut pool = ElasticPool::new(primary.unwrap(), policy);
+        assert!(pool.maintain(&submitter).unwrap());
+        assert!(pool.maintain(&submitter).unwrap());
+        assert_eq!(pool.overflow_rings(), 2);
+
+        let primary = pool.unregister_all(&submitter).map_err(|(e, _)| e).unwrap();
+        // every group is free again
+        for bgid in [1, 10, 11] {
+            let ring = BufRing::new(2, BUF_SIZE, bgid).unwrap();
+            let ring = ring.register(&submitter).map_err(|(e, _)| e).unwrap();
+            drop(ring.unregister(&submitter).map_err(|(e, _)| e).unwrap());
+        }
+        drop(primary);
+    }
+}
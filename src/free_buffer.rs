@@ -0,0 +1,318 @@
+use crate::buf_ring::{BidState, BufRing, state};
+use core::marker::PhantomData;
+use std::io::{self, IoSlice};
+
+/// A buffer owned by the application, filled before it is provided.
+///
+/// Bytes are written from the start of the buffer, and the number written
+/// becomes the entry's `len` once [`provide`](Self::provide) is called.
+/// Dropping the handle publishes nothing: a buffer taken with
+/// [`BufRing::take_free`] is parked again, one from
+/// [`BufRing::free_buffer`] stays held by the application.
+pub struct FreeBuffer<'a> {
+    ring: &'a mut BufRing<state::Init>,
+    buf_id: u16,
+    len: u32,
+    origin: BidState,
+    /// Borrows the ring, which stays on its thread.
+    not_send: PhantomData<*const ()>,
+}
+
+impl<'a> FreeBuffer<'a> {
+    pub(crate) fn new(ring: &'a mut BufRing<state::Init>, buf_id: u16, origin: BidState) -> Self {
+        Self {
+            ring,
+            buf_id,
+            len: 0,
+            origin,
+            not_send: PhantomData,
+        }
+    }
+
+    pub fn buf_id(&self) -> u16 {
+        self.buf_id
+    }
+
+    /// Bytes written so far.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Bytes that can still be written.
+    pub fn remaining(&self) -> u32 {
+        self.ring.buf_size() - self.len
+    }
+
+    /// The bytes written so far.
+    pub fn filled(&self) -> &[u8] {
+        // SAFETY: `buf_id` is valid for the ring and the first `len` bytes were written
+        unsafe { &self.ring.buffer(self.buf_id)[..self.len as usize] }
+    }
+
+    /// Provides the buffer with `len` set to the bytes written.
+    ///
+    /// An empty buffer is parked instead, since the kernel can't use an entry
+    /// of length 0, and so is one past the provision limit.
+    pub fn provide(self) {
+        let mut this = core::mem::ManuallyDrop::new(self);
+        let (buf_id, len) = (this.buf_id, this.len);
+        if len == 0 {
+            this.ring.park(buf_id);
+            return;
+        }
+        // SAFETY: the buffer is held by the application and an initialized
+        // ring is registered
+        unsafe {
+            if this.ring.push_or_park(buf_id, len) {
+                this.ring.publish_unless_deferred();
+            }
+        }
+    }
+
+    /// Copies every slice in `srcs` back to back, or nothing at all.
+    ///
+    /// Returns the number of bytes written. If they don't all fit the buffer
+    /// is left untouched and the error, of kind `WriteZero`, wraps a
+    /// [`CapacityExceeded`].
+    pub fn write_vectored_all(&mut self, srcs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let needed = srcs.iter().map(|s| s.len()).sum::<usize>();
+        let available = self.remaining() as usize;
+        if needed > available {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                CapacityExceeded { needed, available },
+            ));
+        }
+
+        let mut spare = self.spare();
+        for src in srcs {
+            let (dst, rest) = core::mem::take(&mut spare).split_at_mut(src.len());
+            for (dst, &src) in dst.iter_mut().zip(src.iter()) {
+                dst.write(src);
+            }
+            spare = rest;
+        }
+        self.len += needed as u32;
+        Ok(needed)
+    }
+
+    fn spare(&mut self) -> &mut [core::mem::MaybeUninit<u8>] {
+        let len = self.len as usize;
+        // SAFETY: `buf_id` is valid for the ring and isn't provided
+        unsafe { &mut self.ring.buffer_uninit(self.buf_id)[len..] }
+    }
+}
+
+impl io::Write for FreeBuffer<'_> {
+    /// Fails with `WriteZero` once the buffer is full.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let spare = self.spare();
+        if spare.is_empty() {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        let n = buf.len().min(spare.len());
+        for (dst, &src) in spare[..n].iter_mut().zip(buf) {
+            dst.write(src);
+        }
+        self.len += n as u32;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "bytes")]
+// SAFETY: `chunk_mut` is the unwritten rest of the buffer and `advance_mut`
+// never moves past its end
+unsafe impl bytes::BufMut for FreeBuffer<'_> {
+    fn remaining_mut(&self) -> usize {
+        self.remaining() as usize
+    }
+
+    unsafe fn advance_mut(&mut self, cnt: usize) {
+        assert!(cnt <= self.remaining_mut(), "advance past the end of the buffer");
+        self.len += cnt as u32;
+    }
+
+    fn chunk_mut(&mut self) -> &mut bytes::buf::UninitSlice {
+        bytes::buf::UninitSlice::uninit(self.spare())
+    }
+}
+
+impl Drop for FreeBuffer<'_> {
+    fn drop(&mut self) {
+        if self.origin == BidState::Parked {
+            self.ring.park(self.buf_id);
+        }
+    }
+}
+
+/// A write didn't fit in the remaining space of a buffer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CapacityExceeded {
+    /// Bytes the write needed.
+    pub needed: usize,
+    /// Bytes left in the buffer.
+    pub available: usize,
+}
+
+impl core::fmt::Display for CapacityExceeded {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "write of {} bytes exceeds the {} bytes left in the buffer",
+            self.needed, self.available
+        )
+    }
+}
+
+impl std::error::Error for CapacityExceeded {}
+
+#[cfg(test)]
+mod tests {
+    use crate::buf_ring::BufRing;
+    use crate::sim::SimKernel;
+    use std::io::Write;
+
+    fn parked_ring() -> BufRing<crate::buf_ring::state::Init> {
+        BufRing::new(4, 64, 0)
+            .unwrap()
+            .into_registered()
+            .init_parked()
+    }
+
+    /// Whatever writes the bytes, the entry the kernel reads has exactly the
+    /// length written.
+    #[test]
+    fn provided_len_is_the_bytes_written() {
+        let mut ring = parked_ring();
+        let kernel = SimKernel::attach(&ring);
+
+        let mut buf = ring.take_free().unwrap();
+        write!(buf, "hello {}", 42).unwrap();
+        assert_eq!(buf.filled(), b"hello 42");
+        let first = buf.buf_id();
+        buf.provide();
+
+        let mut buf = ring.take_free().unwrap();
+        let value = serde_json::json!({ "bgid": 0, "ok": true });
+        serde_json::to_writer(&mut buf, &value).unwrap();
+        let json = buf.filled().to_vec();
+        let parsed: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(parsed, value);
+        let second = buf.buf_id();
+        buf.provide();
+
+        let entries = ring.snapshot_entries();
+        assert_eq!((entries[0].bid, entries[0].len), (first, 8));
+        let len = json.len() as u32;
+        assert_eq!((entries[1].bid, entries[1].len), (second, len));
+        assert_eq!(kernel.available(&ring), 2);
+        drop(ring.unregister_simulated());
+    }
+
+    /// A filled buffer that is dropped instead of provided is parked again
+    /// and never reaches the kernel.
+    #[test]
+    fn dropping_publishes_nothing() {
+        let mut ring = parked_ring();
+        let kernel = SimKernel::attach(&ring);
+
+        let mut buf = ring.take_free().unwrap();
+        buf.write_all(b"never sent").unwrap();
+        let bid = buf.buf_id();
+        drop(buf);
+
+        assert_eq!(ring.published_tail(), 0);
+        assert_eq!(kernel.available(&ring), 0);
+        assert_eq!((ring.provided(), ring.parked()), (0, 4));
+        // and it is the next one handed out, empty
+        let buf = ring.take_free().unwrap();
+        assert_eq!((buf.buf_id(), buf.len()), (bid, 0));
+        drop(buf);
+        drop(ring.unregister_simulated());
+    }
+
+    /// Writes stop at the end of the buffer.
+    #[test]
+    fn write_stops_when_full() {
+        let mut ring = parked_ring();
+        let mut buf = ring.take_free().unwrap();
+        assert_eq!(buf.write(&[1; 100]).unwrap(), 64);
+        assert_eq!(buf.remaining(), 0);
+        let e = buf.write(b"x").unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::WriteZero);
+        buf.provide();
+        assert_eq!(ring.snapshot_entries()[0].len, 64);
+        drop(ring.unregister_simulated());
+    }
+
+    /// Slices filling the buffer exactly are copied back to back, one byte
+    /// more copies nothing, and no slices at all write nothing.
+    #[test]
+    fn vectored_copy_is_all_or_nothing() {
+        use super::CapacityExceeded;
+        use std::io::IoSlice;
+
+        let mut ring = parked_ring();
+        let (a, b) = ([1; 40], [2; 24]);
+
+        let mut buf = ring.take_free().unwrap();
+        buf.write_all(b"x").unwrap();
+        let srcs = [IoSlice::new(&a), IoSlice::new(&b[..23])];
+        assert_eq!(buf.write_vectored_all(&srcs).unwrap(), 63);
+        assert_eq!(buf.remaining(), 0);
+        assert_eq!(buf.filled()[0], b'x');
+        assert_eq!(buf.filled()[1..41], [1; 40]);
+        assert_eq!(buf.filled()[41..], [2; 23]);
+        buf.provide();
+
+        let mut buf = ring.take_free().unwrap();
+        let srcs = [IoSlice::new(&a), IoSlice::new(&b), IoSlice::new(b"!")];
+        let e = buf.write_vectored_all(&srcs).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::WriteZero);
+        let exceeded = e.get_ref().unwrap().downcast_ref::<CapacityExceeded>();
+        let expected = CapacityExceeded {
+            needed: 65,
+            available: 64,
+        };
+        assert_eq!(exceeded, Some(&expected));
+        assert!(buf.is_empty());
+
+        assert_eq!(buf.write_vectored_all(&[]).unwrap(), 0);
+        assert_eq!(buf.write_vectored_all(&[IoSlice::new(&[])]).unwrap(), 0);
+        assert!(buf.is_empty());
+        drop(buf);
+
+        let entries = ring.snapshot_entries();
+        assert_eq!(entries[0].len, 64);
+        // the failed and empty copies left nothing to provide
+        assert_eq!((ring.provided(), ring.parked()), (1, 3));
+        drop(ring.unregister_simulated());
+    }
+
+    #[test]
+    #[cfg(feature = "bytes")]
+    fn buf_mut_advances_the_len() {
+        use bytes::BufMut;
+
+        let mut ring = parked_ring();
+        let mut buf = ring.take_free().unwrap();
+        buf.put_slice(b"abc");
+        buf.put_u16(0x0102);
+        assert_eq!(buf.remaining_mut(), 59);
+        assert_eq!(buf.filled(), [b'a', b'b', b'c', 1, 2]);
+        buf.provide();
+        assert_eq!(ring.snapshot_entries()[0].len, 5);
+        drop(ring.unregister_simulated());
+    }
+}
@@ -0,0 +1,108 @@
+use crate::buf_ring::{BidState, BufRing, state};
+use crate::buffer_id::BufferId;
+use io_uring::cqueue::EntryMarker;
+
+/// Buffers kept out of rotation, re-provided together once they're done with.
+#[derive(Debug, Default)]
+pub struct KeptBuffers {
+    bgid: Option<u16>,
+    ids: Vec<u16>,
+}
+
+impl KeptBuffers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// keeps `id` instead of recycling it when it goes out of scope
+    pub fn keep<E: EntryMarker>(&mut self, id: BufferId<'_, '_, E>) {
+        let bgid = id.bgid();
+        assert_eq!(*self.bgid.get_or_insert(bgid), bgid, "buffers kept from different rings");
+        self.ids.push(id.keep());
+    }
+
+    pub fn ids(&self) -> &[u16] {
+        &self.ids
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Re-provides every kept buffer to `ring` and advances once, parking
+    /// those past the provision limit.
+    ///
+    /// # Panics
+    ///
+    /// If `ring` isn't the ring the buffers were kept from.
+    pub fn reprovide_all(&mut self, ring: &mut BufRing<state::Init>) {
+        if self.ids.is_empty() {
+            return;
+        }
+        assert_eq!(self.bgid, Some(ring.bgid()), "buffers kept from a different ring");
+        for &id in &self.ids {
+            assert_eq!(ring.bid_state(id), BidState::HeldByApp, "buffer {id} isn't held");
+        }
+
+        // SAFETY: every id was handed out by `ring` and kept exactly once
+        unsafe { ring.recycle_batch(&self.ids) };
+        self.ids.clear();
+        self.bgid = None;
+    }
+}
+
+/// A received buffer kept out of rotation until it is dropped, see
+/// [`BufferId::into_cursor`].
+pub struct KeptBuffer<'a> {
+    ring: &'a mut BufRing<state::Init>,
+    buf_id: u16,
+    offset: u32,
+    len: u32,
+}
+
+impl<'a> KeptBuffer<'a> {
+    pub(crate) fn new(
+        ring: &'a mut BufRing<state::Init>,
+        buf_id: u16,
+        offset: u32,
+        len: u32,
+    ) -> Self {
+        Self {
+            ring,
+            buf_id,
+            offset,
+            len,
+        }
+    }
+
+    pub fn buf_id(&self) -> u16 {
+        self.buf_id
+    }
+}
+
+impl core::ops::Deref for KeptBuffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        let start = self.offset as usize;
+        // SAFETY: `buf_id` came from the kernel and `offset + len` <= `buf_size`
+        unsafe { &self.ring.buffer(self.buf_id)[start..start + self.len as usize] }
+    }
+}
+
+impl AsRef<[u8]> for KeptBuffer<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for KeptBuffer<'_> {
+    fn drop(&mut self) {
+        // SAFETY: the buffer was kept, so the application still holds it
+        unsafe { self.ring.recycle(self.buf_id) }
+    }
+}
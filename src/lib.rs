@@ -1,7 +1,106 @@
+//! Abstraction over BufRingEntries provided by io_uring
+//!
+//! # Thread safety
+//!
+//! | Type | `Send` | `Sync` |
+//! |------|--------|--------|
+//! | [`BufRing`], [`StaticBufRing`], [`SingleProducerRing`], [`ElasticPool`], [`SendBufRing`] | yes | no |
+//! | [`BufferId`], [`FreeBuffer`], [`Bundle`], [`SmallBatch`], [`ProcessBudget`], [`SentBuffer`], [`Multishot`] | no, they borrow the ring | no |
+//! | [`CompletionResolver`], [`Recycler`] | no | no |
+//! | [`ConcurrentRecycler`] | yes | yes |
+//! | [`OwnedBuffer`], [`SharedBufRing`], [`SharedBuffer`] | yes | yes |
+//! | [`OwnedBufRing`] | no, it holds a `&Submitter` | no |
+//!
+//! A ring is built on one thread and moved to the one that owns it. Handles
+//! borrowing it stay on that thread. Data crosses threads detached from the
+//! ring, as an [`OwnedBuffer`] or a [`SharedBuffer`], and buffers go back
+//! through a [`ConcurrentRecycler`] or a [`SharedBufRing`]. The table is
+//! checked by the compile tests in `tests/ui`.
+//!
+//! [`Bundle`]: bundle::Bundle
+//! [`CompletionResolver`]: split::CompletionResolver
+//! [`Recycler`]: split::Recycler
+
+// failed lifecycle transitions hand the ring back by value in the error
+#![allow(clippy::result_large_err)]
+
+pub mod budget;
 pub mod buf_ring;
 pub mod buffer_id;
+pub mod bundle;
+#[cfg(feature = "async-channel")]
+pub mod channel;
+pub mod concurrent;
+mod cqe;
+#[cfg(feature = "tokio")]
+pub mod driver;
+pub mod elastic;
+pub mod free_buffer;
+mod kept;
+#[cfg(feature = "thread-per-core")]
+pub mod local;
+pub mod metadata;
+pub mod multishot;
+pub mod observer;
+pub mod owned_buffer;
+pub mod owned_ring;
+pub mod registry;
+#[cfg(feature = "metrics")]
+mod ring_metrics;
+pub mod send;
+pub mod shared;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+pub mod single_producer;
+pub mod small_batch;
+pub mod split;
+mod sqe;
+pub mod static_buf_ring;
+#[cfg(feature = "stats")]
+pub mod stats;
+#[cfg(feature = "futures")]
+pub mod stream;
+mod sync;
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
+mod watermark;
 
-pub use buf_ring::BufRing;
+pub use budget::ProcessBudget;
+pub use buf_ring::{
+    BidState, BufRing, BufRingConfig, ENTRY_SIZE, EntrySnapshot, InvariantViolation, MAX_ENTRIES,
+    RawCompletion, ReconfigureError, RegistrationInfo, RegistrationMode, RingError,
+    SQPOLL_REGISTER_ATTEMPTS, SqpollRegisterError, plan_memory, suggested_entries,
+};
 pub use buffer_id::BufferId;
+pub use concurrent::ConcurrentRecycler;
+#[cfg(feature = "tokio")]
+pub use driver::{AsyncRingDriver, DriverBuffer};
+pub use elastic::ElasticPool;
+pub use free_buffer::FreeBuffer;
+pub use kept::{KeptBuffer, KeptBuffers};
+#[cfg(feature = "thread-per-core")]
+pub use local::{LocalBufRing, LocalRingExt};
+pub use metadata::MetadataTable;
+pub use multishot::Multishot;
+pub use observer::RingObserver;
+pub use owned_buffer::OwnedBuffer;
+pub use owned_ring::OwnedBufRing;
+pub use registry::RingRegistry;
+pub use send::{SendBufRing, SentBuffer};
+pub use shared::{SharedBufRing, SharedBuffer};
+#[cfg(feature = "sim")]
+pub use sim::SimKernel;
+pub use single_producer::SingleProducerRing;
+pub use small_batch::SmallBatch;
+#[cfg(feature = "test-support")]
+pub use cqe::FakeCqe;
+pub use cqe::{CqeFields, cqe_buffer_id, cqe_has_buffer, is_enobufs};
+pub use static_buf_ring::{StaticBufRing, StaticBufferId};
+#[cfg(feature = "stats")]
+pub use stats::Stats;
+#[cfg(feature = "futures")]
+pub use stream::CompletionStream;
+#[cfg(feature = "watchdog")]
+pub use watchdog::StaleReport;
 
 pub use buf_ring::state as buf_ring_state;
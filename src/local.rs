@@ -0,0 +1,346 @@
+//! Wiring a ring into a thread-per-core runtime.
+//!
+//! Each core owns its io_uring, and with it one [`LocalBufRing`] installed by
+//! [`LocalRingExt::install_buf_ring`]. The runtime routes its completions
+//! through [`LocalBufRing::dispatch`] and calls
+//! [`LocalBufRing::end_of_tick`] once per turn of its event loop, which
+//! returns every buffer dropped during the tick in a single publication.
+//!
+//! Nothing here is `Send`: the ring, its receivers and their buffers stay on
+//! the core that created them.
+
+use crate::buf_ring::{BufRing, BufRingConfig, state};
+use io_uring::{IoUring, cqueue, opcode, squeue, types};
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+use std::task::{Poll, Waker};
+
+/// Installs buffer rings on the io_uring of the current core.
+pub trait LocalRingExt {
+    /// Maps a ring with the geometry of `config`, registers it as `bgid` and
+    /// provides every buffer.
+    fn install_buf_ring(&self, config: BufRingConfig, bgid: u16) -> io::Result<LocalBufRing>;
+}
+
+impl<S: squeue::EntryMarker, C: cqueue::EntryMarker> LocalRingExt for IoUring<S, C> {
+    fn install_buf_ring(&self, config: BufRingConfig, bgid: u16) -> io::Result<LocalBufRing> {
+        let ring = BufRing::from_config(config, bgid)?
+            .register_and_init(&self.submitter())
+            .map_err(|(e, _)| e)?;
+        Ok(LocalBufRing {
+            shared: Rc::new(RefCell::new(Shared {
+                ring,
+                recvs: HashMap::new(),
+                closed: HashSet::new(),
+                returned: Vec::new(),
+            })),
+        })
+    }
+}
+
+struct Shared {
+    ring: BufRing<state::Init>,
+    /// Receivers by the `user_data` of their recv.
+    recvs: HashMap<u64, RecvState>,
+    /// `user_data` of recvs whose receiver was dropped while they still run.
+    closed: HashSet<u64>,
+    /// Buffers dropped since the last tick.
+    returned: Vec<u16>,
+}
+
+#[derive(Default)]
+struct RecvState {
+    ready: VecDeque<io::Result<(u16, u32)>>,
+    ended: bool,
+    waker: Option<Waker>,
+}
+
+/// A ring installed on the current core's io_uring.
+///
+/// Must be [uninstalled](Self::uninstall) before it is dropped, like a
+/// registered [`BufRing`].
+#[derive(Clone)]
+pub struct LocalBufRing {
+    shared: Rc<RefCell<Shared>>,
+}
+
+impl LocalBufRing {
+    /// A multishot recv on `fd` to submit, and the receiver of its buffers.
+    ///
+    /// `user_data` must be unique among the recvs of this ring, and lets
+    /// [`dispatch`](Self::dispatch) route its completions.
+    pub fn recv_multishot(&self, fd: RawFd, user_data: u64) -> (squeue::Entry, LocalRecv) {
+        let mut shared = self.shared.borrow_mut();
+        let sqe = opcode::RecvMulti::new(types::Fd(fd), shared.ring.bgid())
+            .build()
+            .user_data(user_data);
+        shared.recvs.insert(user_data, RecvState::default());
+        let recv = LocalRecv {
+            shared: self.shared.clone(),
+            user_data,
+        };
+        (sqe, recv)
+    }
+
+    /// Routes a completion to its receiver, returns false if it doesn't belong
+    /// to a recv of this ring.
+    pub fn dispatch(&self, cqe: &cqueue::Entry) -> bool {
+        let shared = &mut *self.shared.borrow_mut();
+        let user_data = cqe.user_data();
+        let more = cqueue::more(cqe.flags());
+
+        if shared.closed.contains(&user_data) {
+            if let Ok(Some(completion)) = shared.ring.resolve_raw(cqe.flags(), cqe.result()) {
+                shared.returned.push(completion.bid);
+            }
+            if !more {
+                shared.closed.remove(&user_data);
+            }
+            return true;
+        }
+        let Some(recv) = shared.recvs.get_mut(&user_data) else {
+            return false;
+        };
+
+        match shared.ring.resolve_raw(cqe.flags(), cqe.result()) {
+            Ok(Some(completion)) => recv.ready.push_back(Ok((completion.bid, completion.len))),
+            Ok(None) => {}
+            Err(e) => recv.ready.push_back(Err(e)),
+        }
+        recv.ended |= !more;
+        if let Some(waker) = recv.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+
+    /// Returns every buffer dropped since the last call to the kernel, in a
+    /// single publication. Call it once per tick of the runtime.
+    pub fn end_of_tick(&self) {
+        let shared = &mut *self.shared.borrow_mut();
+        // SAFETY: ids are pushed once, when their buffer is dropped
+        unsafe { shared.ring.recycle_batch(&shared.returned) };
+        shared.returned.clear();
+    }
+
+    pub fn bgid(&self) -> u16 {
+        self.shared.borrow().ring.bgid()
+    }
+
+    /// Unregisters the ring, once no receiver or buffer is left.
+    pub fn uninstall<S: squeue::EntryMarker, C: cqueue::EntryMarker>(
+        self,
+        uring: &IoUring<S, C>,
+    ) -> Result<BufRing<state::Uninit>, (io::Error, Self)> {
+        let shared = match Rc::try_unwrap(self.shared) {
+            Ok(shared) => shared.into_inner(),
+            Err(shared) => {
+                let e = io::Error::new(io::ErrorKind::ResourceBusy, "ring still in use");
+                return Err((e, Self { shared }));
+            }
+        };
+        let Shared {
+            mut ring, returned, ..
+        } = shared;
+        // SAFETY: as in `end_of_tick`
+        unsafe { ring.recycle_batch(&returned) };
+        ring.unregister(&uring.submitter()).map_err(|(e, ring)| {
+            let shared = Shared {
+                ring,
+                recvs: HashMap::new(),
+                closed: HashSet::new(),
+                returned: Vec::new(),
+            };
+            (
+                e,
+                Self {
+                    shared: Rc::new(RefCell::new(shared)),
+                },
+            )
+        })
+    }
+}
+
+/// The buffers received by one multishot recv.
+///
+/// Dropping it while the recv still runs recycles whatever it receives from
+/// then on; cancel the recv to stop it.
+pub struct LocalRecv {
+    shared: Rc<RefCell<Shared>>,
+    user_data: u64,
+}
+
+impl LocalRecv {
+    /// The next buffer, or `None` once the recv terminated and everything it
+    /// received was handed out.
+    pub async fn next(&mut self) -> Option<io::Result<LocalBuffer>> {
+        std::future::poll_fn(|cx| {
+            let mut shared = self.shared.borrow_mut();
+            let recv = shared
+                .recvs
+                .get_mut(&self.user_data)
+                .expect("receiver of an unknown recv");
+            match recv.ready.pop_front() {
+                Some(item) => {
+                    drop(shared);
+                    Poll::Ready(Some(item.map(|(buf_id, len)| LocalBuffer {
+                        shared: self.shared.clone(),
+                        buf_id,
+                        len,
+                    })))
+                }
+                None if recv.ended => Poll::Ready(None),
+                None => {
+                    recv.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+impl Drop for LocalRecv {
+    fn drop(&mut self) {
+        let shared = &mut *self.shared.borrow_mut();
+        let Some(recv) = shared.recvs.remove(&self.user_data) else {
+            return;
+        };
+        shared.returned.extend(
+            recv.ready
+                .into_iter()
+                .filter_map(|item| item.ok().map(|(buf_id, _)| buf_id)),
+        );
+        if !recv.ended {
+            shared.closed.insert(self.user_data);
+        }
+    }
+}
+
+/// A received buffer, returned at the end of the tick it is dropped in.
+pub struct LocalBuffer {
+    shared: Rc<RefCell<Shared>>,
+    buf_id: u16,
+    len: u32,
+}
+
+impl LocalBuffer {
+    pub fn buf_id(&self) -> u16 {
+        self.buf_id
+    }
+}
+
+impl core::ops::Deref for LocalBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the buffer is held by the application until this is dropped,
+        // and the `Rc` keeps the mapping alive
+        unsafe {
+            let ptr = self.shared.borrow().ring.buffer(self.buf_id).as_ptr();
+            core::slice::from_raw_parts(ptr, self.len as usize)
+        }
+    }
+}
+
+impl Drop for LocalBuffer {
+    fn drop(&mut self) {
+        self.shared.borrow_mut().returned.push(self.buf_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::os::fd::AsRawFd;
+    use std::pin::pin;
+    use std::task::Context;
+
+    /// One core: its own io_uring and ring, echoing everything received on
+    /// `stream` until the peer shuts down, with a runtime reduced to
+    /// dispatching completions, polling the one task and ending the tick.
+    fn echo_core(mut stream: TcpStream, bgid: u16) -> usize {
+        let mut uring = IoUring::new(32).unwrap();
+        let config = BufRingConfig {
+            entries: 8,
+            buf_size: 64,
+            opts: Default::default(),
+        };
+        let ring = uring.install_buf_ring(config, bgid).unwrap();
+        let (sqe, mut recv) = ring.recv_multishot(stream.as_raw_fd(), 1);
+        // SAFETY: the stream outlives the recv
+        unsafe { uring.submission().push(&sqe).unwrap() };
+
+        let mut task = pin!(async move {
+            let mut echoed = 0;
+            while let Some(buf) = recv.next().await {
+                let buf = buf.unwrap();
+                stream.write_all(&buf).unwrap();
+                echoed += buf.len();
+            }
+            echoed
+        });
+        let mut cx = Context::from_waker(Waker::noop());
+        let echoed = loop {
+            if let Poll::Ready(echoed) = task.as_mut().poll(&mut cx) {
+                break echoed;
+            }
+            ring.end_of_tick();
+            uring.submit_and_wait(1).unwrap();
+            for cqe in uring.completion() {
+                assert!(ring.dispatch(&cqe));
+            }
+        };
+        // the receiver went with the task
+        ring.end_of_tick();
+        assert_eq!(ring.shared.borrow().ring.provided(), 8);
+        ring.uninstall(&uring).map_err(|(e, _)| e).unwrap();
+        echoed
+    }
+
+    /// Two cores each serve their own connection, and every message comes
+    /// back whole and in order.
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn echo_on_two_cores() {
+        const MESSAGES: usize = 200;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let clients: Vec<_> = (0..2u8)
+            .map(|core| {
+                std::thread::spawn(move || {
+                    let mut stream = TcpStream::connect(addr).unwrap();
+                    for i in 0..MESSAGES {
+                        let msg = format!("core {core} message {i}");
+                        stream.write_all(msg.as_bytes()).unwrap();
+                        let mut echo = vec![0; msg.len()];
+                        stream.read_exact(&mut echo).unwrap();
+                        assert_eq!(echo, msg.as_bytes());
+                    }
+                    stream.shutdown(std::net::Shutdown::Write).unwrap();
+                })
+            })
+            .collect();
+
+        let cores: Vec<_> = (0..2u16)
+            .map(|core| {
+                let (stream, _) = listener.accept().unwrap();
+                std::thread::spawn(move || echo_core(stream, core))
+            })
+            .collect();
+
+        for client in clients {
+            client.join().unwrap();
+        }
+        for core in cores {
+            assert!(core.join().unwrap() > 0);
+        }
+    }
+}
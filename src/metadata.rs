@@ -0,0 +1,98 @@
+use core::any::Any;
+
+/// Per-buffer metadata indexed directly by buffer id.
+pub struct MetadataTable<T> {
+    slots: Box<[T]>,
+}
+
+impl<T: Default> MetadataTable<T> {
+    pub(crate) fn new(entries: u16) -> Self {
+        Self {
+            slots: (0..entries).map(|_| T::default()).collect(),
+        }
+    }
+}
+
+impl<T> MetadataTable<T> {
+    pub fn get(&self, buf_id: u16) -> Option<&T> {
+        self.slots.get(buf_id as usize)
+    }
+
+    pub fn get_mut(&mut self, buf_id: u16) -> Option<&mut T> {
+        self.slots.get_mut(buf_id as usize)
+    }
+}
+
+/// Type erased table stored in the ring, so it can reset slots on recycle.
+pub(crate) trait Metadata: Send {
+    fn reset(&mut self, buf_id: u16);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Default + Send + 'static> Metadata for MetadataTable<T> {
+    #[inline]
+    fn reset(&mut self, buf_id: u16) {
+        self.slots[buf_id as usize] = T::default();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buf_ring::BufRing;
+    use crate::cqe::FakeCqe;
+    use crate::sim::SimKernel;
+
+    /// Each buffer has its own slot, which is reset when the buffer is
+    /// recycled and not before.
+    #[test]
+    fn slots_are_per_buffer_and_reset_on_recycle() {
+        let mut ring = BufRing::new(4, 16, 0).unwrap().register_simulated();
+        let mut kernel = SimKernel::attach(&ring);
+        ring.with_metadata::<String>();
+
+        let mut held = Vec::new();
+        for name in ["a", "b"] {
+            let (flags, res) = kernel.recv(&ring, name.as_bytes(), true);
+            let cqe = FakeCqe { flags, res };
+            let mut id = ring.buffer_id_from_fields(&cqe).unwrap().unwrap();
+            id.metadata_mut::<String>().unwrap().push_str(name);
+            // SAFETY: every buffer is returned before the ring is released
+            held.push(unsafe { id.detach() });
+        }
+        let (a, b) = (held[0].buf_id(), held[1].buf_id());
+        assert_eq!(ring.meta::<String>(a).unwrap(), "a");
+        assert_eq!(ring.meta::<String>(b).unwrap(), "b");
+        for other in (0..4).filter(|&bid| bid != a && bid != b) {
+            assert_eq!(ring.meta::<String>(other).unwrap(), "");
+        }
+        // the wrong type finds no table
+        assert!(ring.meta::<u32>(a).is_none());
+
+        ring.recycle_many([held.remove(0)]).unwrap();
+        assert_eq!(ring.meta::<String>(a).unwrap(), "");
+        assert_eq!(ring.meta::<String>(b).unwrap(), "b");
+
+        // a buffer recycled on drop starts empty the next time around
+        let (flags, res) = kernel.recv(&ring, b"c", true);
+        let cqe = FakeCqe { flags, res };
+        let mut id = ring.buffer_id_from_fields(&cqe).unwrap().unwrap();
+        let c = id.buf_id();
+        id.metadata_mut::<String>().unwrap().push('c');
+        drop(id);
+        assert_eq!(ring.meta::<String>(c).unwrap(), "");
+        assert_eq!(ring.meta::<String>(b).unwrap(), "b");
+
+        ring.recycle_many(held).unwrap();
+        assert!((0..4).all(|bid| ring.meta::<String>(bid).unwrap().is_empty()));
+        drop(ring.unregister_simulated());
+    }
+}
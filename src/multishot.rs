@@ -0,0 +1,88 @@
+use crate::buf_ring::{BufRing, state};
+use crate::buffer_id::BufferId;
+use io_uring::cqueue::{self, Entry, EntryMarker};
+use io_uring::squeue;
+
+use core::marker::PhantomData;
+use std::os::fd::RawFd;
+
+/// The lifecycle of one multishot recv on `fd`.
+///
+/// [`arm`](Self::arm) returns the recv to submit. Each completion is resolved
+/// with [`completion`](Self::completion), which notices when the kernel
+/// terminated the recv, i.e. a completion without `IORING_CQE_F_MORE`, and
+/// [`arm`](Self::arm) then returns the recv to submit again. A recv that ran
+/// out of buffers is only re-armed once some were returned.
+pub struct Multishot<'a> {
+    ring: &'a mut BufRing<state::Init>,
+    fd: RawFd,
+    user_data: u64,
+    armed: bool,
+    /// Borrows the ring, which stays on its thread.
+    not_send: PhantomData<*const ()>,
+}
+
+impl<'a> Multishot<'a> {
+    /// `user_data` identifies the recv's completions, nothing is submitted yet.
+    pub fn new(ring: &'a mut BufRing<state::Init>, fd: RawFd, user_data: u64) -> Self {
+        Self {
+            ring,
+            fd,
+            user_data,
+            armed: false,
+            not_send: PhantomData,
+        }
+    }
+
+    /// The recv to submit if it isn't running, `None` if it already is or if
+    /// no buffer could be provided to it.
+    ///
+    /// Deferred recycles and parked buffers are provided first, see
+    /// [`BufRing::replenish_and_resubmit`].
+    pub fn arm(&mut self) -> Option<squeue::Entry> {
+        if self.armed {
+            return None;
+        }
+        let sqe = self.ring.replenish_and_resubmit(self.fd, self.user_data)?;
+        self.armed = true;
+        Some(sqe)
+    }
+
+    /// Resolves a completion of the recv.
+    ///
+    /// Errors are the completion's own, and terminate the recv like any
+    /// completion without `IORING_CQE_F_MORE`.
+    #[cfg_attr(feature = "watchdog", track_caller)]
+    pub fn completion<'b, E: EntryMarker>(
+        &mut self,
+        cqe: &'b E,
+    ) -> std::io::Result<Option<BufferId<'_, 'b, E>>> {
+        let e: Entry = cqe.clone().into();
+        debug_assert_eq!(
+            e.user_data(),
+            self.user_data,
+            "completion of another request"
+        );
+        if !cqueue::more(e.flags()) {
+            self.armed = false;
+        }
+        self.ring.buffer_id_from_cqe(cqe)
+    }
+
+    /// Whether the recv is submitted and hasn't terminated.
+    pub fn is_armed(&self) -> bool {
+        self.armed
+    }
+
+    pub fn user_data(&self) -> u64 {
+        self.user_data
+    }
+
+    pub fn ring(&self) -> &BufRing<state::Init> {
+        self.ring
+    }
+
+    pub fn ring_mut(&mut self) -> &mut BufRing<state::Init> {
+        self.ring
+    }
+}
@@ -0,0 +1,98 @@
+/// Instrumentation hooks called as buffers leave and re-enter the ring.
+///
+/// Observers never get access to the ring itself, so they can't re-enter it.
+pub trait RingObserver: Send {
+    /// A completion handed `buf_id` to the application with `len` bytes.
+    fn on_acquire(&mut self, buf_id: u16, len: u32) {
+        let _ = (buf_id, len);
+    }
+
+    /// `buf_id` was returned to the ring.
+    fn on_recycle(&mut self, buf_id: u16) {
+        let _ = buf_id;
+    }
+
+    /// A completion failed because the ring was empty.
+    fn on_enobufs(&mut self) {}
+
+    /// `count` entries were published to the kernel at once.
+    fn on_provide_batch(&mut self, count: u16) {
+        let _ = count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buf_ring::BufRing;
+    use crate::cqe::FakeCqe;
+    use crate::sim::SimKernel;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    enum Event {
+        Acquire(u16, u32),
+        Recycle(u16),
+        Enobufs,
+        Batch(u16),
+    }
+
+    struct Recorder(Arc<Mutex<Vec<Event>>>);
+
+    impl RingObserver for Recorder {
+        fn on_acquire(&mut self, buf_id: u16, len: u32) {
+            self.0.lock().unwrap().push(Event::Acquire(buf_id, len));
+        }
+
+        fn on_recycle(&mut self, buf_id: u16) {
+            self.0.lock().unwrap().push(Event::Recycle(buf_id));
+        }
+
+        fn on_enobufs(&mut self) {
+            self.0.lock().unwrap().push(Event::Enobufs);
+        }
+
+        fn on_provide_batch(&mut self, count: u16) {
+            self.0.lock().unwrap().push(Event::Batch(count));
+        }
+    }
+
+    /// Every hook fires once per event of a scripted workload, in order.
+    #[test]
+    fn sees_every_event_of_a_workload() {
+        use Event::*;
+
+        let mut ring = BufRing::new(4, 16, 0).unwrap().register_simulated();
+        let mut kernel = SimKernel::attach(&ring);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        ring.set_observer(Box::new(Recorder(events.clone())));
+
+        let mut held = Vec::new();
+        for payload in [&b"abc"[..], b"de"] {
+            let (flags, res) = kernel.recv(&ring, payload, true);
+            let cqe = FakeCqe { flags, res };
+            // SAFETY: every buffer is returned before the ring is released
+            held.push(unsafe { ring.buffer_id_from_fields(&cqe).unwrap().unwrap().detach() });
+        }
+        ring.recycle_many(held).unwrap();
+
+        let (flags, res) = kernel.recv(&ring, b"f", true);
+        drop(ring.buffer_id_from_fields(&FakeCqe { flags, res }).unwrap());
+        let cqe = FakeCqe::error(libc::ENOBUFS);
+        assert!(ring.buffer_id_from_fields(&cqe).is_err());
+
+        let expected = [
+            Acquire(0, 3),
+            Acquire(1, 2),
+            Recycle(0),
+            Recycle(1),
+            Batch(2),
+            Acquire(2, 1),
+            Recycle(2),
+            Batch(1),
+            Enobufs,
+        ];
+        assert_eq!(*events.lock().unwrap(), expected);
+        drop(ring.unregister_simulated());
+    }
+}
@@ -0,0 +1,68 @@
+/// Received data detached from its ring with [`BufferId::detach`].
+///
+/// Owning one doesn't borrow the ring, so it can be sent to another thread and
+/// back. Return it with [`BufRing::recycle_many`]; dropping it instead leaves
+/// the buffer held by the application for good.
+///
+/// [`BufferId::detach`]: crate::BufferId::detach
+/// [`BufRing::recycle_many`]: crate::BufRing::recycle_many
+#[derive(Debug)]
+pub struct OwnedBuffer {
+    ptr: *const u8,
+    len: u32,
+    buf_id: u16,
+    bgid: u16,
+}
+
+// SAFETY: the buffer is exclusively owned, and the ring owner only touches it
+// again once it is returned
+unsafe impl Send for OwnedBuffer {}
+unsafe impl Sync for OwnedBuffer {}
+
+impl OwnedBuffer {
+    pub(crate) fn new(ptr: *const u8, len: u32, buf_id: u16, bgid: u16) -> Self {
+        Self {
+            ptr,
+            len,
+            buf_id,
+            bgid,
+        }
+    }
+
+    pub fn buf_id(&self) -> u16 {
+        self.buf_id
+    }
+
+    pub fn bgid(&self) -> u16 {
+        self.bgid
+    }
+}
+
+impl core::ops::Deref for OwnedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the ring outlives the buffer, as required by `detach`
+        unsafe { core::slice::from_raw_parts(self.ptr, self.len as usize) }
+    }
+}
+
+/// Why [`BufRing::recycle_many`](crate::BufRing::recycle_many) rejected a buffer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RecycleError {
+    /// The buffer was detached from the ring with this bgid.
+    WrongRing { bgid: u16 },
+    /// The buffer isn't held by the application, e.g. it was returned twice.
+    NotHeld { buf_id: u16 },
+}
+
+impl core::fmt::Display for RecycleError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::WrongRing { bgid } => write!(f, "buffer belongs to buffer group {bgid}"),
+            Self::NotHeld { buf_id } => write!(f, "buffer {buf_id} isn't held by the application"),
+        }
+    }
+}
+
+impl std::error::Error for RecycleError {}
@@ -0,0 +1,113 @@
+use crate::buf_ring::{BufRing, state};
+use io_uring::Submitter;
+
+use core::mem::ManuallyDrop;
+
+/// A [`BufRing`] tied to the submitter it is registered with.
+///
+/// Lifecycle calls don't take a submitter, and dropping the ring unregisters
+/// it before unmapping, so it can't be dropped while the kernel may still write
+/// into it. If unregistering fails on drop the mapping is leaked instead.
+///
+/// The ring's own methods are reachable through `Deref`. Use
+/// [`into_inner`](Self::into_inner) for a ring that outlives the submitter.
+pub struct OwnedBufRing<'s, State> {
+    ring: ManuallyDrop<BufRing<State>>,
+    submitter: &'s Submitter<'s>,
+}
+
+impl<'s, S> OwnedBufRing<'s, S> {
+    pub fn submitter(&self) -> &'s Submitter<'s> {
+        self.submitter
+    }
+
+    /// The ring, no longer unregistered on drop.
+    pub fn into_inner(self) -> BufRing<S> {
+        self.into_parts().0
+    }
+
+    fn into_parts(self) -> (BufRing<S>, &'s Submitter<'s>) {
+        let mut this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never used or dropped again
+        (
+            unsafe { ManuallyDrop::take(&mut this.ring) },
+            this.submitter,
+        )
+    }
+
+    fn map<T>(
+        self,
+        f: impl FnOnce(BufRing<S>, &Submitter<'_>) -> Result<BufRing<T>, (std::io::Error, BufRing<S>)>,
+    ) -> Result<OwnedBufRing<'s, T>, (std::io::Error, Self)> {
+        let (ring, submitter) = self.into_parts();
+        match f(ring, submitter) {
+            Ok(ring) => Ok(OwnedBufRing::from_parts(ring, submitter)),
+            Err((e, ring)) => Err((e, Self::from_parts(ring, submitter))),
+        }
+    }
+
+    fn from_parts(ring: BufRing<S>, submitter: &'s Submitter<'s>) -> Self {
+        Self {
+            ring: ManuallyDrop::new(ring),
+            submitter,
+        }
+    }
+}
+
+impl<'s> OwnedBufRing<'s, state::Uninit> {
+    pub fn new(ring: BufRing<state::Uninit>, submitter: &'s Submitter<'s>) -> Self {
+        Self::from_parts(ring, submitter)
+    }
+
+    pub fn register(self) -> Result<OwnedBufRing<'s, state::Registered>, (std::io::Error, Self)> {
+        self.map(BufRing::<state::Uninit>::register)
+    }
+
+    /// Registers the ring and provides every buffer to the kernel.
+    pub fn register_and_init(
+        self,
+    ) -> Result<OwnedBufRing<'s, state::Init>, (std::io::Error, Self)> {
+        self.map(BufRing::<state::Uninit>::register_and_init)
+    }
+}
+
+impl<'s> OwnedBufRing<'s, state::Registered> {
+    pub fn init(self) -> OwnedBufRing<'s, state::Init> {
+        let (ring, submitter) = self.into_parts();
+        OwnedBufRing::from_parts(ring.init(), submitter)
+    }
+
+    pub fn unregister(self) -> Result<OwnedBufRing<'s, state::Uninit>, (std::io::Error, Self)> {
+        self.map(BufRing::<state::Registered>::unregister)
+    }
+}
+
+impl<'s> OwnedBufRing<'s, state::Init> {
+    pub fn unregister(self) -> Result<OwnedBufRing<'s, state::Uninit>, (std::io::Error, Self)> {
+        self.map(BufRing::<state::Init>::unregister)
+    }
+}
+
+impl<S> core::ops::Deref for OwnedBufRing<'_, S> {
+    type Target = BufRing<S>;
+
+    fn deref(&self) -> &BufRing<S> {
+        &self.ring
+    }
+}
+
+impl<S> core::ops::DerefMut for OwnedBufRing<'_, S> {
+    fn deref_mut(&mut self) -> &mut BufRing<S> {
+        &mut self.ring
+    }
+}
+
+impl<S> Drop for OwnedBufRing<'_, S> {
+    fn drop(&mut self) {
+        if self.ring.unregister_in_place(self.submitter).is_ok() {
+            // SAFETY: never used again
+            unsafe { ManuallyDrop::drop(&mut self.ring) }
+        }
+        // otherwise the kernel may still write into the mapping, leak it
+    }
+}
@@ -0,0 +1,86 @@
+use crate::buf_ring::{BufRing, state};
+use std::collections::HashSet;
+
+/// Hands out buffer group ids and refuses to register two rings with the same one.
+#[derive(Debug, Default)]
+pub struct RingRegistry {
+    allocated: HashSet<u16>,
+    registered: HashSet<u16>,
+}
+
+impl RingRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserves the lowest bgid that is neither allocated nor registered.
+    pub fn allocate_bgid(&mut self) -> Option<u16> {
+        let bgid = (0..=u16::MAX).find(|id| !self.is_in_use(*id))?;
+        self.allocated.insert(bgid);
+        Some(bgid)
+    }
+
+    /// Returns a bgid obtained from `allocate_bgid` that was never registered.
+    pub fn release_bgid(&mut self, bgid: u16) {
+        self.allocated.remove(&bgid);
+    }
+
+    pub fn is_in_use(&self, bgid: u16) -> bool {
+        self.allocated.contains(&bgid) || self.registered.contains(&bgid)
+    }
+
+    /// Registers `ring`, failing with `AlreadyExists` before reaching the kernel
+    /// if another ring registered through this registry uses the same bgid.
+    pub fn register(
+        &mut self,
+        ring: BufRing<state::Uninit>,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<BufRing<state::Registered>, (std::io::Error, BufRing<state::Uninit>)> {
+        let bgid = ring.bgid();
+        if self.registered.contains(&bgid) {
+            return Err((std::io::Error::from(std::io::ErrorKind::AlreadyExists), ring));
+        }
+
+        let ring = ring.register(submitter)?;
+        self.allocated.remove(&bgid);
+        self.registered.insert(bgid);
+        Ok(ring)
+    }
+
+    /// Unregisters `ring` and frees its bgid.
+    pub fn unregister<R: Unregister>(
+        &mut self,
+        ring: R,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<BufRing<state::Uninit>, (std::io::Error, R)> {
+        let ring = ring.unregister(submitter)?;
+        self.registered.remove(&ring.bgid());
+        Ok(ring)
+    }
+}
+
+/// A ring that is currently registered with the kernel.
+pub trait Unregister: Sized {
+    fn unregister(
+        self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<BufRing<state::Uninit>, (std::io::Error, Self)>;
+}
+
+impl Unregister for BufRing<state::Registered> {
+    fn unregister(
+        self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<BufRing<state::Uninit>, (std::io::Error, Self)> {
+        BufRing::<state::Registered>::unregister(self, submitter)
+    }
+}
+
+impl Unregister for BufRing<state::Init> {
+    fn unregister(
+        self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<BufRing<state::Uninit>, (std::io::Error, Self)> {
+        BufRing::<state::Init>::unregister(self, submitter)
+    }
+}
@@ -0,0 +1,160 @@
+use crate::buf_ring::{BidState, BufRing, RingError, state};
+use crate::buffer_id::BufferId;
+use crate::free_buffer::FreeBuffer;
+use io_uring::cqueue::{Entry, EntryMarker};
+
+use core::marker::PhantomData;
+
+/// A ring of buffers for sends: the application fills them, the kernel reads
+/// them, and a completion means the buffer is free again.
+///
+/// Nothing is provided on registration. Take a buffer with
+/// [`take_free`](Self::take_free), fill it, and [`provide`](FreeBuffer::provide)
+/// it, which publishes it with the length written. A send selecting from the
+/// buffer group then transmits the oldest provided buffer, and its completion
+/// resolves to a [`SentBuffer`].
+pub struct SendBufRing {
+    ring: BufRing<state::Init>,
+    /// Bytes the last send of each buffer transmitted, 0 if it wasn't sent yet.
+    sent: Box<[u32]>,
+}
+
+impl BufRing<state::Uninit> {
+    /// Registers the ring for sends, see [`SendBufRing`].
+    pub fn register_for_send(
+        self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<SendBufRing, (std::io::Error, Self)> {
+        let ring = self.register(submitter)?;
+        Ok(SendBufRing {
+            sent: vec![0; ring.entries() as usize].into_boxed_slice(),
+            ring: ring.init_parked(),
+        })
+    }
+}
+
+impl SendBufRing {
+    pub fn unregister(
+        self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<BufRing<state::Uninit>, (std::io::Error, Self)> {
+        let sent = self.sent;
+        self.ring
+            .unregister(submitter)
+            .map_err(|(e, ring)| (e, Self { ring, sent }))
+    }
+
+    /// A free buffer to fill, `None` if every buffer is provided or held.
+    pub fn take_free(&mut self) -> Option<FreeBuffer<'_>> {
+        self.ring.take_free()
+    }
+
+    /// Resolves the buffer a send completion transmitted from, if any.
+    ///
+    /// Errors are the completion's own. `ENOBUFS` means nothing was provided
+    /// when the send was issued.
+    #[cfg_attr(feature = "watchdog", track_caller)]
+    pub fn buffer_id_from_cqe<E: EntryMarker>(
+        &mut self,
+        cqe: &E,
+    ) -> std::io::Result<Option<SentBuffer<'_>>> {
+        let e: Entry = cqe.clone().into();
+        let Some(id) = BufferId::<Entry>::from_flags(&mut self.ring, e.flags(), e.result())? else {
+            return Ok(None);
+        };
+        let (buf_id, _, sent, _) = id.into_parts();
+        self.sent[buf_id as usize] = sent;
+        Ok(Some(SentBuffer {
+            ring: &mut self.ring,
+            buf_id,
+            sent,
+            not_send: PhantomData,
+        }))
+    }
+
+    /// Provides every buffer sent before again, contents untouched, to resend
+    /// the same payloads.
+    ///
+    /// Each buffer is provided with the length its last send transmitted, and
+    /// buffers that were never sent stay free. Returns how many were provided.
+    /// Fails while any buffer is still provided, since the kernel may be
+    /// reading it.
+    pub fn reprovide_all_preserving(&mut self) -> Result<u16, RingError> {
+        let sent = &self.sent;
+        self.ring
+            .reprovide_parked(|buf_id| Some(sent[buf_id as usize]).filter(|&len| len > 0))
+    }
+
+    /// Buffers filled and waiting to be sent.
+    pub fn provided(&self) -> u16 {
+        self.ring.provided()
+    }
+
+    /// Buffers free to be filled.
+    pub fn free(&self) -> u16 {
+        self.ring.parked()
+    }
+
+    pub fn entries(&self) -> u16 {
+        self.ring.entries()
+    }
+
+    pub fn buf_size(&self) -> u32 {
+        self.ring.buf_size()
+    }
+
+    pub fn bgid(&self) -> u16 {
+        self.ring.bgid()
+    }
+
+    /// Publishes provided buffers held back by deferred advance, see
+    /// [`BufRing::flush`].
+    pub fn flush(&mut self) {
+        self.ring.flush();
+    }
+}
+
+/// A buffer whose send completed.
+///
+/// Its contents are what was handed to the kernel, not data received.
+/// Dropping it frees the buffer for [`SendBufRing::take_free`], and
+/// [`refill`](Self::refill) reuses it right away.
+pub struct SentBuffer<'a> {
+    ring: &'a mut BufRing<state::Init>,
+    buf_id: u16,
+    sent: u32,
+    /// Borrows the ring, which stays on its thread.
+    not_send: PhantomData<*const ()>,
+}
+
+impl<'a> SentBuffer<'a> {
+    pub fn buf_id(&self) -> u16 {
+        self.buf_id
+    }
+
+    /// Bytes the kernel sent, which may fall short of the length provided on a
+    /// partial send.
+    pub fn sent(&self) -> u32 {
+        self.sent
+    }
+
+    /// The bytes that were sent.
+    pub fn sent_data(&self) -> &[u8] {
+        // SAFETY: `buf_id` came from the kernel and `sent` <= `buf_size`
+        unsafe { &self.ring.buffer(self.buf_id)[..self.sent as usize] }
+    }
+
+    /// Empties the buffer to fill it again.
+    pub fn refill(self) -> FreeBuffer<'a> {
+        let this = core::mem::ManuallyDrop::new(self);
+        // SAFETY: `this` is never used or dropped again
+        let ring = unsafe { core::ptr::read(&this.ring) };
+        FreeBuffer::new(ring, this.buf_id, BidState::Parked)
+    }
+}
+
+impl Drop for SentBuffer<'_> {
+    fn drop(&mut self) {
+        self.ring.park(self.buf_id);
+    }
+}
@@ -0,0 +1,360 @@
+use crate::buf_ring::{BufRing, RegistrationMode, state};
+use crate::concurrent::PublishQueue;
+use crate::cqe::{CqeFields, cqe_buffer_id, cqe_has_buffer};
+use crate::sync::{AtomicBool, Ordering};
+use io_uring::cqueue::{Entry, EntryMarker};
+
+use std::sync::Arc;
+
+/// A ring shared between threads, each processing its own completions.
+///
+/// Handles are cheap to clone. Completions resolve to [`SharedBuffer`]s, which
+/// are `Send` and go back to the kernel when dropped, from whichever thread
+/// holds them. Returns use the same lock free reservation and publication
+/// protocol as [`ConcurrentRecycler`](crate::ConcurrentRecycler), so there's no
+/// lock on the hot path.
+///
+/// The ring's own bookkeeping (bid states, stats, observers) is frozen while it
+/// is shared and catches up in [`into_inner`](Self::into_inner).
+///
+/// Dropping the last handle unmaps the ring once it was
+/// [unregistered](Self::unregister), and leaks it otherwise, like a registered
+/// [`BufRing`].
+#[derive(Clone)]
+pub struct SharedBufRing {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    ring: BufRing<state::Init>,
+    queue: PublishQueue,
+    /// Whether each buffer is held by a `SharedBuffer`.
+    held: Box<[AtomicBool]>,
+    unregistered: AtomicBool,
+}
+
+// SAFETY: the ring is only read through `&self`, entries are only written by
+// the queue into slots it claimed exclusively, and the tail is only moved
+// atomically
+unsafe impl Sync for Inner {}
+
+impl SharedBufRing {
+    /// Shares `ring`, publishing anything written but not yet published.
+    ///
+    /// # Panics
+    ///
+    /// If `ring` is registered in [`RegistrationMode::Incremental`], whose
+    /// partially filled buffers can't be tracked without the ring's bookkeeping.
+    pub fn new(mut ring: BufRing<state::Init>) -> Self {
+        assert_eq!(
+            ring.registration_mode(),
+            RegistrationMode::Plain,
+            "shared rings don't support incremental consumption"
+        );
+        ring.flush();
+        let queue = PublishQueue::new(&ring);
+        let held = (0..ring.entries())
+            .map(|_| AtomicBool::new(false))
+            .collect();
+        Self {
+            inner: Arc::new(Inner {
+                ring,
+                queue,
+                held,
+                unregistered: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Resolves the buffer `cqe` selected, if any.
+    ///
+    /// Errors are the completion's own, as with [`BufRing::buffer_id_from_cqe`].
+    ///
+    /// # Panics
+    ///
+    /// If the buffer is already held, e.g. because the completion was resolved
+    /// twice or came from another ring.
+    pub fn resolve_cqe<E: EntryMarker>(&self, cqe: &E) -> std::io::Result<Option<SharedBuffer>> {
+        let e: Entry = cqe.clone().into();
+        self.resolve_fields(&e)
+    }
+
+    /// Like [`resolve_cqe`](Self::resolve_cqe), for anything carrying the
+    /// fields of a completion, e.g. a [`FakeCqe`](crate::cqe::FakeCqe) in tests.
+    pub fn resolve_fields<C: CqeFields>(&self, cqe: &C) -> std::io::Result<Option<SharedBuffer>> {
+        let res = cqe.result();
+        if res < 0 {
+            return Err(std::io::Error::from_raw_os_error(-res));
+        }
+        if !cqe_has_buffer(cqe.flags()) {
+            return Ok(None);
+        }
+
+        let buf_id = cqe_buffer_id(cqe.flags());
+        let held = self
+            .inner
+            .held
+            .get(buf_id as usize)
+            .expect("buffer id out of range");
+        assert!(
+            !held.swap(true, Ordering::Acquire),
+            "buffer {buf_id} is already held"
+        );
+
+        Ok(Some(SharedBuffer {
+            ring: self.clone(),
+            buf_id,
+            len: res as u32,
+        }))
+    }
+
+    pub fn bgid(&self) -> u16 {
+        self.inner.ring.bgid()
+    }
+
+    pub fn entries(&self) -> u16 {
+        self.inner.ring.entries()
+    }
+
+    pub fn buf_size(&self) -> u32 {
+        self.inner.ring.buf_size()
+    }
+
+    /// Unregisters the buffer group, so the last handle unmaps the ring.
+    ///
+    /// Buffers still held stay readable until they are dropped.
+    pub fn unregister(&self, submitter: &io_uring::Submitter<'_>) -> std::io::Result<()> {
+        submitter.unregister_buf_ring(self.bgid())?;
+        self.inner.unregistered.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    /// Takes the ring back if this is the last handle and no buffer is held.
+    ///
+    /// Hands the handle back otherwise, and always once the ring was
+    /// [unregistered](Self::unregister), which can then only be dropped.
+    pub fn into_inner(self) -> Result<BufRing<state::Init>, Self> {
+        if self.inner.unregistered.load(Ordering::Acquire) {
+            return Err(self);
+        }
+        let inner = Arc::try_unwrap(self.inner).map_err(|inner| Self { inner })?;
+        let mut inner = core::mem::ManuallyDrop::new(inner);
+        // SAFETY: `inner` is never used or dropped again, and `unregistered`
+        // needs no drop
+        let (mut ring, mut queue) = unsafe {
+            core::ptr::drop_in_place(&mut inner.held);
+            (core::ptr::read(&inner.ring), core::ptr::read(&inner.queue))
+        };
+        queue.finish(&mut ring);
+        Ok(ring)
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if self.unregistered.load(Ordering::Acquire) {
+            // SAFETY: the kernel no longer uses the buffer group
+            unsafe { self.ring.forget_registration() };
+        }
+    }
+}
+
+/// A buffer resolved through a [`SharedBufRing`], returned to the kernel when
+/// dropped.
+pub struct SharedBuffer {
+    ring: SharedBufRing,
+    buf_id: u16,
+    len: u32,
+}
+
+impl SharedBuffer {
+    pub fn buf_id(&self) -> u16 {
+        self.buf_id
+    }
+
+    pub fn bgid(&self) -> u16 {
+        self.ring.bgid()
+    }
+}
+
+impl core::ops::Deref for SharedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the buffer is held by this handle, and `len` came from the kernel
+        unsafe { &self.ring.inner.ring.buffer(self.buf_id)[..self.len as usize] }
+    }
+}
+
+impl Drop for SharedBuffer {
+    fn drop(&mut self) {
+        let inner = &self.ring.inner;
+        // released before the entry is published: once the kernel can hand the
+        // buffer out again, resolving it must not find it held
+        inner.held[self.buf_id as usize].store(false, Ordering::Release);
+        // SAFETY: the buffer is held by this handle, which returns it only once
+        unsafe { inner.queue.recycle(&inner.ring, self.buf_id) }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use crate::cqe::FakeCqe;
+    use crate::sim::SimKernel;
+    use std::sync::mpsc;
+
+    const ENTRIES: u16 = 64;
+    const BUF_SIZE: u32 = 32;
+    const WORKERS: usize = 4;
+    const ROUNDS: usize = 200_000;
+
+    struct Rng(u32);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 as usize % n
+        }
+    }
+
+    /// Resolves completions of a simulated kernel on several threads, which
+    /// hold a few buffers each and drop them in random order. Resolving
+    /// panics on a buffer already held, and every buffer must still hold what
+    /// the kernel received into it when it is dropped.
+    #[test]
+    #[cfg_attr(miri, ignore = "too slow under Miri")]
+    fn stress_resolve_and_recycle_from_every_thread() {
+        let ring = BufRing::new(ENTRIES, BUF_SIZE, 0)
+            .unwrap()
+            .register_simulated();
+        let shared = SharedBufRing::new(ring);
+
+        std::thread::scope(|s| {
+            let (senders, receivers): (Vec<_>, Vec<_>) = (0..WORKERS)
+                .map(|_| mpsc::channel::<(FakeCqe, u8)>())
+                .unzip();
+            for (i, rx) in receivers.into_iter().enumerate() {
+                let shared = shared.clone();
+                s.spawn(move || {
+                    let mut rng = Rng(0x9e37_79b9 ^ i as u32);
+                    let mut held: Vec<(SharedBuffer, u8)> = Vec::new();
+                    let check = |(buf, tag): &(SharedBuffer, u8)| {
+                        assert!(
+                            buf.iter().all(|b| b == tag),
+                            "buffer {} overwritten while held",
+                            buf.buf_id()
+                        );
+                    };
+                    for (cqe, tag) in rx {
+                        let buf = shared.resolve_fields(&cqe).unwrap().unwrap();
+                        assert_eq!(buf.len(), BUF_SIZE as usize);
+                        held.push((buf, tag));
+                        if held.len() > 3 {
+                            let held = held.swap_remove(rng.below(held.len()));
+                            check(&held);
+                        }
+                    }
+                    held.iter().for_each(check);
+                });
+            }
+
+            let ring = &shared.inner.ring;
+            let mut kernel = SimKernel::attach(ring);
+            let mut rng = Rng(0x2545_f491);
+            let mut received = 0;
+            while received < ROUNDS {
+                let tag = received as u8;
+                let (flags, res) = kernel.recv(ring, &[tag; BUF_SIZE as usize], true);
+                if res == -libc::ENOBUFS {
+                    std::thread::yield_now();
+                    continue;
+                }
+                senders[rng.below(WORKERS)]
+                    .send((FakeCqe { flags, res }, tag))
+                    .unwrap();
+                received += 1;
+            }
+        });
+
+        let tail = shared.inner.ring.shared_tail().load(Ordering::Acquire);
+        assert_eq!(tail, (ENTRIES as usize + ROUNDS) as u16);
+        let ring = shared.into_inner().ok().unwrap();
+        drop(ring.unregister_simulated());
+    }
+
+    #[test]
+    fn into_inner_hands_back_other_and_unregistered_handles() {
+        let ring = BufRing::new(4, 32, 0).unwrap().register_simulated();
+        let shared = SharedBufRing::new(ring);
+        let other = shared.clone();
+        let shared = shared.into_inner().err().unwrap();
+        drop(other);
+        let ring = shared.into_inner().ok().unwrap();
+        drop(ring.unregister_simulated());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn into_inner_hands_back_an_unregistered_ring() {
+        let uring = io_uring::IoUring::new(8).unwrap();
+        let ring = BufRing::new(4, 32, 3).unwrap();
+        let ring = ring
+            .register_and_init(&uring.submitter())
+            .map_err(|(e, _)| e)
+            .unwrap();
+        let shared = SharedBufRing::new(ring);
+        shared.unregister(&uring.submitter()).unwrap();
+        // the last handle, but only dropping it unmaps the ring
+        let shared = shared.into_inner().err().unwrap();
+        drop(shared);
+    }
+
+    #[test]
+    #[should_panic(expected = "already held")]
+    fn resolving_a_held_buffer_panics() {
+        let ring = BufRing::new(4, 32, 0).unwrap().register_simulated();
+        let shared = SharedBufRing::new(ring);
+        let cqe = FakeCqe::buffer(1, 8, true);
+        let _held = shared.resolve_fields(&cqe).unwrap();
+        let _ = shared.resolve_fields(&cqe);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use crate::buf_ring::model::ModelKernel;
+    use crate::cqe::FakeCqe;
+
+    /// A buffer dropped on one thread is published, and the kernel hands it
+    /// out again to another: resolving it there must not find it held.
+    #[test]
+    fn loom_released_before_published() {
+        loom::model(|| {
+            let ring = BufRing::new(4, 64, 0).unwrap().into_registered();
+            let shared = SharedBufRing::new(ring.init_parked());
+            let kernel = ModelKernel::attach(&shared.inner.ring);
+
+            let buf = shared
+                .resolve_fields(&FakeCqe::buffer(0, 64, true))
+                .unwrap();
+            let dropper = loom::thread::spawn(move || drop(buf));
+            let handle = shared.clone();
+            let consumer = loom::thread::spawn(move || {
+                while kernel.consume(0) == 0 {
+                    loom::thread::yield_now();
+                }
+                let again = handle.resolve_fields(&FakeCqe::buffer(0, 64, true));
+                drop(again);
+            });
+            dropper.join().unwrap();
+            consumer.join().unwrap();
+
+            let ring = shared.into_inner().ok().unwrap();
+            drop(ring.unregister_simulated());
+        });
+    }
+}
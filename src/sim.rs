@@ -0,0 +1,209 @@
+//! A simulated kernel for exercising rings without io_uring.
+//!
+//! A ring moved to `Init` with [`BufRing::register_simulated`] is never
+//! registered with a kernel. [`SimKernel`] consumes its published entries the
+//! way the kernel does, writes scripted payloads into the selected buffers, and
+//! returns the `(flags, res)` of the completion, to be fed back through
+//! [`BufRing::resolve_raw`] or the resolution path under test.
+
+use crate::buf_ring::{BufRing, state};
+use crate::sync::Ordering;
+
+// io_uring doesn't expose its sys bindings
+// so they've been redefined here.
+const IORING_CQE_F_BUFFER: u32 = 1;
+const IORING_CQE_F_MORE: u32 = 1 << 1;
+const IORING_CQE_BUFFER_SHIFT: u32 = 16;
+
+impl BufRing<state::Uninit> {
+    /// Provides every buffer without registering the ring, for a
+    /// [`SimKernel`] to consume.
+    pub fn register_simulated(self) -> BufRing<state::Init> {
+        self.into_registered().init()
+    }
+}
+
+impl BufRing<state::Init> {
+    /// Moves a ring set up with [`register_simulated`](BufRing::register_simulated)
+    /// back to `Uninit`.
+    pub fn unregister_simulated(mut self) -> BufRing<state::Uninit> {
+        self.reset_registration();
+        // SAFETY: same type layout
+        unsafe { core::mem::transmute(self) }
+    }
+}
+
+/// The consumer side of a simulated ring.
+///
+/// Keeps its own head like the kernel, starting at 0 as on registration, and
+/// only ever reads entries published with a `Release` tail store.
+#[derive(Debug)]
+pub struct SimKernel {
+    bgid: u16,
+    head: u16,
+}
+
+impl SimKernel {
+    pub fn attach(ring: &BufRing<state::Init>) -> Self {
+        Self {
+            bgid: ring.bgid(),
+            head: 0,
+        }
+    }
+
+    /// Entries consumed so far, as `IORING_REGISTER_PBUF_STATUS` reports it.
+    pub fn head(&self) -> u16 {
+        self.head
+    }
+
+    /// Published entries not consumed yet.
+    pub fn available(&self, ring: &BufRing<state::Init>) -> u16 {
+        self.check(ring);
+        self.tail(ring).wrapping_sub(self.head)
+    }
+
+    /// Receives `payload` into the next buffer, truncated to its length, and
+    /// returns the completion. `more` is whether the multishot recv goes on.
+    ///
+    /// Completes with `ENOBUFS`, and without `IORING_CQE_F_MORE`, once nothing
+    /// is left to consume.
+    pub fn recv(&mut self, ring: &BufRing<state::Init>, payload: &[u8], more: bool) -> (u32, i32) {
+        if self.available(ring) == 0 {
+            return (0, -libc::ENOBUFS);
+        }
+        let (bid, n) = self.fill_next(ring, payload);
+        (Self::flags(bid, more), n as i32)
+    }
+
+    /// Receives `payload` as a bundle, filling consecutive buffers until it
+    /// fits, and returns the completion.
+    ///
+    /// Completes with `ENOBUFS` if nothing is left to consume, and truncates
+    /// the payload once the ring runs out.
+    pub fn recv_bundle(
+        &mut self,
+        ring: &BufRing<state::Init>,
+        payload: &[u8],
+        more: bool,
+    ) -> (u32, i32) {
+        if self.available(ring) == 0 {
+            return (0, -libc::ENOBUFS);
+        }
+        let (first, mut total) = self.fill_next(ring, payload);
+        while (total as usize) < payload.len() && self.available(ring) > 0 {
+            total += self.fill_next(ring, &payload[total as usize..]).1;
+        }
+        (Self::flags(first, more), total as i32)
+    }
+
+    /// Receives `payload` into every published buffer, then completes with
+    /// `ENOBUFS`, as a recv does when the application stops recycling.
+    pub fn exhaust(&mut self, ring: &BufRing<state::Init>, payload: &[u8]) -> Vec<(u32, i32)> {
+        let mut cqes = Vec::with_capacity(self.available(ring) as usize + 1);
+        while self.available(ring) > 0 {
+            cqes.push(self.recv(ring, payload, true));
+        }
+        cqes.push((0, -libc::ENOBUFS));
+        cqes
+    }
+
+    /// Consumes the entry at the head and copies as much of `payload` as fits
+    /// into its buffer, returns the buffer id and the bytes copied.
+    fn fill_next(&mut self, ring: &BufRing<state::Init>, payload: &[u8]) -> (u16, u32) {
+        // SAFETY: the entry was published before the tail that was read
+        let entry = unsafe { &*ring.entry_ptr(self.head as u32) };
+        self.head = self.head.wrapping_add(1);
+
+        let (base, size) = ring.data_region();
+        let offset = entry.addr().wrapping_sub(base as u64);
+        let n = payload.len().min(entry.len() as usize);
+        assert!(
+            offset.saturating_add(n as u64) <= size as u64,
+            "entry points outside the ring's buffers"
+        );
+        // SAFETY: in bounds of the buffers, which the application doesn't
+        // touch while they are provided
+        unsafe {
+            let dst = base.cast_mut().add(offset as usize);
+            core::ptr::copy_nonoverlapping(payload.as_ptr(), dst, n);
+        }
+        (entry.bid(), n as u32)
+    }
+
+    fn tail(&self, ring: &BufRing<state::Init>) -> u16 {
+        ring.shared_tail().load(Ordering::Acquire)
+    }
+
+    fn check(&self, ring: &BufRing<state::Init>) {
+        assert_eq!(
+            ring.bgid(),
+            self.bgid,
+            "simulated kernel attached to another ring"
+        );
+    }
+
+    fn flags(bid: u16, more: bool) -> u32 {
+        let more = if more { IORING_CQE_F_MORE } else { 0 };
+        IORING_CQE_F_BUFFER | (bid as u32) << IORING_CQE_BUFFER_SHIFT | more
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::owned_buffer::OwnedBuffer;
+    use crate::cqe::FakeCqe;
+
+    fn consume(ring: &mut BufRing<state::Init>, buf: OwnedBuffer, received: &mut Vec<u8>) {
+        received.extend_from_slice(&buf);
+        assert_eq!(ring.recycle_many([buf]).ok(), Some(1));
+    }
+
+    /// The simulated counterpart of a multishot recv over a socket: a stream
+    /// arrives in buffer sized chunks faster than the application consumes
+    /// them, the recv ends with `ENOBUFS` whenever the ring runs dry and is
+    /// rearmed once buffers come back, and the stream reads back in order.
+    #[test]
+    fn multishot_rearms_after_enobufs() {
+        let payload: Vec<u8> = (0..64 * 16).map(|i| (i % 251) as u8).collect();
+        let mut ring = BufRing::new(4, 16, 0).unwrap().register_simulated();
+        let mut kernel = SimKernel::attach(&ring);
+
+        let mut chunks = payload.chunks(16);
+        let mut next = chunks.next();
+        let mut held = Vec::new();
+        let (mut received, mut rearms) = (Vec::new(), 0);
+        while let Some(chunk) = next {
+            let (flags, res) = kernel.recv(&ring, chunk, true);
+            let cqe = FakeCqe { flags, res };
+            // SAFETY: every buffer is returned before the ring is released
+            let resolved = ring
+                .buffer_id_from_fields(&cqe)
+                .map(|id| unsafe { id.unwrap().detach() });
+            match resolved {
+                Ok(buf) => {
+                    held.push(buf);
+                    next = chunks.next();
+                }
+                Err(e) => {
+                    assert_eq!(e.raw_os_error(), Some(libc::ENOBUFS));
+                    assert!(ring.replenish_and_resubmit(0, 1).is_none());
+                    // the consumer catches up
+                    for buf in held.drain(..) {
+                        consume(&mut ring, buf, &mut received);
+                    }
+                    assert!(ring.replenish_and_resubmit(0, 1).is_some());
+                    rearms += 1;
+                }
+            }
+        }
+        for buf in held {
+            consume(&mut ring, buf, &mut received);
+        }
+        assert_eq!(received, payload);
+        // the last four chunks fit without running dry
+        assert_eq!(rearms, 15);
+        assert_eq!(ring.provided(), 4);
+        drop(ring.unregister_simulated());
+    }
+}
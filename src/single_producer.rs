@@ -0,0 +1,158 @@
+use crate::buf_ring::{BufRing, RawCompletion, RingError, state};
+use crate::buffer_id::BufferId;
+use crate::bundle::Bundle;
+use crate::cqe::CqeFields;
+use crate::owned_buffer::{OwnedBuffer, RecycleError};
+use io_uring::cqueue::EntryMarker;
+
+/// A ring that publishes its tail with a plain store, see
+/// [`BufRing::into_single_producer`].
+///
+/// It has no way to hand out a [`ConcurrentRecycler`](crate::ConcurrentRecycler),
+/// whose increments the stores would overwrite. The ring's other methods are
+/// reachable through `Deref`, and the ones that change it are forwarded; use
+/// [`into_inner`](Self::into_inner) for anything else.
+pub struct SingleProducerRing {
+    ring: BufRing<state::Init>,
+}
+
+impl BufRing<state::Init> {
+    /// Publishes the tail with a plain store instead of an atomic
+    /// read-modify-write.
+    ///
+    /// The kernel only ever reads the tail. If this ring is the only writer,
+    /// its own copy of the tail is always current, so the new value can be
+    /// computed locally and stored with `Release` ordering, which orders the
+    /// entry writes before it just like the `fetch_add` does, without a locked
+    /// instruction.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may write the tail for as long as the ring is single
+    /// producer, e.g. foreign code through [`tail_ptr`](Self::tail_ptr).
+    pub unsafe fn into_single_producer(mut self) -> SingleProducerRing {
+        self.set_single_producer(true);
+        SingleProducerRing { ring: self }
+    }
+}
+
+impl SingleProducerRing {
+    /// The ring, publishing with `fetch_add` again.
+    pub fn into_inner(mut self) -> BufRing<state::Init> {
+        self.ring.set_single_producer(false);
+        self.ring
+    }
+
+    pub fn unregister(
+        self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<BufRing<state::Uninit>, (std::io::Error, Self)> {
+        self.into_inner()
+            .unregister(submitter)
+            .map_err(|(e, ring)| (e, Self { ring }.with_single_producer()))
+    }
+
+    fn with_single_producer(mut self) -> Self {
+        self.ring.set_single_producer(true);
+        self
+    }
+
+    /// See [`BufRing::buffer_id_from_cqe`].
+    #[cfg_attr(feature = "watchdog", track_caller)]
+    pub fn buffer_id_from_cqe<'a, 'b, E: EntryMarker>(
+        &'a mut self,
+        cqe: &'b E,
+    ) -> std::io::Result<Option<BufferId<'a, 'b, E>>> {
+        self.ring.buffer_id_from_cqe(cqe)
+    }
+
+    /// See [`BufRing::buffer_id_from_fields`].
+    #[cfg_attr(feature = "watchdog", track_caller)]
+    pub fn buffer_id_from_fields<'a, 'b, C: CqeFields>(
+        &'a mut self,
+        cqe: &'b C,
+    ) -> std::io::Result<Option<BufferId<'a, 'b, io_uring::cqueue::Entry>>> {
+        self.ring.buffer_id_from_fields(cqe)
+    }
+
+    /// See [`BufRing::bundle_from_cqe`].
+    pub fn bundle_from_cqe<E: EntryMarker>(
+        &mut self,
+        cqe: &E,
+    ) -> std::io::Result<Option<Bundle<'_>>> {
+        self.ring.bundle_from_cqe(cqe)
+    }
+
+    /// See [`BufRing::resolve_raw`].
+    #[cfg_attr(feature = "watchdog", track_caller)]
+    pub fn resolve_raw(&mut self, flags: u32, res: i32) -> std::io::Result<Option<RawCompletion>> {
+        self.ring.resolve_raw(flags, res)
+    }
+
+    /// See [`BufRing::recycle_many`].
+    pub fn recycle_many(
+        &mut self,
+        bufs: impl IntoIterator<Item = OwnedBuffer>,
+    ) -> Result<u16, (RecycleError, Vec<OwnedBuffer>)> {
+        self.ring.recycle_many(bufs)
+    }
+
+    /// See [`BufRing::provide_with_len`].
+    pub fn provide_with_len(&mut self, buf_id: u16, len: u32) -> Result<(), RingError> {
+        self.ring.provide_with_len(buf_id, len)
+    }
+
+    /// See [`BufRing::refill_unprovided`].
+    pub fn refill_unprovided(&mut self) -> u16 {
+        self.ring.refill_unprovided()
+    }
+
+    /// See [`BufRing::set_deferred_advance`].
+    pub fn set_deferred_advance(&mut self, deferred: bool) {
+        self.ring.set_deferred_advance(deferred);
+    }
+
+    /// See [`BufRing::flush`].
+    pub fn flush(&mut self) {
+        self.ring.flush();
+    }
+}
+
+impl core::ops::Deref for SingleProducerRing {
+    type Target = BufRing<state::Init>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.ring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cqe::FakeCqe;
+    use crate::sim::SimKernel;
+
+    /// Stored tails keep up with the simulated kernel across the wrap, and
+    /// the ring taken back out publishes with `fetch_add` again.
+    #[test]
+    #[cfg_attr(miri, ignore = "too slow under Miri")]
+    fn stores_publish_every_recycle() {
+        let ring = BufRing::new(4, 16, 0).unwrap().register_simulated();
+        // SAFETY: nothing else writes the tail
+        let mut ring = unsafe { ring.into_single_producer() };
+        let mut kernel = SimKernel::attach(&ring);
+
+        for round in 0..70_000u32 {
+            let (flags, res) = kernel.recv(&ring, &round.to_le_bytes(), true);
+            let cqe = FakeCqe { flags, res };
+            let id = ring.buffer_id_from_fields(&cqe).unwrap().unwrap();
+            assert_eq!(id.buffer(), round.to_le_bytes());
+        }
+        assert_eq!(kernel.available(&ring), 4);
+        assert_eq!(ring.published_tail(), (70_000u32 + 4) as u16);
+
+        let mut ring = ring.into_inner();
+        drop(ring.concurrent_recycler());
+        drop(ring.unregister_simulated());
+    }
+}
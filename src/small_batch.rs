@@ -0,0 +1,101 @@
+use crate::buf_ring::{BufRing, state};
+use crate::buffer_id::BufferId;
+use io_uring::cqueue::EntryMarker;
+
+use core::marker::PhantomData;
+
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    buf_id: u16,
+    offset: u32,
+    len: u32,
+    held: bool,
+}
+
+/// Up to `N` resolved completions, recycled together with a single publication.
+///
+/// Everything lives on the stack, so collecting a handful of completions per
+/// poll doesn't allocate. Pushing the `N + 1`th completion flushes the batch
+/// first, and so does dropping it.
+pub struct SmallBatch<'a, const N: usize> {
+    ring: &'a mut BufRing<state::Init>,
+    slots: [Slot; N],
+    len: usize,
+    /// Borrows the ring, which stays on its thread.
+    not_send: PhantomData<*const ()>,
+}
+
+impl<'a, const N: usize> SmallBatch<'a, N> {
+    pub fn new(ring: &'a mut BufRing<state::Init>) -> Self {
+        const { assert!(N > 0, "SmallBatch capacity `N` must not be 0") };
+        Self {
+            ring,
+            slots: [Slot::default(); N],
+            len: 0,
+            not_send: PhantomData,
+        }
+    }
+
+    /// Resolves `cqe` into the batch, returns whether it carried a buffer.
+    pub fn push<E: EntryMarker>(&mut self, cqe: &E) -> std::io::Result<bool> {
+        if self.len == N {
+            self.flush();
+        }
+        let Some(id) = BufferId::new(self.ring, cqe)? else {
+            return Ok(false);
+        };
+        let (buf_id, offset, len, held) = id.into_parts();
+        self.slots[self.len] = Slot {
+            buf_id,
+            offset,
+            len,
+            held,
+        };
+        self.len += 1;
+        Ok(true)
+    }
+
+    /// The received data of the `i`th completion in the batch.
+    pub fn get(&self, i: usize) -> Option<&[u8]> {
+        let slot = self.slots[..self.len].get(i)?;
+        let start = slot.offset as usize;
+        // SAFETY: the id came from the kernel and the data lies within its buffer
+        Some(unsafe { &self.ring.buffer(slot.buf_id)[start..start + slot.len as usize] })
+    }
+
+    /// The buffer id of the `i`th completion in the batch.
+    pub fn buf_id(&self, i: usize) -> Option<u16> {
+        self.slots[..self.len].get(i).map(|slot| slot.buf_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Recycles every buffer in the batch and advances once, parking those
+    /// past the provision limit.
+    pub fn flush(&mut self) {
+        let mut ids = [0u16; N];
+        let mut count = 0;
+        for slot in &self.slots[..self.len] {
+            if slot.held {
+                ids[count] = slot.buf_id;
+                count += 1;
+            }
+        }
+        self.len = 0;
+        // SAFETY: every id was handed to the application by a completion and
+        // is recycled only once
+        unsafe { self.ring.recycle_batch(&ids[..count]) };
+    }
+}
+
+impl<const N: usize> Drop for SmallBatch<'_, N> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
@@ -0,0 +1,200 @@
+use crate::buf_ring::{BidState, BufRing, RawCompletion, state};
+use io_uring::cqueue::{Entry, EntryMarker};
+
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+/// The half of a [split](BufRing::split_borrow) ring that resolves
+/// completions and reads their buffers.
+///
+/// Completions resolve to [`RawCompletion`]s, whose buffers stay held by the
+/// application until they are returned through the [`Recycler`].
+pub struct CompletionResolver<'a> {
+    ring: NonNull<BufRing<state::Init>>,
+    marker: PhantomData<&'a mut BufRing<state::Init>>,
+}
+
+/// The half of a [split](BufRing::split_borrow) ring that returns buffers to
+/// the kernel.
+pub struct Recycler<'a> {
+    ring: NonNull<BufRing<state::Init>>,
+    marker: PhantomData<&'a mut BufRing<state::Init>>,
+}
+
+// Both halves point at the same ring. Neither is `Send` or `Sync`, so they're
+// only ever used from one thread, and no method keeps a reference to the ring
+// past its return, so the two never borrow it at the same time.
+
+impl BufRing<state::Init> {
+    /// Splits the ring into a half resolving completions and a half recycling
+    /// buffers, which can live in different structs of the same thread.
+    pub fn split_borrow(&mut self) -> (CompletionResolver<'_>, Recycler<'_>) {
+        let ring = NonNull::from(self);
+        (
+            CompletionResolver {
+                ring,
+                marker: PhantomData,
+            },
+            Recycler {
+                ring,
+                marker: PhantomData,
+            },
+        )
+    }
+}
+
+impl CompletionResolver<'_> {
+    fn ring(&self) -> &BufRing<state::Init> {
+        // SAFETY: see above
+        unsafe { self.ring.as_ref() }
+    }
+
+    /// Resolves `cqe`, see [`BufRing::resolve_raw`].
+    #[cfg_attr(feature = "watchdog", track_caller)]
+    pub fn resolve<E: EntryMarker>(&mut self, cqe: &E) -> std::io::Result<Option<RawCompletion>> {
+        let e: Entry = cqe.clone().into();
+        // SAFETY: see above
+        unsafe { self.ring.as_mut() }.resolve_raw(e.flags(), e.result())
+    }
+
+    /// The data received by `completion`.
+    ///
+    /// # Panics
+    ///
+    /// If `completion` doesn't lie within one of the ring's buffers.
+    pub fn data(&self, completion: &RawCompletion) -> &[u8] {
+        let ring = self.ring();
+        assert!(completion.bid < ring.entries(), "buffer id out of range");
+        let start = completion.offset as usize;
+        // SAFETY: checked above
+        unsafe { &ring.buffer(completion.bid)[start..start + completion.len as usize] }
+    }
+
+    pub fn bgid(&self) -> u16 {
+        self.ring().bgid()
+    }
+
+    pub fn buf_size(&self) -> u32 {
+        self.ring().buf_size()
+    }
+}
+
+impl Recycler<'_> {
+    fn ring(&mut self) -> &mut BufRing<state::Init> {
+        // SAFETY: see above
+        unsafe { self.ring.as_mut() }
+    }
+
+    /// Returns `buf_id` to the kernel, like dropping its [`BufferId`](crate::BufferId).
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `buf_id` < `entries()`, that it is held by
+    /// the application, that it is returned only once, and that nothing read
+    /// through [`CompletionResolver::data`] is used afterwards.
+    pub unsafe fn recycle(&mut self, buf_id: u16) {
+        let ring = self.ring();
+        debug_assert_eq!(ring.bid_state(buf_id), BidState::HeldByApp);
+        unsafe { ring.recycle(buf_id) }
+    }
+
+    /// See [`BufRing::flush`].
+    pub fn flush(&mut self) {
+        self.ring().flush();
+    }
+
+    /// See [`BufRing::has_at_least`].
+    pub fn has_at_least(&mut self, n: u16) -> bool {
+        self.ring().has_at_least(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::net::UnixDatagram;
+
+    struct Reader<'a> {
+        resolver: CompletionResolver<'a>,
+        received: Vec<Vec<u8>>,
+    }
+
+    impl Reader<'_> {
+        /// Copies out the data and hands back the buffer to return.
+        fn read(&mut self, cqe: &Entry) -> u16 {
+            let completion = self.resolver.resolve(cqe).unwrap().unwrap();
+            self.received.push(self.resolver.data(&completion).to_vec());
+            completion.bid
+        }
+    }
+
+    struct Returner<'a> {
+        recycler: Recycler<'a>,
+        queued: Vec<u16>,
+    }
+
+    impl Returner<'_> {
+        fn return_all(&mut self) {
+            for buf_id in self.queued.drain(..) {
+                // SAFETY: resolved once by the reader, whose copy is all that's kept
+                unsafe { self.recycler.recycle(buf_id) };
+            }
+            self.recycler.flush();
+        }
+    }
+
+    /// The halves live in separate structs: the reader resolves rounds of
+    /// completions, the returner gives their buffers back afterwards, and
+    /// every message arrives once, in order, without running the ring dry.
+    #[test]
+    #[cfg_attr(miri, ignore = "needs io_uring")]
+    fn resolve_then_recycle_from_separate_structs() {
+        let mut uring = io_uring::IoUring::new(8).unwrap();
+        let ring = BufRing::new(4, 32, 13).unwrap();
+        let mut ring = ring
+            .register_and_init(&uring.submitter())
+            .map_err(|(e, _)| e)
+            .unwrap();
+        let (tx, rx) = UnixDatagram::pair().unwrap();
+
+        let (resolver, recycler) = ring.split_borrow();
+        let mut reader = Reader {
+            resolver,
+            received: Vec::new(),
+        };
+        let mut returner = Returner {
+            recycler,
+            queued: Vec::new(),
+        };
+        for round in 0..10u8 {
+            for i in 0..4 {
+                tx.send(&[round, i]).unwrap();
+                let sqe = io_uring::opcode::Recv::new(
+                    io_uring::types::Fd(rx.as_raw_fd()),
+                    core::ptr::null_mut(),
+                    0,
+                )
+                .buf_group(reader.resolver.bgid())
+                .build()
+                .flags(io_uring::squeue::Flags::BUFFER_SELECT);
+                unsafe { uring.submission().push(&sqe).unwrap() };
+            }
+            uring.submit_and_wait(4).unwrap();
+            for cqe in uring.completion() {
+                returner.queued.push(reader.read(&cqe));
+            }
+            assert!(!returner.recycler.has_at_least(1));
+            returner.return_all();
+            assert!(returner.recycler.has_at_least(4));
+        }
+
+        let expected: Vec<_> = (0..10u8)
+            .flat_map(|round| (0..4).map(move |i| vec![round, i]))
+            .collect();
+        assert_eq!(reader.received, expected);
+        assert_eq!(ring.provided(), 4);
+        let ring = ring.unregister(&uring.submitter());
+        drop(ring.map_err(|(e, _)| e).unwrap());
+    }
+}
@@ -0,0 +1,52 @@
+use crate::buf_ring::{BufRing, state};
+use io_uring::{opcode, squeue, types};
+use std::os::fd::RawFd;
+
+impl BufRing<state::Init> {
+    /// Re-provides every buffer the application doesn't hold and returns a
+    /// multishot recv to re-arm `fd` with.
+    ///
+    /// Deferred recycles are published and parked buffers are provided, up to
+    /// the provision limit if one is set. Returns `None` if still no buffer is
+    /// provided, since resubmitting would only fail again; the caller has to
+    /// drop some buffers first.
+    pub fn replenish_and_resubmit(&mut self, fd: RawFd, user_data: u64) -> Option<squeue::Entry> {
+        self.flush();
+        self.provide_parked_to_limit();
+        (self.provided() > 0).then(|| self.recv_multi_sqe(fd, user_data))
+    }
+
+    /// A recv on `fd` that selects a buffer from this ring.
+    ///
+    /// `len` caps how much is received, `0` uses the whole buffer.
+    pub fn recv_sqe(&self, fd: RawFd, len: u32, user_data: u64) -> squeue::Entry {
+        opcode::Recv::new(types::Fd(fd), core::ptr::null_mut(), len)
+            .buf_group(self.bgid())
+            .build()
+            .flags(squeue::Flags::BUFFER_SELECT)
+            .user_data(user_data)
+    }
+
+    /// Same as [`BufRing::recv_sqe`] against the registered file at `file_index`.
+    pub fn recv_sqe_fixed(&self, file_index: u32, len: u32, user_data: u64) -> squeue::Entry {
+        opcode::Recv::new(types::Fixed(file_index), core::ptr::null_mut(), len)
+            .buf_group(self.bgid())
+            .build()
+            .flags(squeue::Flags::BUFFER_SELECT)
+            .user_data(user_data)
+    }
+
+    /// A multishot recv on `fd` that selects buffers from this ring.
+    pub fn recv_multi_sqe(&self, fd: RawFd, user_data: u64) -> squeue::Entry {
+        opcode::RecvMulti::new(types::Fd(fd), self.bgid())
+            .build()
+            .user_data(user_data)
+    }
+
+    /// Same as [`BufRing::recv_multi_sqe`] against the registered file at `file_index`.
+    pub fn recv_multi_sqe_fixed(&self, file_index: u32, user_data: u64) -> squeue::Entry {
+        opcode::RecvMulti::new(types::Fixed(file_index), self.bgid())
+            .build()
+            .user_data(user_data)
+    }
+}
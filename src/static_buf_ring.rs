@@ -0,0 +1,279 @@
+use crate::buf_ring::{MapOpts, map_ring, state, tail_atomic, unmap_ring};
+use crate::cqe::{cqe_buffer_id, cqe_has_buffer};
+use crate::sync::Ordering;
+use io_uring::cqueue::{Entry, EntryMarker};
+use io_uring::types::BufRingEntry;
+
+use core::marker::PhantomData;
+
+/// A [`BufRing`](crate::BufRing) whose entry count is fixed at compile time.
+///
+/// `N` must be a power of two no larger than 32768, which is checked when the
+/// ring is constructed, at compile time. Unlike [`BufRing::new`](crate::BufRing::new)
+/// nothing is rounded up, and the mask is the constant `N - 1`.
+///
+/// Like a `BufRing`, a ring dropped while registered is leaked rather than
+/// unmapped, and debug builds panic.
+pub struct StaticBufRing<const N: usize, State> {
+    base: *mut BufRingEntry,
+    buffer_base: *const u8,
+    buf_size: u32,
+    bgid: u16,
+    tail: u16,
+    registered: bool,
+    state: PhantomData<State>,
+}
+
+// SAFETY: same as `BufRing`
+unsafe impl<const N: usize, S> Send for StaticBufRing<N, S> {}
+
+impl<const N: usize, S> StaticBufRing<N, S> {
+    const MASK: u32 = (N - 1) as u32;
+}
+
+impl<const N: usize> StaticBufRing<N, state::Uninit> {
+    pub fn new(buf_size: u32, bgid: u16) -> std::io::Result<Self> {
+        Self::new_with_opts(buf_size, bgid, Default::default())
+    }
+
+    pub fn new_with_opts(buf_size: u32, bgid: u16, opts: MapOpts) -> std::io::Result<Self> {
+        const {
+            assert!(
+                N.is_power_of_two(),
+                "StaticBufRing entries `N` must be a power of two"
+            );
+            assert!(N <= 1 << 15, "StaticBufRing entries `N` must be at most 32768");
+        }
+        let (base, buffer_base) = map_ring(N as u16, buf_size, opts)?;
+
+        Ok(Self {
+            base,
+            buffer_base,
+            buf_size,
+            bgid,
+            tail: 0,
+            registered: false,
+            state: PhantomData,
+        })
+    }
+
+    pub fn set_bgid(&mut self, bgid: u16) {
+        self.bgid = bgid;
+    }
+
+    pub fn register(
+        mut self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<StaticBufRing<N, state::Registered>, (std::io::Error, Self)> {
+        if let Err(e) = unsafe {
+            submitter.register_buf_ring_with_flags(self.ring_addr(), N as u16, self.bgid(), 0)
+        } {
+            return Err((e, self));
+        }
+        self.registered = true;
+        // SAFETY: same type layout
+        Ok(unsafe { core::mem::transmute::<Self, StaticBufRing<N, state::Registered>>(self) })
+    }
+}
+
+impl<const N: usize> StaticBufRing<N, state::Registered> {
+    pub fn unregister(
+        self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<StaticBufRing<N, state::Uninit>, (std::io::Error, Self)> {
+        unsafe { self.unregister_(submitter) }
+    }
+
+    pub fn init(mut self) -> StaticBufRing<N, state::Init> {
+        for i in 0..N as u16 {
+            unsafe { self.write_entry(i as u32, i) };
+        }
+        unsafe { self.advance_(N as u16) }
+
+        // SAFETY: same type layout
+        unsafe { core::mem::transmute(self) }
+    }
+}
+
+impl<const N: usize> StaticBufRing<N, state::Init> {
+    pub fn unregister(
+        self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<StaticBufRing<N, state::Uninit>, (std::io::Error, Self)> {
+        unsafe { self.unregister_(submitter) }
+    }
+
+    /// Resolves the buffer `cqe` selected, recycled when the id is dropped.
+    pub fn buffer_id_from_cqe<'a, 'b, E: EntryMarker>(
+        &'a mut self,
+        cqe: &'b E,
+    ) -> std::io::Result<Option<StaticBufferId<'a, 'b, N, E>>> {
+        let e: Entry = cqe.clone().into();
+        if e.result() < 0 {
+            return Err(std::io::Error::from_raw_os_error(-e.result()));
+        }
+        if !cqe_has_buffer(e.flags()) {
+            return Ok(None);
+        }
+        let buf_id = cqe_buffer_id(e.flags());
+        debug_assert!((buf_id as usize) < N, "buffer id out of range");
+        Ok(Some(StaticBufferId {
+            ring: self,
+            buf_id,
+            len: e.result() as u32,
+            marker: PhantomData,
+        }))
+    }
+
+    /// # Safety
+    ///
+    /// The caller must ensure that `buf_id` is < `N`
+    pub unsafe fn buffer(&self, buf_id: u16) -> &[u8] {
+        unsafe {
+            let buf = self.get_buffer(buf_id);
+            core::slice::from_raw_parts(buf, self.buf_size as usize)
+        }
+    }
+
+    /// Writes the entry for `buf_id` into `slot & (N - 1)` without advancing the tail.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `buf_id` is < `N` and that
+    /// `slot` is not currently published to the kernel.
+    pub unsafe fn provide_buffer_at(&mut self, slot: u16, buf_id: u16) {
+        unsafe { self.write_entry(slot as u32, buf_id) }
+    }
+
+    /// # Safety
+    ///
+    /// The caller must ensure that an entry has been written into the buf ring.
+    pub unsafe fn advance(&mut self, count: u16) {
+        unsafe { self.advance_(count) }
+    }
+}
+
+impl<const N: usize, S> StaticBufRing<N, S> {
+    /// # Safety
+    ///
+    /// The caller must ensure that `buf_id` < `N`
+    #[inline]
+    unsafe fn write_entry(&mut self, slot: u32, buf_id: u16) {
+        let (entry, buffer_addr) = unsafe {
+            (
+                &mut *self.base.offset((slot & Self::MASK) as isize),
+                self.get_buffer(buf_id),
+            )
+        };
+        entry.set_addr(buffer_addr as u64);
+        entry.set_len(self.buf_size);
+        entry.set_bid(buf_id);
+    }
+
+    /// # Safety
+    ///
+    /// The caller must ensure `buf_id` < `N`
+    #[inline]
+    unsafe fn get_buffer(&self, buf_id: u16) -> *const u8 {
+        debug_assert!((buf_id as usize) < N, "buffer id out of range");
+        // in `usize`, the product can exceed `u32::MAX` for large pools
+        unsafe { self.buffer_base.add(buf_id as usize * self.buf_size as usize) }
+    }
+
+    /// Publishes with `Release` ordering, same as `BufRing`.
+    ///
+    /// # Safety
+    ///
+    /// This function should not be called before the buf ring is registered
+    #[inline]
+    unsafe fn advance_(&mut self, count: u16) {
+        self.tail = self.tail.wrapping_add(count);
+        unsafe {
+            let _ = (*tail_atomic(self.base)).fetch_add(count, Ordering::Release);
+        }
+    }
+
+    pub const fn entries(&self) -> u16 {
+        N as u16
+    }
+
+    pub fn buf_size(&self) -> u32 {
+        self.buf_size
+    }
+
+    pub fn ring_addr(&self) -> u64 {
+        self.base as u64
+    }
+
+    pub fn bgid(&self) -> u16 {
+        self.bgid
+    }
+
+    /// # Safety
+    ///
+    /// The caller must ensure that the buf ring is registered
+    unsafe fn unregister_(
+        mut self,
+        submitter: &io_uring::Submitter<'_>,
+    ) -> Result<StaticBufRing<N, state::Uninit>, (std::io::Error, Self)> {
+        if let Err(e) = submitter.unregister_buf_ring(self.bgid()) {
+            return Err((e, self));
+        }
+        // the kernel starts its head at 0 on the next registration
+        self.tail = 0;
+        unsafe { (*tail_atomic(self.base)).store(0, Ordering::Release) };
+        self.registered = false;
+
+        // SAFETY: same type layout
+        Ok(unsafe { core::mem::transmute::<Self, StaticBufRing<N, state::Uninit>>(self) })
+    }
+}
+
+impl<const N: usize, S> Drop for StaticBufRing<N, S> {
+    fn drop(&mut self) {
+        if self.registered {
+            debug_assert!(
+                std::thread::panicking(),
+                "StaticBufRing with bgid {} dropped while registered",
+                self.bgid
+            );
+            // the kernel may still write into the mapping, leak it instead
+            return;
+        }
+        // includes any padding before the buffers
+        let size = self.buffer_base as usize - self.base as usize + N * self.buf_size as usize;
+        unsafe { unmap_ring(self.base, size) }
+    }
+}
+
+/// A buffer of a [`StaticBufRing`] selected by a completion, recycled on drop.
+pub struct StaticBufferId<'a, 'b, const N: usize, E: EntryMarker> {
+    ring: &'a mut StaticBufRing<N, state::Init>,
+    buf_id: u16,
+    len: u32,
+    marker: PhantomData<&'b E>,
+}
+
+impl<const N: usize, E: EntryMarker> StaticBufferId<'_, '_, N, E> {
+    pub fn buf_id(&self) -> u16 {
+        self.buf_id
+    }
+
+    /// The received data.
+    pub fn buffer(&self) -> &[u8] {
+        // SAFETY: the id came from the kernel and `len` <= `buf_size`
+        unsafe { &self.ring.buffer(self.buf_id)[..self.len as usize] }
+    }
+}
+
+impl<const N: usize, E: EntryMarker> Drop for StaticBufferId<'_, '_, N, E> {
+    fn drop(&mut self) {
+        let ring = &mut *self.ring;
+        // SAFETY: the kernel consumed the buffer's slot before completing it,
+        // so the slot at the tail is free, and the ring is registered
+        unsafe {
+            ring.write_entry(ring.tail as u32, self.buf_id);
+            ring.advance_(1);
+        }
+    }
+}
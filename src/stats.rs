@@ -0,0 +1,135 @@
+/// Buckets in [`Stats::size_histogram`].
+///
+/// Bucket `i` counts completions of `16 << (i - 1)` exclusive to `16 << i`
+/// inclusive bytes, bucket 0 everything up to 16 bytes. The last bucket counts
+/// completions that filled the whole buffer instead, which usually means the
+/// data was truncated and `buf_size` is too small.
+pub const SIZE_BUCKETS: usize = 30;
+
+/// Totals maintained by a ring when the `stats` feature is enabled.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Stats {
+    /// Completions that carried a buffer.
+    pub completions: u64,
+    /// Bytes received across those completions.
+    pub bytes: u64,
+    /// Buffers returned to the kernel.
+    pub recycled: u64,
+    /// Completions that failed with `ENOBUFS`.
+    pub enobufs: u64,
+    /// Highest number of buffers held by the application at once.
+    pub max_held: u16,
+    /// Tail publications.
+    pub batches: u64,
+    /// Completion sizes, see [`SIZE_BUCKETS`].
+    pub size_histogram: [u64; SIZE_BUCKETS],
+    /// Smallest completion, 0 before the first one.
+    pub min_len: u32,
+    /// Largest completion.
+    pub max_len: u32,
+}
+
+impl Stats {
+    /// Mean completion size, 0 before the first one.
+    pub fn mean_len(&self) -> u64 {
+        self.bytes.checked_div(self.completions).unwrap_or(0)
+    }
+
+    /// Records a completion of `len` bytes into a buffer of `buf_size`.
+    #[inline]
+    pub(crate) fn record_completion(&mut self, len: u32, buf_size: u32) {
+        let bucket = if len == buf_size {
+            SIZE_BUCKETS - 1
+        } else {
+            (len.max(16) - 1).ilog2() as usize - 3
+        };
+        self.size_histogram[bucket] += 1;
+
+        if self.completions == 0 || len < self.min_len {
+            self.min_len = len;
+        }
+        self.max_len = self.max_len.max(len);
+        self.completions += 1;
+        self.bytes += len as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buf_ring::BufRing;
+    use crate::cqe::FakeCqe;
+    use crate::sim::SimKernel;
+
+    /// Every counter after a scripted workload on a simulated ring.
+    #[test]
+    fn counts_a_scripted_workload_exactly() {
+        let mut ring = BufRing::new(8, 64, 0).unwrap().register_simulated();
+        let mut kernel = SimKernel::attach(&ring);
+        // `init` published every buffer at once
+        assert_eq!(ring.stats().batches, 1);
+        ring.reset_stats();
+
+        let mut held = Vec::new();
+        for len in [10, 64, 100] {
+            let (flags, res) = kernel.recv(&ring, &vec![0; len], true);
+            let cqe = FakeCqe { flags, res };
+            // SAFETY: every buffer is returned before the ring is released
+            held.push(unsafe { ring.buffer_id_from_fields(&cqe).unwrap().unwrap().detach() });
+        }
+        ring.recycle_many(held.drain(..)).unwrap();
+
+        let (flags, res) = kernel.recv(&ring, &[0; 20], true);
+        drop(ring.buffer_id_from_fields(&FakeCqe { flags, res }).unwrap());
+
+        let cqe = FakeCqe::error(libc::ENOBUFS);
+        assert!(ring.buffer_id_from_fields(&cqe).is_err());
+
+        let mut histogram = [0; SIZE_BUCKETS];
+        // 10 bytes, 20 bytes, and two full buffers
+        histogram[0] = 1;
+        histogram[1] = 1;
+        histogram[SIZE_BUCKETS - 1] = 2;
+        let expected = Stats {
+            completions: 4,
+            bytes: 10 + 64 + 64 + 20,
+            recycled: 4,
+            enobufs: 1,
+            max_held: 3,
+            batches: 2,
+            size_histogram: histogram,
+            min_len: 10,
+            max_len: 64,
+        };
+        assert_eq!(ring.stats(), expected);
+        assert_eq!(ring.stats().mean_len(), 158 / 4);
+
+        ring.reset_stats();
+        assert_eq!(ring.stats(), Stats::default());
+        drop(ring.unregister_simulated());
+    }
+
+    /// Sizes on both sides of each bucket bound, and a completion filling
+    /// the buffer exactly, which counts as possibly truncated instead.
+    #[test]
+    fn histogram_buckets_known_sizes() {
+        let mut ring = BufRing::new(4, 4096, 0).unwrap().register_simulated();
+        let mut kernel = SimKernel::attach(&ring);
+        for len in [1, 16, 17, 32, 33, 2048, 2049, 4095, 4096] {
+            let (flags, res) = kernel.recv(&ring, &vec![0; len], true);
+            drop(ring.buffer_id_from_fields(&FakeCqe { flags, res }).unwrap());
+        }
+
+        let mut expected = [0; SIZE_BUCKETS];
+        // up to 16, 17..=32, 33..=64, 1025..=2048, 2049..=4096 and full
+        expected[0] = 2;
+        expected[1] = 2;
+        expected[2] = 1;
+        expected[7] = 1;
+        expected[8] = 2;
+        expected[SIZE_BUCKETS - 1] = 1;
+        assert_eq!(ring.size_histogram(), expected);
+        assert_eq!((ring.stats().min_len, ring.stats().max_len), (1, 4096));
+        drop(ring.unregister_simulated());
+    }
+}
@@ -0,0 +1,268 @@
+//! Resolved completions as a [`Stream`].
+//!
+//! Completions come from a poll function rather than a specific runtime, e.g.
+//! one reading the completion queue and arming an eventfd reactor with the
+//! waker when it is empty.
+
+use crate::buf_ring::{BufRing, state};
+use crate::cqe::CqeFields;
+use crate::owned_buffer::OwnedBuffer;
+use futures_core::Stream;
+use io_uring::cqueue;
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Called with a completion that ended a multishot recv, returns whether the
+/// recv was submitted again.
+pub type RearmHook<'a, C = cqueue::Entry> =
+    Box<dyn FnMut(&mut BufRing<state::Init>, &C) -> bool + 'a>;
+
+/// A stream of the buffers selected by completions from a poll function.
+///
+/// `poll_cqe` returns the next completion if one is available, and otherwise
+/// registers the context's waker to be woken once one is, returning `Pending`.
+/// It returns `Ready(None)` to shut the stream down.
+///
+/// The stream also ends after a completion without `IORING_CQE_F_MORE`,
+/// i.e. once the multishot recv feeding it terminates, unless the
+/// [rearm hook](Self::on_terminated) resubmits it. Completion errors,
+/// including `ENOBUFS`, are yielded as items before that.
+///
+/// Buffers are detached from the ring, return them with
+/// [`recycle`](Self::recycle). Completions are `cqueue::Entry`s by default,
+/// anything carrying their fields works, e.g. a
+/// [`FakeCqe`](crate::cqe::FakeCqe) in tests.
+pub struct CompletionStream<'a, F, C = cqueue::Entry> {
+    ring: &'a mut BufRing<state::Init>,
+    poll_cqe: F,
+    rearm: Option<RearmHook<'a, C>>,
+    ended: bool,
+}
+
+impl<'a, F, C> CompletionStream<'a, F, C>
+where
+    F: FnMut(&mut Context<'_>) -> Poll<Option<C>>,
+    C: CqeFields,
+{
+    /// # Safety
+    ///
+    /// `ring` must outlive every buffer yielded, and must not be unregistered
+    /// while any of them hasn't been recycled, see [`BufferId::detach`].
+    ///
+    /// [`BufferId::detach`]: crate::BufferId::detach
+    pub unsafe fn new(ring: &'a mut BufRing<state::Init>, poll_cqe: F) -> Self {
+        Self {
+            ring,
+            poll_cqe,
+            rearm: None,
+            ended: false,
+        }
+    }
+
+    /// Called when the multishot recv terminates, the stream goes on if it
+    /// returns true.
+    pub fn on_terminated(&mut self, hook: RearmHook<'a, C>) {
+        self.rearm = Some(hook);
+    }
+
+    /// Returns yielded buffers to the kernel, see [`BufRing::recycle_many`].
+    ///
+    /// If one is rejected none are returned, and the error hands them all back.
+    pub fn recycle(
+        &mut self,
+        bufs: impl IntoIterator<Item = OwnedBuffer>,
+    ) -> Result<u16, (crate::owned_buffer::RecycleError, Vec<OwnedBuffer>)> {
+        self.ring.recycle_many(bufs)
+    }
+
+    pub fn ring(&self) -> &BufRing<state::Init> {
+        self.ring
+    }
+}
+
+impl<F, C> Stream for CompletionStream<'_, F, C>
+where
+    F: FnMut(&mut Context<'_>) -> Poll<Option<C>> + Unpin,
+    C: CqeFields,
+{
+    type Item = std::io::Result<OwnedBuffer>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if this.ended {
+                return Poll::Ready(None);
+            }
+            let Some(cqe) = core::task::ready!((this.poll_cqe)(cx)) else {
+                this.ended = true;
+                return Poll::Ready(None);
+            };
+
+            let item = match this.ring.buffer_id_from_fields(&cqe) {
+                // SAFETY: upheld by the caller of `new`
+                Ok(Some(id)) => Some(Ok(unsafe { id.detach() })),
+                Ok(None) => None,
+                Err(e) => Some(Err(e)),
+            };
+            if !cqueue::more(cqe.flags()) {
+                this.ended = !this
+                    .rearm
+                    .as_mut()
+                    .is_some_and(|rearm| rearm(&mut *this.ring, &cqe));
+            }
+            if let Some(item) = item {
+                return Poll::Ready(Some(item));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cqe::FakeCqe;
+    use crate::sim::SimKernel;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Wake, Waker};
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// A completion queue fed by hand, parking the waker while it is empty.
+    #[derive(Default)]
+    struct Queue {
+        cqes: VecDeque<FakeCqe>,
+        waker: Option<Waker>,
+        closed: bool,
+    }
+
+    impl Queue {
+        fn push(&mut self, (flags, res): (u32, i32)) {
+            self.cqes.push_back(FakeCqe { flags, res });
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+
+        fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Option<FakeCqe>> {
+            match self.cqes.pop_front() {
+                Some(cqe) => Poll::Ready(Some(cqe)),
+                None if self.closed => Poll::Ready(None),
+                None => {
+                    self.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    /// The stream waits for completions through the waker, yields `ENOBUFS`
+    /// once the consumer falls behind, and ends with the recv that ended.
+    #[test]
+    fn wakes_yields_backpressure_and_terminates() {
+        let mut ring = BufRing::new(2, 16, 0).unwrap().register_simulated();
+        let mut kernel = SimKernel::attach(&ring);
+        let queue = Rc::new(RefCell::new(Queue::default()));
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(wakes.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        let source = queue.clone();
+        // SAFETY: every buffer is recycled before the ring is released
+        let mut stream =
+            unsafe { CompletionStream::new(&mut ring, move |cx| source.borrow_mut().poll(cx)) };
+        let mut next =
+            |stream: &mut CompletionStream<'_, _, _>| Pin::new(stream).poll_next(&mut cx);
+
+        assert!(next(&mut stream).is_pending());
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 0);
+        queue
+            .borrow_mut()
+            .push(kernel.recv(stream.ring(), b"one", true));
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+        let Poll::Ready(Some(Ok(first))) = next(&mut stream) else {
+            panic!("no buffer after the wake-up");
+        };
+        assert_eq!(&first[..], b"one");
+
+        // nothing is recycled, so the second recv empties the ring
+        queue
+            .borrow_mut()
+            .push(kernel.recv(stream.ring(), b"two", true));
+        queue
+            .borrow_mut()
+            .push(kernel.recv(stream.ring(), b"three", true));
+        let Poll::Ready(Some(Ok(second))) = next(&mut stream) else {
+            panic!("second buffer missing");
+        };
+        let Poll::Ready(Some(Err(e))) = next(&mut stream) else {
+            panic!("the empty ring wasn't reported");
+        };
+        assert_eq!(e.raw_os_error(), Some(libc::ENOBUFS));
+        // the failed recv carried no `IORING_CQE_F_MORE`, so the stream is over
+        queue
+            .borrow_mut()
+            .push(kernel.recv(stream.ring(), b"late", true));
+        assert!(matches!(next(&mut stream), Poll::Ready(None)));
+        assert_eq!(queue.borrow().cqes.len(), 1);
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+
+        stream.recycle([first, second]).unwrap();
+        drop(stream);
+        assert_eq!(ring.provided(), 2);
+        drop(ring.unregister_simulated());
+    }
+
+    /// A rearm hook keeps the stream going after the recv ends, until the
+    /// poll function shuts it down.
+    #[test]
+    fn rearm_hook_continues_until_shut_down() {
+        let mut ring = BufRing::new(2, 16, 0).unwrap().register_simulated();
+        let mut kernel = SimKernel::attach(&ring);
+        let queue = Rc::new(RefCell::new(Queue::default()));
+        let waker = Waker::from(Arc::new(CountingWaker(AtomicUsize::new(0))));
+        let mut cx = Context::from_waker(&waker);
+
+        let source = queue.clone();
+        // SAFETY: every buffer is recycled before the ring is released
+        let mut stream =
+            unsafe { CompletionStream::new(&mut ring, move |cx| source.borrow_mut().poll(cx)) };
+        let rearms = Rc::new(RefCell::new(0));
+        let counted = rearms.clone();
+        stream.on_terminated(Box::new(move |_, cqe: &FakeCqe| {
+            assert!(!io_uring::cqueue::more(cqe.flags));
+            *counted.borrow_mut() += 1;
+            true
+        }));
+
+        for round in 0..3 {
+            queue
+                .borrow_mut()
+                .push(kernel.recv(stream.ring(), b"last", false));
+            let Poll::Ready(Some(Ok(buf))) = Pin::new(&mut stream).poll_next(&mut cx) else {
+                panic!("round {round} yielded nothing");
+            };
+            stream.recycle([buf]).unwrap();
+        }
+        assert_eq!(*rearms.borrow(), 3);
+        assert!(Pin::new(&mut stream).poll_next(&mut cx).is_pending());
+
+        queue.borrow_mut().closed = true;
+        assert!(matches!(
+            Pin::new(&mut stream).poll_next(&mut cx),
+            Poll::Ready(None)
+        ));
+        drop(stream);
+        drop(ring.unregister_simulated());
+    }
+}
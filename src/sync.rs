@@ -0,0 +1,17 @@
+//! Atomics of the publication protocol, loom's under `cfg(loom)`.
+//!
+//! The shared tail lives in the mapping read by the kernel, so a ring always
+//! publishes through it outside of loom. Under loom a loom atomic kept beside
+//! the mapping stands in for it, [`SharedTail`], so models see every tail
+//! store and can check them against a model kernel consumer, along with the
+//! counters and markers of the concurrent recycler.
+//!
+//! Models run with `RUSTFLAGS="--cfg loom" cargo test --release --lib loom_`.
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering, fence};
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering, fence};
+
+/// The atomic a ring publishes its tail through, see the module docs.
+pub(crate) type SharedTail = AtomicU16;
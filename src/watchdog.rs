@@ -0,0 +1,92 @@
+use core::panic::Location;
+use std::time::{Duration, Instant};
+
+/// A buffer held by the application for longer than expected.
+#[derive(Clone, Copy, Debug)]
+pub struct StaleReport {
+    pub buf_id: u16,
+    pub age: Duration,
+    /// Where the completion handing out the buffer was resolved.
+    pub location: &'static Location<'static>,
+}
+
+/// When and where each held buffer was handed out.
+pub(crate) struct Watchdog {
+    held: Box<[Option<(Instant, &'static Location<'static>)>]>,
+}
+
+impl Watchdog {
+    pub(crate) fn new(entries: u16) -> Self {
+        Self {
+            held: vec![None; entries as usize].into_boxed_slice(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn acquire(&mut self, buf_id: u16, location: &'static Location<'static>) {
+        self.held[buf_id as usize] = Some((Instant::now(), location));
+    }
+
+    #[inline]
+    pub(crate) fn release(&mut self, buf_id: u16) {
+        self.held[buf_id as usize] = None;
+    }
+
+    pub(crate) fn stale(&self, max_age: Duration) -> Vec<StaleReport> {
+        let now = Instant::now();
+        self.held
+            .iter()
+            .enumerate()
+            .filter_map(|(buf_id, held)| {
+                let (since, location) = (*held)?;
+                let age = now.duration_since(since);
+                (age > max_age).then_some(StaleReport {
+                    buf_id: buf_id as u16,
+                    age,
+                    location,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buf_ring::BufRing;
+    use crate::cqe::FakeCqe;
+    use crate::sim::SimKernel;
+    use std::time::Duration;
+
+    /// A buffer held across a sleep is reported with where it was resolved,
+    /// one resolved afterwards isn't, and returning it clears the report.
+    #[test]
+    fn reports_a_buffer_held_too_long() {
+        let mut ring = BufRing::new(4, 16, 0).unwrap().register_simulated();
+        let mut kernel = SimKernel::attach(&ring);
+        let mut take = |ring: &mut BufRing<_>| {
+            let (flags, res) = kernel.recv(ring, b"x", true);
+            let cqe = FakeCqe { flags, res };
+            let line = line!() + 2;
+            // SAFETY: every buffer is returned before the ring is released
+            let id = ring.buffer_id_from_fields(&cqe).unwrap().unwrap();
+            (unsafe { id.detach() }, line)
+        };
+
+        let (old, line) = take(&mut ring);
+        std::thread::sleep(Duration::from_millis(50));
+        let (fresh, _) = take(&mut ring);
+
+        let stale = ring.check_stale(Duration::from_millis(25));
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].buf_id, old.buf_id());
+        assert!(stale[0].age >= Duration::from_millis(50));
+        assert_eq!(stale[0].location.file(), file!());
+        assert_eq!(stale[0].location.line(), line);
+
+        ring.recycle_many([old]).unwrap();
+        assert!(ring.check_stale(Duration::from_millis(25)).is_empty());
+        ring.recycle_many([fresh]).unwrap();
+        assert!(ring.check_stale(Duration::ZERO).is_empty());
+        drop(ring.unregister_simulated());
+    }
+}
@@ -0,0 +1,95 @@
+/// Fires a callback when the number of provided buffers drops below `low`.
+///
+/// Once fired it stays quiet until the count climbs back to `rearm`, so a ring
+/// hovering around the threshold doesn't call back on every completion.
+pub(crate) struct LowWatermark {
+    low: u16,
+    rearm: u16,
+    below: bool,
+    callback: Box<dyn FnMut(u16) + Send>,
+}
+
+impl LowWatermark {
+    pub(crate) fn new(
+        low: u16,
+        entries: u16,
+        provided: u16,
+        callback: Box<dyn FnMut(u16) + Send>,
+    ) -> Self {
+        let hysteresis = (entries / 16).max(1);
+        Self {
+            low,
+            rearm: low.saturating_add(hysteresis).min(entries),
+            below: provided < low,
+            callback,
+        }
+    }
+
+    pub(crate) fn is_below(&self) -> bool {
+        self.below
+    }
+
+    #[inline]
+    pub(crate) fn update(&mut self, provided: u16) {
+        if self.below {
+            if provided >= self.rearm {
+                self.below = false;
+            }
+        } else if provided < self.low {
+            self.below = true;
+            (self.callback)(provided);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::buf_ring::BufRing;
+    use crate::cqe::FakeCqe;
+    use crate::sim::SimKernel;
+    use std::sync::{Arc, Mutex};
+
+    /// Drains a ring past the watermark, hovers around it, then recovers to
+    /// the re-arm point and drains again: the callback fires once per fall.
+    #[test]
+    fn fires_once_per_fall_below_the_watermark() {
+        // 32 entries re-arm 2 above the watermark
+        let mut ring = BufRing::new(32, 16, 0).unwrap().register_simulated();
+        let mut kernel = SimKernel::attach(&ring);
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let seen = fired.clone();
+        ring.set_low_watermark(8, move |provided| seen.lock().unwrap().push(provided));
+
+        let mut held = Vec::new();
+        let mut take = |ring: &mut BufRing<_>, held: &mut Vec<_>| {
+            let (flags, res) = kernel.recv(ring, b"x", true);
+            let cqe = FakeCqe { flags, res };
+            // SAFETY: every buffer is returned before the ring is released
+            held.push(unsafe { ring.buffer_id_from_fields(&cqe).unwrap().unwrap().detach() });
+        };
+        while ring.provided() >= 8 {
+            take(&mut ring, &mut held);
+        }
+        assert_eq!(*fired.lock().unwrap(), [7]);
+
+        // hovering between 7 and 9 stays quiet
+        for _ in 0..10 {
+            ring.recycle_many(held.drain(..2)).unwrap();
+            assert_eq!(ring.provided(), 9);
+            take(&mut ring, &mut held);
+            take(&mut ring, &mut held);
+        }
+        assert_eq!(fired.lock().unwrap().len(), 1);
+
+        // back up to 10 re-arms it, and the next fall fires again
+        ring.recycle_many(held.drain(..3)).unwrap();
+        assert_eq!(ring.provided(), 10);
+        while ring.provided() >= 8 {
+            take(&mut ring, &mut held);
+        }
+        assert_eq!(*fired.lock().unwrap(), [7, 7]);
+
+        ring.recycle_many(held).unwrap();
+        drop(ring.unregister_simulated());
+    }
+}
@@ -0,0 +1,8 @@
+//! Compile tests keeping the thread safety table in the crate docs honest.
+
+#[test]
+fn thread_safety() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/pass/*.rs");
+    t.compile_fail("tests/ui/fail/*.rs");
+}
@@ -0,0 +1,20 @@
+use io_uring_buf_ring::buf_ring_state::Init;
+use io_uring_buf_ring::bundle::Bundle;
+use io_uring_buf_ring::split::{CompletionResolver, Recycler};
+use io_uring_buf_ring::{
+    FreeBuffer, Multishot, OwnedBufRing, ProcessBudget, SentBuffer, SmallBatch,
+};
+
+fn send<T: Send>() {}
+
+fn main() {
+    send::<FreeBuffer<'static>>();
+    send::<Bundle<'static>>();
+    send::<SmallBatch<'static, 4>>();
+    send::<ProcessBudget<'static>>();
+    send::<SentBuffer<'static>>();
+    send::<Multishot<'static>>();
+    send::<CompletionResolver<'static>>();
+    send::<Recycler<'static>>();
+    send::<OwnedBufRing<'static, Init>>();
+}
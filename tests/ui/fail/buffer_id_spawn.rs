@@ -0,0 +1,9 @@
+use io_uring_buf_ring::buf_ring_state::Init;
+use io_uring_buf_ring::{BufRing, CqeFields};
+
+fn hand_off<C: CqeFields>(ring: &'static mut BufRing<Init>, cqe: &'static C) {
+    let id = ring.buffer_id_from_fields(cqe).unwrap().unwrap();
+    std::thread::spawn(move || drop(id));
+}
+
+fn main() {}
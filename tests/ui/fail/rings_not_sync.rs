@@ -0,0 +1,12 @@
+use io_uring_buf_ring::buf_ring_state::Init;
+use io_uring_buf_ring::{BufRing, ElasticPool, SendBufRing, SingleProducerRing, StaticBufRing};
+
+fn sync<T: Sync>() {}
+
+fn main() {
+    sync::<BufRing<Init>>();
+    sync::<StaticBufRing<4, Init>>();
+    sync::<SingleProducerRing>();
+    sync::<ElasticPool>();
+    sync::<SendBufRing>();
+}
@@ -0,0 +1,7 @@
+use io_uring_buf_ring::SingleProducerRing;
+
+fn recycle_from_threads(ring: &mut SingleProducerRing) {
+    let _ = ring.concurrent_recycler();
+}
+
+fn main() {}
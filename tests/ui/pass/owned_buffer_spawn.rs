@@ -0,0 +1,9 @@
+use io_uring_buf_ring::OwnedBuffer;
+
+fn hand_off(buf: OwnedBuffer) {
+    std::thread::spawn(move || drop(buf)).join().unwrap();
+}
+
+fn main() {
+    let _ = hand_off;
+}
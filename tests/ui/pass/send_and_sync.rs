@@ -0,0 +1,26 @@
+use io_uring_buf_ring::buf_ring_state::{Init, Uninit};
+use io_uring_buf_ring::{
+    BufRing, ConcurrentRecycler, ElasticPool, OwnedBuffer, SendBufRing, SharedBufRing,
+    SharedBuffer, SingleProducerRing, StaticBufRing,
+};
+
+fn send<T: Send>() {}
+fn sync<T: Sync>() {}
+
+fn main() {
+    send::<BufRing<Uninit>>();
+    send::<BufRing<Init>>();
+    send::<StaticBufRing<4, Init>>();
+    send::<SingleProducerRing>();
+    send::<ElasticPool>();
+    send::<SendBufRing>();
+
+    send::<ConcurrentRecycler<'static>>();
+    sync::<ConcurrentRecycler<'static>>();
+    send::<OwnedBuffer>();
+    sync::<OwnedBuffer>();
+    send::<SharedBufRing>();
+    sync::<SharedBufRing>();
+    send::<SharedBuffer>();
+    sync::<SharedBuffer>();
+}